@@ -0,0 +1,152 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - File checksums
+//
+// A SHA-256 digest is recorded for every file sent and received. The sender
+// hashes the files it hands to the engine and sends the digests in the
+// transfer's sidecar (see `sidecar`); the receiver hashes each file in
+// staging, checks it against the sidecar, and verifies it again once moved
+// to its final path. Recorded digests can be checked against the file on
+// disk later.
+
+use crate::types::{AppError, TransferFile};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Compute the SHA-256 digest of a file as a hex string
+pub fn sha256_file(path: &Path) -> Result<String, AppError> {
+    let mut file = fs::File::open(path)
+        .map_err(|e| AppError::FileIo(format!("Failed to open {:?}: {}", path, e)))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .map_err(|e| AppError::FileIo(format!("Failed to read {:?}: {}", path, e)))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Reader hashing the bytes read through it
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Digest of everything read so far, as a hex string
+    pub fn digest(self) -> String {
+        hex::encode(self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Check a file against its recorded digest
+///
+/// Returns the digest if it matches.
+pub fn verify(name: &str, path: &Path, expected: &str) -> Result<String, AppError> {
+    let actual = sha256_file(path)?;
+    if !actual.eq_ignore_ascii_case(expected) {
+        return Err(AppError::ChecksumMismatch {
+            name: name.to_string(),
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(actual)
+}
+
+/// Digests of the given files, by file name
+///
+/// Files that cannot be read are left out.
+pub fn digests(files: Vec<(String, PathBuf)>) -> HashMap<String, String> {
+    files
+        .into_iter()
+        .filter_map(|(id, path)| match sha256_file(&path) {
+            Ok(digest) => Some((id, digest)),
+            Err(e) => {
+                tracing::warn!("Cannot hash {:?}: {}", path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Compute digests off the async runtime
+pub async fn digests_blocking(files: Vec<(String, PathBuf)>) -> HashMap<String, String> {
+    tokio::task::spawn_blocking(move || digests(files))
+        .await
+        .unwrap_or_default()
+}
+
+/// Store digests on the files they belong to, by file name
+pub fn apply(files: &mut [TransferFile], digests: &HashMap<String, String>) {
+    for file in files {
+        if let Some(digest) = digests.get(&file.name) {
+            file.sha256 = Some(digest.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let dir = std::env::temp_dir().join(format!("gosh-checksum-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("abc.txt");
+        fs::write(&path, b"abc").unwrap();
+
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_file(&path).unwrap(), digest);
+        assert!(verify("abc.txt", &path, &digest.to_uppercase()).is_ok());
+
+        fs::write(&path, b"abd").unwrap();
+        assert!(matches!(
+            verify("abc.txt", &path, digest),
+            Err(AppError::ChecksumMismatch { .. })
+        ));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_digests_skip_unreadable() {
+        let dir = std::env::temp_dir().join(format!("gosh-checksum-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("abc.txt");
+        fs::write(&path, b"abc").unwrap();
+
+        let found = digests(vec![
+            ("f1".to_string(), path.clone()),
+            ("f2".to_string(), dir.join("missing.txt")),
+        ]);
+        assert_eq!(found.len(), 1);
+        assert!(found.contains_key("f1"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
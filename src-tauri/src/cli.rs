@@ -0,0 +1,368 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Command-line interface
+//
+// A few maintenance tasks can be run without opening the window:
+//
+//   gosh-transfer history export <file> [--format csv|jsonl] [--from <date>] [--to <date>]
+//   gosh-transfer history import <file> [--format csv|jsonl]
+//   gosh-transfer prepare-send <address> <path>... [--port <port>]
+//       [--exclude <pattern>]... [--include <pattern>]... [--ignore-files]
+//
+// Any other arguments start the app as usual. Dates are RFC 3339 timestamps
+// or `YYYY-MM-DD` (midnight UTC). The history commands refuse to run while
+// the app is open, as it holds the history log.
+//
+// `prepare-send` lists what a send would transfer and checks the peer,
+// without sending; it exits with 0 only if the send could start. A single
+// directory is listed as a directory send, which the patterns filter.
+
+use crate::history::HistoryStore;
+use crate::settings::SettingsStore;
+use crate::types::{AppError, DirectoryFilter, ExportFormat, PreparedSend};
+use crate::{build_engine_config, export, manifest, notifications, staging};
+use chrono::{DateTime, NaiveDate, Utc};
+use gosh_lan_transfer::GoshTransferEngine;
+use std::path::PathBuf;
+
+const USAGE: &str = "\
+Usage:
+  gosh-transfer history export <file> [--format csv|jsonl] [--from <date>] [--to <date>]
+  gosh-transfer history import <file> [--format csv|jsonl]
+  gosh-transfer prepare-send <address> <path>... [--port <port>]
+      [--exclude <pattern>]... [--include <pattern>]... [--ignore-files]";
+
+/// Subcommands, by their first argument
+const SUBCOMMANDS: &[&str] = &["history", "prepare-send"];
+
+/// A parsed subcommand
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    ExportHistory {
+        path: PathBuf,
+        format: Option<ExportFormat>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    },
+    ImportHistory {
+        path: PathBuf,
+        format: Option<ExportFormat>,
+    },
+    PrepareSend {
+        address: String,
+        /// Port of the peer; the configured port if unset
+        port: Option<u16>,
+        paths: Vec<PathBuf>,
+        filter: DirectoryFilter,
+    },
+}
+
+/// Whether `args` (without the program name) name a subcommand
+pub fn is_subcommand(args: &[String]) -> bool {
+    args.first()
+        .is_some_and(|arg| SUBCOMMANDS.contains(&arg.as_str()))
+}
+
+/// Run the subcommand given in `args` (without the program name)
+///
+/// Returns the exit code, or None if `args` is not a subcommand and the app
+/// should start.
+pub fn run(args: &[String]) -> Option<i32> {
+    if !is_subcommand(args) {
+        return None;
+    }
+
+    let command = match parse(args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return Some(1);
+        }
+    };
+    Some(match execute(&command) {
+        Ok((message, code)) => {
+            println!("{}", message);
+            code
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    })
+}
+
+/// Parse subcommand arguments
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    match args.first().map(String::as_str) {
+        Some("prepare-send") => parse_prepare_send(args),
+        _ => parse_history(args),
+    }
+}
+
+/// Parse `history ...` arguments
+fn parse_history(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter().skip(1);
+    let action = args.next().ok_or("Missing history action")?;
+    let path = PathBuf::from(args.next().ok_or("Missing file")?);
+
+    let mut format = None;
+    let mut from = None;
+    let mut to = None;
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", flag))?;
+        match flag.as_str() {
+            "--format" => {
+                format = Some(match value.as_str() {
+                    "csv" => ExportFormat::Csv,
+                    "jsonl" => ExportFormat::Jsonl,
+                    other => return Err(format!("Unknown format: {}", other)),
+                })
+            }
+            "--from" if action == "export" => from = Some(parse_date(value)?),
+            "--to" if action == "export" => to = Some(parse_date(value)?),
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    match action.as_str() {
+        "export" => Ok(Command::ExportHistory {
+            path,
+            format,
+            from,
+            to,
+        }),
+        "import" => Ok(Command::ImportHistory { path, format }),
+        other => Err(format!("Unknown history action: {}", other)),
+    }
+}
+
+/// Parse `prepare-send ...` arguments
+fn parse_prepare_send(args: &[String]) -> Result<Command, String> {
+    let mut args = args.iter().skip(1);
+    let address = args.next().ok_or("Missing address")?.clone();
+
+    let mut port = None;
+    let mut paths = Vec::new();
+    let mut filter = DirectoryFilter::default();
+    while let Some(arg) = args.next() {
+        if arg == "--ignore-files" {
+            filter.use_ignore_files = true;
+            continue;
+        }
+        if !arg.starts_with("--") {
+            paths.push(PathBuf::from(arg));
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing value for {}", arg))?;
+        match arg.as_str() {
+            "--port" => {
+                port = Some(
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid port: {}", value))?,
+                )
+            }
+            "--exclude" => filter.exclude.push(value.clone()),
+            "--include" => filter.include.push(value.clone()),
+            other => return Err(format!("Unknown option: {}", other)),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("Missing path".to_string());
+    }
+    Ok(Command::PrepareSend {
+        address,
+        port,
+        paths,
+        filter,
+    })
+}
+
+fn parse_date(value: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| format!("Invalid date: {}", value))
+}
+
+/// Run a command, returning its output and exit code
+fn execute(command: &Command) -> Result<(String, i32), String> {
+    match command {
+        Command::ExportHistory {
+            path,
+            format,
+            from,
+            to,
+        } => open_history()
+            .and_then(|history| export::export_to_file(&history, path, *format, *from, *to))
+            .map(|rows| (format!("Exported {} rows to {}", rows, path.display()), 0)),
+        Command::ImportHistory { path, format } => open_history()
+            .and_then(|history| export::import_from_file(&history, path, *format))
+            .map(|added| {
+                let message = format!("Imported {} transfers from {}", added, path.display());
+                (message, 0)
+            }),
+        Command::PrepareSend {
+            address,
+            port,
+            paths,
+            filter,
+        } => prepare_send(address, *port, paths, filter).map(|prepared| {
+            let code = if prepared.ready() { 0 } else { 1 };
+            (describe(&prepared), code)
+        }),
+    }
+    .map_err(|e| e.to_string())
+}
+
+fn prepare_send(
+    address: &str,
+    port: Option<u16>,
+    paths: &[PathBuf],
+    filter: &DirectoryFilter,
+) -> Result<PreparedSend, AppError> {
+    let settings = SettingsStore::new()?.get();
+    let port = port.unwrap_or(settings.port);
+    let directory = matches!(paths, [path] if path.is_dir());
+    if !directory && !filter.is_empty() {
+        return Err(AppError::InvalidConfig(
+            "Patterns apply to a single directory only".to_string(),
+        ));
+    }
+
+    let runtime = tokio::runtime::Runtime::new()
+        .map_err(|e| AppError::Network(format!("Failed to start runtime: {}", e)))?;
+    runtime.block_on(async {
+        // The server is not started, so nothing is received into staging
+        let staging_dir = staging::staging_root(&settings.download_dir);
+        let (engine, _events) = GoshTransferEngine::with_channel_events(build_engine_config(
+            &settings,
+            &staging_dir,
+            settings.port,
+        ));
+        let filter = Some(filter.clone()).filter(|f| !f.is_empty());
+        manifest::prepare(&engine, address, port, paths.to_vec(), directory, filter).await
+    })
+}
+
+/// Describe a prepared send for the terminal
+fn describe(prepared: &PreparedSend) -> String {
+    let manifest = &prepared.manifest;
+    let mut lines: Vec<String> = manifest
+        .files
+        .iter()
+        .map(|file| {
+            format!(
+                "  {}  {}  {}",
+                file.name,
+                notifications::format_size(file.size),
+                file.mime_type.as_deref().unwrap_or("-")
+            )
+        })
+        .collect();
+    lines.push(format!(
+        "{} files, {}",
+        manifest.file_count,
+        notifications::format_size(manifest.total_size)
+    ));
+    if manifest.excluded_count > 0 {
+        lines.push(format!("{} files excluded", manifest.excluded_count));
+    }
+    for link in &manifest.symlinks {
+        lines.push(format!(
+            "Symlink: {} -> {}",
+            link.name,
+            link.target.display()
+        ));
+    }
+    for path in &manifest.unreadable {
+        lines.push(format!(
+            "Unreadable: {}: {}",
+            path.path.display(),
+            path.error
+        ));
+    }
+
+    let peer = &prepared.peer;
+    lines.push(match (peer.reachable, &peer.info, &peer.error) {
+        (true, Some(info), _) => format!(
+            "Peer reachable: {}",
+            info.get("deviceName")
+                .and_then(|n| n.as_str())
+                .unwrap_or("unknown device")
+        ),
+        (true, None, _) => "Peer reachable".to_string(),
+        (false, _, Some(error)) => format!("Peer unreachable: {}", error),
+        (false, _, None) => "Peer unreachable".to_string(),
+    });
+    lines.push(
+        if prepared.ready() {
+            "Ready to send"
+        } else {
+            "Not ready to send"
+        }
+        .to_string(),
+    );
+    lines.join("\n")
+}
+
+/// Open the history with the configured retention
+fn open_history() -> Result<HistoryStore, AppError> {
+    let settings = SettingsStore::new()?.get();
+    HistoryStore::new(settings.history_retention)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(run(&args("--verbose")), None);
+        assert_eq!(
+            parse(&args("history export out.csv --from 2026-01-01")),
+            Ok(Command::ExportHistory {
+                path: PathBuf::from("out.csv"),
+                format: None,
+                from: Some(parse_date("2026-01-01T00:00:00Z").unwrap()),
+                to: None,
+            })
+        );
+        assert_eq!(
+            parse(&args("history import backup.txt --format jsonl")),
+            Ok(Command::ImportHistory {
+                path: PathBuf::from("backup.txt"),
+                format: Some(ExportFormat::Jsonl),
+            })
+        );
+        assert!(parse(&args("history import in.csv --from 2026-01-01")).is_err());
+        assert!(parse(&args("history export")).is_err());
+
+        assert_eq!(
+            parse(&args(
+                "prepare-send 10.0.0.2 project --port 9000 --exclude *.log --ignore-files"
+            )),
+            Ok(Command::PrepareSend {
+                address: "10.0.0.2".to_string(),
+                port: Some(9000),
+                paths: vec![PathBuf::from("project")],
+                filter: DirectoryFilter {
+                    include: Vec::new(),
+                    exclude: vec!["*.log".to_string()],
+                    use_ignore_files: true,
+                },
+            })
+        );
+        assert!(parse(&args("prepare-send 10.0.0.2 --port 9000")).is_err());
+    }
+}
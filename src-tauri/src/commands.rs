@@ -4,9 +4,29 @@
 // All UI interactions go through these commands.
 // The frontend communicates ONLY via Tauri commands/events.
 
-use crate::{favorites::FavoritesStore, history::HistoryStore, settings::SettingsStore, types::*};
-use gosh_lan_transfer::{EngineConfig, EngineEvent, GoshTransferEngine};
-use std::{path::PathBuf, sync::Arc};
+use crate::{
+    checksum,
+    events::{self, AppEvent, EventBus, EventStats, RecordedEvent},
+    export,
+    favorites::FavoritesStore,
+    history::HistoryStore,
+    manifest,
+    quota::QuotaLedger,
+    resume, routing, select, send,
+    settings::SettingsStore,
+    staging::{self, ReceiveQueue},
+    stats,
+    throttle::IncomingRelay,
+    tracker::TransferTracker,
+    types::*,
+    webhooks::WebhookStore,
+};
+use chrono::{DateTime, Utc};
+use gosh_lan_transfer::{EngineEvent, GoshTransferEngine};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tauri::{AppHandle, Emitter, State};
 use tokio::sync::{broadcast, Mutex, RwLock};
 
@@ -18,6 +38,65 @@ pub struct AppState {
     pub settings_store: SettingsStore,
     pub settings: RwLock<AppSettings>,
     pub history_store: HistoryStore,
+    pub webhooks: WebhookStore,
+    pub events: EventBus,
+    pub tracker: TransferTracker,
+    pub receive_queue: ReceiveQueue,
+    pub incoming_relay: IncomingRelay,
+    pub quota: QuotaLedger,
+}
+
+impl AppState {
+    /// Apply an event to the active-transfer snapshot, then publish it
+    pub fn publish(&self, event: AppEvent) {
+        self.tracker.apply(&event);
+        self.events.publish(event);
+    }
+
+    /// Mark an incoming transfer as accepted in history and record where it
+    /// is staged and where the routing rules will put its files
+    ///
+    /// Called before the engine accepts, so its first events find the plan
+    /// in place. Only transfers awaiting a decision are changed.
+    pub async fn record_accepted(&self, transfer_id: &str, staging_dir: &Path) {
+        let settings = self.settings.read().await;
+        let favorites = self.favorites.list();
+        self.tracker.accept(transfer_id);
+        let result = self.history_store.update(transfer_id, |record| {
+            if record.status != TransferStatus::Pending {
+                return;
+            }
+            record.status = TransferStatus::InProgress;
+            record.staging_dir = Some(staging_dir.to_path_buf());
+            record.routes = routing::plan(
+                &settings.routing_rules,
+                &favorites,
+                record,
+                &settings.download_dir,
+                chrono::Local::now(),
+            );
+        });
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to record accepted transfer {}: {}", transfer_id, e);
+        }
+    }
+
+    /// Undo `record_accepted` for a transfer the engine failed to accept
+    pub fn record_accept_failed(&self, transfer_id: &str) {
+        self.tracker.unaccept(transfer_id);
+        let result = self.history_store.update(transfer_id, |record| {
+            if record.status == TransferStatus::InProgress {
+                record.status = TransferStatus::Pending;
+                record.staging_dir = None;
+                record.routes.clear();
+            }
+        });
+
+        if let Err(e) = result {
+            tracing::warn!("Failed to record transfer {}: {}", transfer_id, e);
+        }
+    }
 }
 
 // ============================================================================
@@ -50,10 +129,11 @@ pub async fn update_favorite(
     id: String,
     name: Option<String>,
     address: Option<String>,
+    compress: Option<bool>,
 ) -> Result<Favorite, String> {
     state
         .favorites
-        .update(&id, name, address, None)
+        .update(&id, name, address, None, compress)
         .map_err(|e| e.to_string())
 }
 
@@ -126,48 +206,193 @@ pub async fn get_peer_info(
 // ============================================================================
 
 /// Send files to a peer
+///
+/// With `compress` unset, files are compressed if the peer is a favorite
+/// with compression turned on.
 #[tauri::command]
 pub async fn send_files(
-    state: State<'_, AppState>,
+    app: AppHandle,
     address: String,
     port: u16,
     file_paths: Vec<String>,
+    compress: Option<bool>,
 ) -> Result<(), String> {
     let paths: Vec<PathBuf> = file_paths.into_iter().map(PathBuf::from).collect();
-
-    let engine = state.engine.lock().await;
-    engine
-        .send_files(&address, port, paths)
-        .await
-        .map_err(|e| e.to_string())
+    let record =
+        tokio::task::spawn_blocking(move || send::outgoing_record(&address, port, paths, false))
+            .await
+            .map_err(|e| e.to_string())?;
+    send::run(&app, record, compress).await
 }
 
 /// Send a directory to a peer (preserving structure)
+///
+/// `filter` leaves out files by pattern or ignore files. With
+/// `preserve_metadata`, modification times, permissions, empty directories
+/// and symlinks are sent along.
 #[tauri::command]
 pub async fn send_directory(
-    state: State<'_, AppState>,
+    app: AppHandle,
     address: String,
     port: u16,
     directory_path: String,
+    filter: Option<DirectoryFilter>,
+    compress: Option<bool>,
+    preserve_metadata: Option<bool>,
+) -> Result<(), String> {
+    let record = tokio::task::spawn_blocking(move || {
+        send::directory_record(
+            &address,
+            port,
+            PathBuf::from(directory_path),
+            filter,
+            preserve_metadata.unwrap_or(false),
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    send::run(&app, record, compress).await
+}
+
+/// Send a text message to a peer
+///
+/// The peer sees the start of the text with the request; once received, the
+/// text is kept in history rather than saved as a file.
+#[tauri::command]
+pub async fn send_text(
+    app: AppHandle,
+    address: String,
+    port: u16,
+    text: String,
 ) -> Result<(), String> {
-    let path = PathBuf::from(directory_path);
+    let record = send::text_record(&address, port, text).map_err(|e| e.to_string())?;
+    send::run(&app, record, None).await
+}
+
+/// List the files a directory send with `filter` would transfer
+#[tauri::command]
+pub async fn preview_directory(
+    directory_path: String,
+    filter: Option<DirectoryFilter>,
+) -> Result<DirectoryPreview, String> {
+    let filter = filter.unwrap_or_default();
+    tokio::task::spawn_blocking(move || select::preview(Path::new(&directory_path), &filter))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
 
+/// List what a send would transfer and check the peer, without sending
+///
+/// Give `file_paths` for a file send, or `directory_path` (and optionally
+/// `filter`) for a directory send.
+#[tauri::command]
+pub async fn prepare_send(
+    state: State<'_, AppState>,
+    address: String,
+    port: u16,
+    file_paths: Option<Vec<String>>,
+    directory_path: Option<String>,
+    filter: Option<DirectoryFilter>,
+) -> Result<PreparedSend, String> {
+    let (paths, directory) = match (file_paths, directory_path) {
+        (None, Some(dir)) => (vec![PathBuf::from(dir)], true),
+        (Some(files), None) => (files.into_iter().map(PathBuf::from).collect(), false),
+        _ => return Err("Give either file paths or a directory path".to_string()),
+    };
     let engine = state.engine.lock().await;
-    engine
-        .send_directory(&address, port, path)
+    manifest::prepare(&engine, &address, port, paths, directory, filter)
         .await
         .map_err(|e| e.to_string())
 }
 
+/// Send a transfer from history again
+///
+/// Sends all files, or with `only_failed` only those that did not make it,
+/// to the original peer unless another address or port is given. The new
+/// record links to the original one; its ID is returned.
+#[tauri::command]
+pub async fn resend_from_history(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+    only_failed: bool,
+    address: Option<String>,
+    port: Option<u16>,
+) -> Result<String, String> {
+    let original = state
+        .history_store
+        .get(&id)
+        .ok_or_else(|| format!("Transfer not found: {}", id))?;
+    let record = tokio::task::spawn_blocking(move || {
+        send::resend_record(&original, only_failed, address, port)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+
+    let new_id = record.id.clone();
+    send::run(&app, record, None).await?;
+    Ok(new_id)
+}
+
+/// Resume a failed or cancelled send
+///
+/// The files that did not make it are sent to the same peer again, each
+/// one the peer kept part of from where it stopped. The new record links to
+/// the original one; its ID is returned.
+#[tauri::command]
+pub async fn resume_transfer(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<String, String> {
+    let original = state
+        .history_store
+        .get(&id)
+        .ok_or_else(|| format!("Transfer not found: {}", id))?;
+    let record = tokio::task::spawn_blocking(move || send::resume_record(&original))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+
+    let new_id = record.id.clone();
+    send::run(&app, record, None).await?;
+    Ok(new_id)
+}
+
+/// Remove the files an interrupted incoming transfer kept for resuming
+#[tauri::command]
+pub async fn discard_partial_files(state: State<'_, AppState>, id: String) -> Result<(), String> {
+    let record = state
+        .history_store
+        .get(&id)
+        .ok_or_else(|| format!("Transfer not found: {}", id))?;
+    // Parts are kept in the staging root they were received in
+    let kept = record.partial_files.first().map(|p| p.path.as_path());
+    let root = match kept.and_then(staging::root_of) {
+        Some(root) => resume::dir(root),
+        None => return Ok(()),
+    };
+    resume::discard(&root, &id).map_err(|e| e.to_string())?;
+    state
+        .history_store
+        .update(&id, |record| record.partial_files.clear())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
 /// Accept a pending transfer
+///
+/// Incoming transfers are received side by side; one whose file names clash
+/// with those of a transfer being received starts once that one ends.
 #[tauri::command]
 pub async fn accept_transfer(
     state: State<'_, AppState>,
     transfer_id: String,
-) -> Result<String, String> {
-    let engine = state.engine.lock().await;
-    engine
-        .accept_transfer(&transfer_id)
+) -> Result<(), String> {
+    crate::receive::accept_incoming(&state, &transfer_id)
         .await
         .map_err(|e| e.to_string())
 }
@@ -182,33 +407,64 @@ pub async fn reject_transfer(
     engine
         .reject_transfer(&transfer_id)
         .await
-        .map_err(|e| e.to_string())
+        .map_err(|e| e.to_string())?;
+    drop(engine);
+
+    state.tracker.remove(&transfer_id);
+    let _ = state
+        .history_store
+        .set_status(&transfer_id, TransferStatus::Rejected, None);
+    Ok(())
 }
 
 /// Cancel an in-progress transfer
+///
+/// The files of an incoming transfer that arrived complete are delivered.
 #[tauri::command]
 pub async fn cancel_transfer(
+    app: AppHandle,
     state: State<'_, AppState>,
     transfer_id: String,
 ) -> Result<(), String> {
     let engine = state.engine.lock().await;
-    engine
-        .cancel_transfer(&transfer_id)
-        .await
-        .map_err(|e| e.to_string())
+    // A transfer waiting its turn has not been accepted by the engine yet
+    if state.receive_queue.is_waiting(&transfer_id) {
+        engine.reject_transfer(&transfer_id).await
+    } else {
+        engine.cancel_transfer(&transfer_id).await
+    }
+    .map_err(|e| e.to_string())?;
+    drop(engine);
+
+    let receiving = state.history_store.get(&transfer_id).is_some_and(|r| {
+        r.direction == TransferDirection::Received && r.status == TransferStatus::InProgress
+    });
+    state.tracker.remove(&transfer_id);
+    let _ = state
+        .history_store
+        .set_status(&transfer_id, TransferStatus::Cancelled, None);
+    crate::receive::finish_receive(&state, &transfer_id).await;
+    if receiving {
+        tauri::async_runtime::spawn(crate::receive::process_received(app, transfer_id));
+    }
+    Ok(())
 }
 
 /// Accept all pending transfers
+///
+/// They are received one after another, in the order they arrived.
 #[tauri::command]
 pub async fn accept_all_transfers(state: State<'_, AppState>) -> Result<Vec<String>, String> {
-    let engine = state.engine.lock().await;
-    let results = engine.accept_all_transfers().await;
-
-    // Collect successful transfer IDs
-    let accepted: Vec<String> = results
-        .into_iter()
-        .filter_map(|(id, result)| result.ok().map(|_| id))
-        .collect();
+    let mut pending = state.engine.lock().await.get_pending_transfers().await;
+    pending.sort_by_key(|t| t.received_at);
+
+    let mut accepted = Vec::new();
+    for transfer in pending {
+        match crate::receive::accept_incoming(&state, &transfer.id).await {
+            Ok(()) => accepted.push(transfer.id),
+            Err(e) => tracing::warn!("Failed to accept transfer {}: {}", transfer.id, e),
+        }
+    }
 
     Ok(accepted)
 }
@@ -218,12 +474,21 @@ pub async fn accept_all_transfers(state: State<'_, AppState>) -> Result<Vec<Stri
 pub async fn reject_all_transfers(state: State<'_, AppState>) -> Result<(), String> {
     let engine = state.engine.lock().await;
     let results = engine.reject_all_transfers().await;
+    drop(engine);
 
     // Check if any rejections failed
-    let errors: Vec<_> = results
-        .into_iter()
-        .filter_map(|(id, result)| result.err().map(|e| format!("{}: {}", id, e)))
-        .collect();
+    let mut errors = Vec::new();
+    for (id, result) in results {
+        match result {
+            Ok(_) => {
+                state.tracker.remove(&id);
+                let _ = state
+                    .history_store
+                    .set_status(&id, TransferStatus::Rejected, None);
+            }
+            Err(e) => errors.push(format!("{}: {}", id, e)),
+        }
+    }
 
     if errors.is_empty() {
         Ok(())
@@ -240,27 +505,83 @@ pub async fn get_pending_transfers(
     let engine = state.engine.lock().await;
     let pending = engine.get_pending_transfers().await;
     Ok(pending
-        .into_iter()
-        .map(|p| PendingTransfer {
-            id: p.id,
-            source_ip: p.source_ip,
-            sender_name: p.sender_name,
-            files: p
-                .files
-                .into_iter()
-                .map(|f| TransferFile {
-                    id: f.id,
-                    name: f.name,
-                    size: f.size,
-                    mime_type: f.mime_type,
-                })
-                .collect(),
-            total_size: p.total_size,
-            received_at: p.received_at,
+        .iter()
+        .map(|t| {
+            state
+                .incoming_relay
+                .restore_source(events::pending_from_engine(t))
         })
         .collect())
 }
 
+/// Get a snapshot of the transfers that have not finished yet
+#[tauri::command]
+pub async fn get_active_transfers(
+    state: State<'_, AppState>,
+) -> Result<Vec<ActiveTransfer>, String> {
+    Ok(state.tracker.list())
+}
+
+/// Get recently published events, oldest first, for replay after a reload
+///
+/// Pass the `seq` of the last event already seen to receive only newer ones.
+/// Progress events are not kept; use `get_active_transfers` instead.
+#[tauri::command]
+pub async fn get_recent_events(
+    state: State<'_, AppState>,
+    after: Option<u64>,
+) -> Result<Vec<RecordedEvent>, String> {
+    Ok(state.events.recent(after))
+}
+
+/// Get counts of published, coalesced and dropped events
+#[tauri::command]
+pub async fn get_event_stats(state: State<'_, AppState>) -> Result<EventStats, String> {
+    Ok(state.events.stats())
+}
+
+/// Resolve a received file's pending name conflict
+#[tauri::command]
+pub async fn resolve_conflict(
+    state: State<'_, AppState>,
+    transfer_id: String,
+    name: String,
+    policy: ConflictPolicy,
+) -> Result<String, String> {
+    crate::receive::resolve_conflict(&state, &transfer_id, &name, policy)
+        .map(|path| path.to_string_lossy().to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Check a file of a transfer on disk against its recorded SHA-256 digest
+///
+/// Returns the digest if the file matches.
+#[tauri::command]
+pub async fn verify_file(
+    state: State<'_, AppState>,
+    transfer_id: String,
+    file_id: String,
+) -> Result<String, String> {
+    let record = state
+        .history_store
+        .get(&transfer_id)
+        .ok_or_else(|| format!("Transfer not found: {}", transfer_id))?;
+    let file = record
+        .files
+        .into_iter()
+        .find(|f| f.id == file_id)
+        .ok_or_else(|| format!("File not found: {}", file_id))?;
+    let (path, expected) = match (file.path, file.sha256) {
+        (Some(path), Some(expected)) => (path, expected),
+        _ => return Err(format!("No checksum recorded for {}", file.name)),
+    };
+
+    tokio::task::spawn_blocking(move || checksum::verify(&file.name, &path, &expected))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
 /// Get transfer history
 #[tauri::command]
 pub async fn get_transfer_history(
@@ -269,12 +590,158 @@ pub async fn get_transfer_history(
     Ok(state.history_store.list())
 }
 
+/// Filter, sort and paginate transfer history
+#[tauri::command]
+pub async fn query_transfer_history(
+    state: State<'_, AppState>,
+    query: HistoryQuery,
+) -> Result<HistoryPage, String> {
+    Ok(state.history_store.query(&query))
+}
+
 /// Clear transfer history
 #[tauri::command]
 pub async fn clear_transfer_history(state: State<'_, AppState>) -> Result<(), String> {
     state.history_store.clear().map_err(|e| e.to_string())
 }
 
+/// Delete history records by ID, returning the number deleted
+#[tauri::command]
+pub async fn delete_history_entries(
+    state: State<'_, AppState>,
+    ids: Vec<String>,
+) -> Result<usize, String> {
+    state.history_store.delete(&ids).map_err(|e| e.to_string())
+}
+
+/// Delete the history records matching a query's filters
+///
+/// `older_than_days` additionally limits the deletion to records started
+/// that many days ago or earlier. A query without any filter is refused;
+/// use `clear_transfer_history` to delete everything.
+#[tauri::command]
+pub async fn delete_history_matching(
+    state: State<'_, AppState>,
+    mut query: HistoryQuery,
+    older_than_days: Option<u32>,
+) -> Result<usize, String> {
+    if let Some(days) = older_than_days {
+        let cutoff = Utc::now() - chrono::Duration::days(days as i64);
+        query.to = Some(query.to.map_or(cutoff, |to| to.min(cutoff)));
+    }
+    if !query.has_filters() {
+        return Err("Refusing to delete history without a filter".to_string());
+    }
+
+    state
+        .history_store
+        .delete_matching(&query)
+        .map_err(|e| e.to_string())
+}
+
+/// Forget a peer: delete its history records and favorites, and stop
+/// trusting it
+///
+/// Favorites are matched by address or last resolved IP; history records
+/// and trusted hosts by the given address and the addresses of the deleted
+/// favorites.
+#[tauri::command]
+pub async fn forget_peer(
+    state: State<'_, AppState>,
+    app: AppHandle,
+    address: String,
+) -> Result<ForgottenPeer, String> {
+    let address = address.trim();
+    let favorites = state
+        .favorites
+        .delete_by_address(address)
+        .map_err(|e| e.to_string())?;
+
+    let mut addresses = vec![address.to_lowercase()];
+    for favorite in &favorites {
+        addresses.push(favorite.address.to_lowercase());
+        addresses.extend(favorite.last_resolved_ip.clone());
+    }
+    let history_deleted = state
+        .history_store
+        .delete_where(|record| addresses.contains(&record.peer_address.to_lowercase()))
+        .map_err(|e| e.to_string())?;
+
+    let mut settings = state.settings.write().await;
+    let (trusted_hosts, kept): (Vec<String>, Vec<String>) = settings
+        .trusted_hosts
+        .drain(..)
+        .partition(|host| addresses.contains(&host.to_lowercase()));
+    settings.trusted_hosts = kept;
+    if !trusted_hosts.is_empty() {
+        state
+            .settings_store
+            .update(settings.clone())
+            .map_err(|e| e.to_string())?;
+        let _ = app.emit("settings-updated", settings.clone());
+    }
+
+    Ok(ForgottenPeer {
+        history_deleted,
+        favorites,
+        trusted_hosts,
+    })
+}
+
+/// Export history to a CSV or JSON Lines file, one row per file
+///
+/// The format is taken from the file extension unless given. Returns the
+/// number of rows written.
+#[tauri::command]
+pub async fn export_history(
+    state: State<'_, AppState>,
+    path: String,
+    format: Option<ExportFormat>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<usize, String> {
+    export::export_to_file(&state.history_store, Path::new(&path), format, from, to)
+        .map_err(|e| e.to_string())
+}
+
+/// Import history exported by `export_history`, skipping known transfers
+///
+/// Returns the number of transfers added.
+#[tauri::command]
+pub async fn import_history(
+    state: State<'_, AppState>,
+    path: String,
+    format: Option<ExportFormat>,
+) -> Result<usize, String> {
+    export::import_from_file(&state.history_store, Path::new(&path), format)
+        .map_err(|e| e.to_string())
+}
+
+/// Get transfer statistics, optionally limited to transfers started
+/// within `[from, to)`
+#[tauri::command]
+pub async fn get_transfer_stats(
+    state: State<'_, AppState>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<TransferStats, String> {
+    Ok(stats::compute(&state.history_store.list(), from, to))
+}
+
+/// Get the webhook delivery log, oldest first
+#[tauri::command]
+pub async fn get_webhook_deliveries(
+    state: State<'_, AppState>,
+) -> Result<Vec<WebhookDelivery>, String> {
+    Ok(state.webhooks.list())
+}
+
+/// Clear the webhook delivery log
+#[tauri::command]
+pub async fn clear_webhook_deliveries(state: State<'_, AppState>) -> Result<(), String> {
+    state.webhooks.clear().map_err(|e| e.to_string())
+}
+
 // ============================================================================
 // SETTINGS COMMANDS
 // ============================================================================
@@ -310,28 +777,48 @@ pub async fn update_settings(
     *settings = new_settings.clone();
     drop(settings);
 
-    // Update engine config
-    let engine_config = EngineConfig::builder()
-        .port(new_settings.port)
-        .device_name(&new_settings.device_name)
-        .download_dir(&new_settings.download_dir)
-        .trusted_hosts(new_settings.trusted_hosts.clone())
-        .receive_only(new_settings.receive_only)
-        .build();
+    if let Err(e) = state
+        .history_store
+        .set_retention(new_settings.history_retention.clone())
+    {
+        tracing::warn!("Failed to apply history retention: {}", e);
+    }
+
+    // Update engine config, keeping it on the transfers being received
+    let staging_dir = state
+        .receive_queue
+        .receiving_dir()
+        .unwrap_or_else(|| staging::prepare_receiving(&new_settings.download_dir));
+    let engine_port = state.incoming_relay.engine_port(old_port);
+    let engine_config = crate::build_engine_config(&new_settings, &staging_dir, engine_port);
 
     let mut engine = state.engine.lock().await;
     engine.update_config(engine_config).await;
 
-    // If port changed, attempt to rebind the server
-    if port_changed {
-        if let Err(e) = engine.change_port(new_settings.port).await {
+    // Rebind the server, or the relay limiting downloads in front of it, and
+    // start or stop the relay as download limits are set or cleared
+    let applied = state
+        .incoming_relay
+        .apply(&app, &mut engine, &new_settings, old_port)
+        .await;
+    if let Err(e) = applied {
+        if !port_changed {
+            tracing::warn!("Failed to rebind the server: {}", e);
+        } else {
             tracing::warn!("Failed to change port to {}: {}", new_settings.port, e);
             // Revert port setting on failure
-            drop(engine);
             let mut settings = state.settings.write().await;
             settings.port = old_port;
             let reverted_settings = settings.clone();
             drop(settings);
+            if let Err(e) = state
+                .incoming_relay
+                .apply(&app, &mut engine, &reverted_settings, old_port)
+                .await
+            {
+                tracing::warn!("Failed to rebind the server: {}", e);
+            }
+            drop(engine);
             state
                 .settings_store
                 .update(reverted_settings.clone())
@@ -348,16 +835,15 @@ pub async fn update_settings(
 }
 
 /// Add a trusted host
+///
+/// Trusted hosts are auto-accepted by the app (within receive limits),
+/// so the engine is not told about them.
 #[tauri::command]
 pub async fn add_trusted_host(state: State<'_, AppState>, host: String) -> Result<(), String> {
     let mut settings = state.settings.write().await;
     if !settings.trusted_hosts.contains(&host) {
-        settings.trusted_hosts.push(host.clone());
+        settings.trusted_hosts.push(host);
     }
-    drop(settings);
-
-    let mut engine = state.engine.lock().await;
-    engine.add_trusted_host(host).await;
 
     Ok(())
 }
@@ -367,10 +853,6 @@ pub async fn add_trusted_host(state: State<'_, AppState>, host: String) -> Resul
 pub async fn remove_trusted_host(state: State<'_, AppState>, host: String) -> Result<(), String> {
     let mut settings = state.settings.write().await;
     settings.trusted_hosts.retain(|h| h != &host);
-    drop(settings);
-
-    let mut engine = state.engine.lock().await;
-    engine.remove_trusted_host(&host).await;
 
     Ok(())
 }
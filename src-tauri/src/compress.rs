@@ -0,0 +1,401 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Compression
+//
+// Sends can compress files with zstd, per transfer or for every send to a
+// favorite. The engine sends files as they are, so compression happens
+// around it: the sender compresses each compressible file into a temporary
+// directory as `<name>.gosh.zst` and sends that instead, and the receiver
+// unpacks them in staging before anything else sees them. Files that
+// are already compressed (by extension or MIME type), or that zstd does not
+// shrink, are sent unchanged.
+//
+// Peers advertise support as "zstd" in the `capabilities` list of their
+// `/info` response, and only those get compressed files; a peer without the
+// list gets files unchanged. The engine serves `/info` and does not list
+// capabilities yet. The sender's sidecar (see `sidecar`) names the files it
+// compressed, and the receiver unpacks only those, within its receive
+// limits.
+
+use crate::types::{AppError, CompressionInfo, FileStatus, TransferRecord};
+use crate::{checksum, filter, select};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Suffix of files compressed for a transfer
+pub const SUFFIX: &str = ".gosh.zst";
+
+/// zstd level used for sends
+const LEVEL: i32 = 3;
+
+/// Extensions of formats that are compressed already
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "br", "bz2", "cab", "deb", "docx", "epub", "flac", "gif", "gz",
+    "heic", "jar", "jpeg", "jpg", "lz", "lz4", "lzma", "m4a", "m4v", "mkv", "mov", "mp3", "mp4",
+    "odp", "ods", "odt", "ogg", "opus", "png", "pptx", "rar", "rpm", "tgz", "txz", "webm", "webp",
+    "xlsx", "xz", "zip", "zst",
+];
+
+/// MIME types (or prefixes ending in `/`) of formats that are compressed
+/// already
+const COMPRESSED_MIME_TYPES: &[&str] = &[
+    "audio/",
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "video/",
+    "application/zip",
+    "application/gzip",
+    "application/x-7z-compressed",
+    "application/zstd",
+];
+
+/// The name a file had before it was compressed for a transfer
+pub fn original_name(name: &str) -> &str {
+    name.strip_suffix(SUFFIX).unwrap_or(name)
+}
+
+/// Whether a file is worth compressing, judging by its name and MIME type
+pub fn is_compressible(name: &str, mime_type: Option<&str>) -> bool {
+    let extension = Path::new(name).extension().and_then(|e| e.to_str());
+    if extension.is_some_and(|ext| {
+        COMPRESSED_EXTENSIONS
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(ext))
+    }) {
+        return false;
+    }
+
+    let mime = mime_type.map(str::to_lowercase);
+    !mime.is_some_and(|mime| {
+        COMPRESSED_MIME_TYPES.iter().any(|m| {
+            if m.ends_with('/') {
+                mime.starts_with(m)
+            } else {
+                mime == *m
+            }
+        })
+    })
+}
+
+/// Whether a peer's `/info` response allows sending compressed files
+///
+/// Only peers listing "zstd" in their capabilities do.
+pub fn peer_supports(info: &serde_json::Value) -> bool {
+    info.get("capabilities")
+        .and_then(|c| c.as_array())
+        .is_some_and(|capabilities| capabilities.iter().any(|c| c == "zstd"))
+}
+
+fn add_sizes(info: &mut Option<CompressionInfo>, original: u64, compressed: u64) {
+    let info = info.get_or_insert_with(CompressionInfo::default);
+    info.original_bytes += original;
+    info.compressed_bytes += compressed;
+    info.ratio = info.compressed_bytes as f64 / info.original_bytes.max(1) as f64;
+}
+
+/// Files prepared for a compressed send
+///
+/// The temporary directory is removed when dropped.
+pub struct Packed {
+    dir: PathBuf,
+    /// Paths to send in place of the original ones
+    pub paths: Vec<PathBuf>,
+    /// Sizes of the files that were compressed
+    pub info: CompressionInfo,
+    /// Digests of the compressed files' contents, by compressed path
+    pub digests: HashMap<PathBuf, String>,
+}
+
+impl Drop for Packed {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            tracing::warn!("Failed to remove {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+/// Compress `source` to `<target>.gosh.zst`, hashing the bytes compressed
+///
+/// Returns the compressed path, or None if the file is not worth
+/// compressing.
+fn pack_file(
+    source: &Path,
+    target: &Path,
+    info: &mut Option<CompressionInfo>,
+    digests: &mut HashMap<PathBuf, String>,
+) -> Result<Option<PathBuf>, AppError> {
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    if !is_compressible(&name, None) {
+        return Ok(None);
+    }
+
+    let mut packed = target.as_os_str().to_os_string();
+    packed.push(SUFFIX);
+    let packed = PathBuf::from(packed);
+    let io_error =
+        |e: std::io::Error| AppError::FileIo(format!("Failed to compress {:?}: {}", source, e));
+    let mut input = checksum::HashingReader::new(fs::File::open(source).map_err(io_error)?);
+    let output = fs::File::create(&packed).map_err(io_error)?;
+    zstd::stream::copy_encode(&mut input, output, LEVEL).map_err(io_error)?;
+
+    let original = fs::metadata(source).map_err(io_error)?.len();
+    let compressed = fs::metadata(&packed).map_err(io_error)?.len();
+    if compressed >= original {
+        let _ = fs::remove_file(&packed);
+        return Ok(None);
+    }
+    add_sizes(info, original, compressed);
+    digests.insert(packed.clone(), input.digest());
+    Ok(Some(packed))
+}
+
+/// Copy a directory to `target`, compressing the files worth it
+///
+/// Other files are hard-linked, or copied if that fails.
+fn pack_dir(
+    source: &Path,
+    target: &Path,
+    info: &mut Option<CompressionInfo>,
+    digests: &mut HashMap<PathBuf, String>,
+) -> Result<(), AppError> {
+    fs::create_dir_all(target)
+        .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", target, e)))?;
+    let entries = fs::read_dir(source)
+        .map_err(|e| AppError::FileIo(format!("Failed to read {:?}: {}", source, e)))?;
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let dest = target.join(entry.file_name());
+        if path.is_dir() {
+            pack_dir(&path, &dest, info, digests)?;
+        } else if pack_file(&path, &dest, info, digests)?.is_none() {
+            select::link_or_copy(&path, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prepare the files of a send for compression
+///
+/// Returns None if no file is worth compressing, in which case the
+/// original paths are sent.
+pub fn pack(
+    transfer_id: &str,
+    paths: &[PathBuf],
+    directory: bool,
+) -> Result<Option<Packed>, AppError> {
+    let dir = std::env::temp_dir().join(format!("gosh-transfer-{}", transfer_id));
+    fs::create_dir_all(&dir)
+        .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", dir, e)))?;
+    // Removes the directory again on errors
+    let mut packed = Packed {
+        dir,
+        paths: Vec::new(),
+        info: CompressionInfo::default(),
+        digests: HashMap::new(),
+    };
+
+    let mut info = None;
+    for path in paths {
+        let target = packed.dir.join(path.file_name().unwrap_or_default());
+        if directory {
+            pack_dir(path, &target, &mut info, &mut packed.digests)?;
+            packed.paths.push(target);
+        } else if path.is_file() {
+            let sent = pack_file(path, &target, &mut info, &mut packed.digests)?;
+            packed.paths.push(sent.unwrap_or_else(|| path.clone()));
+        } else {
+            packed.paths.push(path.clone());
+        }
+    }
+
+    Ok(info.map(|info| {
+        packed.info = info;
+        packed
+    }))
+}
+
+/// Decompress the files of a received transfer that the sender compressed
+/// for it, in staging
+///
+/// Only files the sender's sidecar lists as compressed (by original name in
+/// `compressed`) are unpacked, and only as long as the transfer stays within
+/// `limit` bytes in all. A file that would take it past the limit is removed
+/// and marked failed; the returned errors name those files. The record's
+/// files and routes take the original names. Files that cannot be
+/// decompressed keep their compressed name.
+pub fn unpack(
+    staging: &Path,
+    record: &mut TransferRecord,
+    compressed: &HashSet<String>,
+    limit: u64,
+) -> Vec<String> {
+    let mut info = None;
+    let mut errors = Vec::new();
+    let mut total = record.total_size;
+    for file in &mut record.files {
+        let name = original_name(&file.name).to_string();
+        if name == file.name || !compressed.contains(&name) {
+            continue;
+        }
+        let (source, target) = match (
+            filter::received_path(staging, &file.name),
+            filter::received_path(staging, &name),
+        ) {
+            (Some(source), Some(target)) if source.is_file() => (source, target),
+            _ => continue,
+        };
+
+        // What this file may grow to, given the rest of the transfer
+        let rest = total.saturating_sub(file.size);
+        let allowed = limit.saturating_sub(rest);
+        match decode(&source, &target, allowed) {
+            Ok(Some(size)) => {
+                let _ = fs::remove_file(&source);
+                add_sizes(&mut info, size, file.size);
+                for route in record.routes.iter_mut().filter(|r| r.name == file.name) {
+                    route.name = name.clone();
+                }
+                total = rest.saturating_add(size);
+                file.size = size;
+                file.name = name;
+            }
+            Ok(None) => {
+                let _ = fs::remove_file(&source);
+                let _ = fs::remove_file(&target);
+                let error = format!(
+                    "{}: {}",
+                    name,
+                    AppError::TransferTooLarge {
+                        size: rest.saturating_add(allowed).saturating_add(1),
+                        limit,
+                    }
+                );
+                file.status = Some(FileStatus::Failed);
+                file.error = Some(error.clone());
+                errors.push(error);
+            }
+            Err(e) => {
+                tracing::warn!("Failed to decompress {:?}: {}", source, e);
+                let _ = fs::remove_file(&target);
+            }
+        }
+    }
+
+    if let Some(info) = info {
+        record.total_size = record.files.iter().map(|f| f.size).sum();
+        record.compression = Some(info);
+    }
+    errors
+}
+
+/// Decompress `source` to `target`, writing at most `allowed` bytes
+///
+/// Returns the decompressed size, or None if it would be more.
+fn decode(source: &Path, target: &Path, allowed: u64) -> std::io::Result<Option<u64>> {
+    let decoder = zstd::stream::read::Decoder::new(fs::File::open(source)?)?;
+    let mut output = fs::File::create(target)?;
+    let written = std::io::copy(&mut decoder.take(allowed.saturating_add(1)), &mut output)?;
+    Ok((written <= allowed).then_some(written))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PendingTransfer, TransferFile, TransferKind};
+
+    #[test]
+    fn test_is_compressible() {
+        assert!(is_compressible("data.csv", Some("text/csv")));
+        assert!(!is_compressible("photo.JPG", None));
+        assert!(!is_compressible("clip", Some("video/mp4")));
+    }
+
+    #[test]
+    fn test_peer_supports() {
+        assert!(!peer_supports(&serde_json::json!({ "deviceName": "pc" })));
+        assert!(!peer_supports(
+            &serde_json::json!({ "capabilities": ["resume"] })
+        ));
+        assert!(peer_supports(
+            &serde_json::json!({ "capabilities": ["zstd"] })
+        ));
+    }
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let dir = std::env::temp_dir().join(format!("gosh-compress-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("logs")).unwrap();
+        let log = "2026-01-01 INFO started\n".repeat(1000);
+        fs::write(dir.join("logs/app.log"), &log).unwrap();
+        fs::write(dir.join("logs/photo.jpg"), [7u8; 100]).unwrap();
+
+        let packed = pack("t1", &[dir.join("logs")], true).unwrap().unwrap();
+        let sent = &packed.paths[0];
+        assert!(sent.join("app.log.gosh.zst").is_file());
+        assert!(sent.join("photo.jpg").is_file());
+        assert_eq!(packed.info.original_bytes, log.len() as u64);
+        assert!(packed.info.ratio < 0.1);
+        assert_eq!(
+            packed.digests[&sent.join("app.log.gosh.zst")],
+            checksum::sha256_file(&dir.join("logs/app.log")).unwrap()
+        );
+
+        // Receive the packed directory
+        let staging = dir.join("staging");
+        fs::create_dir_all(staging.join("logs")).unwrap();
+        fs::copy(
+            sent.join("app.log.gosh.zst"),
+            staging.join("logs/app.log.gosh.zst"),
+        )
+        .unwrap();
+        let file = TransferFile::new("f1", "logs/app.log.gosh.zst", packed.info.compressed_bytes);
+        let incoming = PendingTransfer {
+            id: "t1".to_string(),
+            source_ip: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![file],
+            total_size: packed.info.compressed_bytes,
+            received_at: chrono::Utc::now(),
+            kind: TransferKind::Files,
+            text_preview: None,
+        };
+
+        // Only files the sender listed as compressed are unpacked
+        let mut record = TransferRecord::incoming(&incoming);
+        assert!(unpack(&staging, &mut record, &HashSet::new(), u64::MAX).is_empty());
+        assert_eq!(record.files[0].name, "logs/app.log.gosh.zst");
+
+        // ... and only within the limit
+        let compressed = HashSet::from(["logs/app.log".to_string()]);
+        let errors = unpack(&staging, &mut record, &compressed, 1000);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(record.files[0].status, Some(FileStatus::Failed));
+        assert!(!staging.join("logs/app.log.gosh.zst").exists());
+        assert!(!staging.join("logs/app.log").exists());
+
+        fs::copy(
+            sent.join("app.log.gosh.zst"),
+            staging.join("logs/app.log.gosh.zst"),
+        )
+        .unwrap();
+        let mut record = TransferRecord::incoming(&incoming);
+        assert!(unpack(&staging, &mut record, &compressed, log.len() as u64).is_empty());
+        assert_eq!(record.files[0].name, "logs/app.log");
+        assert_eq!(record.total_size, log.len() as u64);
+        assert_eq!(
+            fs::read_to_string(staging.join("logs/app.log")).unwrap(),
+            log
+        );
+        assert!(!staging.join("logs/app.log.gosh.zst").exists());
+        assert!(record.compression.is_some());
+
+        let temp = packed.dir.clone();
+        drop(packed);
+        assert!(!temp.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Name-conflict handling for received files
+//
+// Received files are staged by the engine and then moved to their final
+// destination. If the destination name is taken, the configured policy
+// decides whether the new file is renamed, overwrites, is dropped, or is
+// left aside for the user.
+
+use crate::checksum::sha256_file;
+use crate::types::{AppError, ConflictPolicy, ConflictResolution};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Move `source` to `target`, applying `policy` if `target` already exists
+///
+/// `modified` is the sender's modification time of the received file; the
+/// staged copy's own time is only when it arrived. Returns where the data
+/// ended up and, if there was a conflict, how it was resolved.
+pub fn place(
+    source: &Path,
+    target: &Path,
+    policy: ConflictPolicy,
+    modified: Option<SystemTime>,
+) -> Result<(PathBuf, Option<ConflictResolution>), AppError> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", parent, e)))?;
+    }
+
+    if !target.exists() {
+        move_file(source, target)?;
+        return Ok((target.to_path_buf(), None));
+    }
+
+    let resolution = match policy {
+        ConflictPolicy::Rename => ConflictResolution::Renamed,
+        ConflictPolicy::Overwrite => ConflictResolution::Overwritten,
+        ConflictPolicy::SkipIfIdentical => {
+            if is_identical(source, target)? {
+                ConflictResolution::Skipped
+            } else {
+                ConflictResolution::Renamed
+            }
+        }
+        ConflictPolicy::KeepNewer => match modified {
+            Some(sent) if sent > modified_time(target)? => ConflictResolution::Overwritten,
+            Some(_) => ConflictResolution::Skipped,
+            None => ConflictResolution::Renamed,
+        },
+        ConflictPolicy::Ask => {
+            return Ok((source.to_path_buf(), Some(ConflictResolution::Pending)))
+        }
+    };
+
+    let path = match resolution {
+        ConflictResolution::Renamed => {
+            let renamed = unique_path(target);
+            move_file(source, &renamed)?;
+            renamed
+        }
+        ConflictResolution::Overwritten => {
+            move_file(source, target)?;
+            target.to_path_buf()
+        }
+        ConflictResolution::Skipped => {
+            fs::remove_file(source)
+                .map_err(|e| AppError::FileIo(format!("Failed to remove skipped file: {}", e)))?;
+            target.to_path_buf()
+        }
+        ConflictResolution::Pending => unreachable!(),
+    };
+
+    Ok((path, Some(resolution)))
+}
+
+/// Move a file, falling back to copy + remove across file systems
+pub fn move_file(from: &Path, to: &Path) -> Result<(), AppError> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to).map_err(|e| AppError::FileIo(format!("Failed to move file: {}", e)))?;
+    fs::remove_file(from)
+        .map_err(|e| AppError::FileIo(format!("Failed to remove moved file: {}", e)))
+}
+
+/// Append ` (1)`, ` (2)`, ... to the file stem until the path is free
+pub fn unique_path(path: &Path) -> PathBuf {
+    if !path.exists() {
+        return path.to_path_buf();
+    }
+
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("file");
+    let ext = path.extension().and_then(|e| e.to_str());
+    (1..)
+        .map(|n| {
+            let name = match ext {
+                Some(ext) => format!("{} ({}).{}", stem, n, ext),
+                None => format!("{} ({})", stem, n),
+            };
+            path.with_file_name(name)
+        })
+        .find(|p| !p.exists())
+        .unwrap()
+}
+
+/// Remove the now-empty parent directories of `path`, up to (not including) `root`
+pub fn remove_empty_parents(path: &Path, root: &Path) {
+    for dir in path.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+}
+
+fn is_identical(a: &Path, b: &Path) -> Result<bool, AppError> {
+    let size = |p: &Path| {
+        fs::metadata(p)
+            .map(|m| m.len())
+            .map_err(|e| AppError::FileIo(format!("Failed to read {:?}: {}", p, e)))
+    };
+
+    Ok(size(a)? == size(b)? && sha256_file(a)? == sha256_file(b)?)
+}
+
+fn modified_time(path: &Path) -> Result<SystemTime, AppError> {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| AppError::FileIo(format!("Failed to read {:?}: {}", path, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_place_policies() {
+        let dir = std::env::temp_dir().join(format!("gosh-conflict-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = dir.join("app.zip");
+        fs::write(&target, b"v1").unwrap();
+
+        let staged = dir.join("staged.zip");
+        fs::write(&staged, b"v1").unwrap();
+        let (path, resolution) =
+            place(&staged, &target, ConflictPolicy::SkipIfIdentical, None).unwrap();
+        assert_eq!(resolution, Some(ConflictResolution::Skipped));
+        assert_eq!(path, target);
+        assert!(!staged.exists());
+
+        fs::write(&staged, b"v2").unwrap();
+        let (path, resolution) =
+            place(&staged, &target, ConflictPolicy::SkipIfIdentical, None).unwrap();
+        assert_eq!(resolution, Some(ConflictResolution::Renamed));
+        assert_eq!(path, dir.join("app (1).zip"));
+
+        fs::write(&staged, b"v3").unwrap();
+        let (_, resolution) = place(&staged, &target, ConflictPolicy::Ask, None).unwrap();
+        assert_eq!(resolution, Some(ConflictResolution::Pending));
+        assert!(staged.exists());
+
+        let (_, resolution) = place(&staged, &target, ConflictPolicy::Overwrite, None).unwrap();
+        assert_eq!(resolution, Some(ConflictResolution::Overwritten));
+        assert_eq!(fs::read(&target).unwrap(), b"v3");
+
+        // Keep-newer goes by the sender's time, and keeps both without it
+        let existing = modified_time(&target).unwrap();
+        let older = Some(existing - std::time::Duration::from_secs(60));
+        fs::write(&staged, b"v4").unwrap();
+        let (_, resolution) = place(&staged, &target, ConflictPolicy::KeepNewer, older).unwrap();
+        assert_eq!(resolution, Some(ConflictResolution::Skipped));
+        assert_eq!(fs::read(&target).unwrap(), b"v3");
+
+        fs::write(&staged, b"v5").unwrap();
+        let (path, resolution) = place(&staged, &target, ConflictPolicy::KeepNewer, None).unwrap();
+        assert_eq!(resolution, Some(ConflictResolution::Renamed));
+        assert_eq!(path, dir.join("app (2).zip"));
+
+        let newer = Some(existing + std::time::Duration::from_secs(60));
+        fs::write(&staged, b"v6").unwrap();
+        let (_, resolution) = place(&staged, &target, ConflictPolicy::KeepNewer, newer).unwrap();
+        assert_eq!(resolution, Some(ConflictResolution::Overwritten));
+        assert_eq!(fs::read(&target).unwrap(), b"v6");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
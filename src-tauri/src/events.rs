@@ -0,0 +1,763 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Application events
+//
+// Engine events are translated into typed `AppEvent`s and published on a
+// broadcast bus. Each consumer (webview, history recorder, notifier,
+// webhooks) subscribes independently, so a slow consumer never holds up the
+// others and new consumers can be added without touching the engine loop.
+// Recent events (except progress) are kept so late subscribers, such as a
+// reloaded webview, can catch up on what they missed.
+//
+// Progress is coalesced per transfer before publishing, so a fast transfer
+// cannot flood the bus. A consumer that still falls behind is told how many
+// events it missed (`EventsLagged`) and resynchronizes instead of stopping.
+
+use crate::text;
+use crate::types::{FileConflict, PendingTransfer, QuarantinedFile, TransferFile, TransferKind};
+use chrono::{DateTime, Utc};
+use gosh_lan_transfer::EngineEvent;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::broadcast;
+
+/// Number of events buffered per subscriber before it starts lagging
+const BUS_CAPACITY: usize = 256;
+
+/// Number of recent events kept for replay
+const REPLAY_CAPACITY: usize = 100;
+
+/// How often coalesced progress is published, i.e. at most 10 updates per
+/// second for each transfer
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Progress of a running transfer
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferProgress {
+    pub transfer_id: String,
+    pub bytes_transferred: u64,
+    pub total_bytes: u64,
+    pub current_file: Option<String>,
+    pub speed_bps: u64,
+}
+
+/// Event published on the bus and forwarded to the frontend
+///
+/// The serialized form is the payload of the frontend event named by
+/// [`AppEvent::name`], and the body of webhook deliveries.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AppEvent {
+    /// Incoming transfer, after screening
+    #[serde(rename_all = "camelCase")]
+    TransferRequest {
+        transfer: PendingTransfer,
+        /// Receive limit that held the transfer for approval
+        held_reason: Option<String>,
+        /// Accepted automatically; the user is not asked
+        auto_accepted: bool,
+    },
+    /// Incoming transfer rejected by the receive filter or limits
+    #[serde(rename_all = "camelCase")]
+    TransferRejected {
+        transfer_id: String,
+        reason: String,
+    },
+    #[serde(rename = "progress")]
+    TransferProgress {
+        progress: TransferProgress,
+    },
+    #[serde(rename_all = "camelCase")]
+    TransferComplete {
+        transfer_id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    TransferFailed {
+        transfer_id: String,
+        error: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    TransferRetry {
+        transfer_id: String,
+        attempt: u32,
+        max_attempts: u32,
+        error: String,
+    },
+    ServerStarted {
+        port: u16,
+    },
+    ServerStopped,
+    #[serde(rename_all = "camelCase")]
+    PortChanged {
+        old_port: u16,
+        new_port: u16,
+    },
+    /// Received files moved to quarantine
+    #[serde(rename_all = "camelCase")]
+    FilesQuarantined {
+        transfer_id: String,
+        files: Vec<QuarantinedFile>,
+    },
+    /// Received files waiting for the user to resolve a name conflict
+    #[serde(rename_all = "camelCase")]
+    FileConflicts {
+        transfer_id: String,
+        conflicts: Vec<FileConflict>,
+    },
+    /// Text message received; `copied` if it was put on the clipboard
+    #[serde(rename_all = "camelCase")]
+    TextReceived {
+        transfer_id: String,
+        text: String,
+        copied: bool,
+    },
+    /// Events were dropped because a consumer fell behind; state should be
+    /// reloaded from `get_active_transfers` and `get_pending_transfers`
+    EventsLagged {
+        skipped: u64,
+    },
+}
+
+impl AppEvent {
+    /// Name of the frontend event
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TransferRequest { .. } => "transfer-request",
+            Self::TransferRejected { .. } => "transfer-rejected",
+            Self::TransferProgress { .. } => "transfer-progress",
+            Self::TransferComplete { .. } => "transfer-complete",
+            Self::TransferFailed { .. } => "transfer-failed",
+            Self::TransferRetry { .. } => "transfer-retry",
+            Self::ServerStarted { .. } => "server-started",
+            Self::ServerStopped => "server-stopped",
+            Self::PortChanged { .. } => "port-changed",
+            Self::FilesQuarantined { .. } => "files-quarantined",
+            Self::FileConflicts { .. } => "file-conflicts",
+            Self::TextReceived { .. } => "text-received",
+            Self::EventsLagged { .. } => "events-lagged",
+        }
+    }
+
+    /// Transfer the event belongs to, if any
+    pub fn transfer_id(&self) -> Option<&str> {
+        match self {
+            Self::TransferRequest { transfer, .. } => Some(&transfer.id),
+            Self::TransferProgress { progress } => Some(&progress.transfer_id),
+            Self::TransferRejected { transfer_id, .. }
+            | Self::TransferComplete { transfer_id }
+            | Self::TransferFailed { transfer_id, .. }
+            | Self::TransferRetry { transfer_id, .. }
+            | Self::FilesQuarantined { transfer_id, .. }
+            | Self::FileConflicts { transfer_id, .. }
+            | Self::TextReceived { transfer_id, .. } => Some(transfer_id),
+            Self::ServerStarted { .. }
+            | Self::ServerStopped
+            | Self::PortChanged { .. }
+            | Self::EventsLagged { .. } => None,
+        }
+    }
+}
+
+/// Translate an engine event
+///
+/// Transfer requests are returned unscreened (`held_reason: None`); the
+/// receive pipeline publishes them once screening is done.
+pub fn from_engine(event: &EngineEvent) -> AppEvent {
+    match event {
+        EngineEvent::TransferRequest(transfer) => AppEvent::TransferRequest {
+            transfer: pending_from_engine(transfer),
+            held_reason: None,
+            auto_accepted: false,
+        },
+        EngineEvent::TransferProgress(progress) => AppEvent::TransferProgress {
+            progress: TransferProgress {
+                transfer_id: progress.transfer_id.clone(),
+                bytes_transferred: progress.bytes_transferred,
+                total_bytes: progress.total_bytes,
+                current_file: progress.current_file.clone(),
+                speed_bps: progress.speed_bps,
+            },
+        },
+        EngineEvent::TransferComplete { transfer_id } => AppEvent::TransferComplete {
+            transfer_id: transfer_id.clone(),
+        },
+        EngineEvent::TransferFailed { transfer_id, error } => AppEvent::TransferFailed {
+            transfer_id: transfer_id.clone(),
+            error: error.to_string(),
+        },
+        EngineEvent::ServerStarted { port } => AppEvent::ServerStarted { port: *port },
+        EngineEvent::ServerStopped => AppEvent::ServerStopped,
+        EngineEvent::TransferRetry {
+            transfer_id,
+            attempt,
+            max_attempts,
+            error,
+        } => AppEvent::TransferRetry {
+            transfer_id: transfer_id.clone(),
+            attempt: *attempt,
+            max_attempts: *max_attempts,
+            error: error.to_string(),
+        },
+        EngineEvent::PortChanged { old_port, new_port } => AppEvent::PortChanged {
+            old_port: *old_port,
+            new_port: *new_port,
+        },
+    }
+}
+
+/// A published event kept for replay
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordedEvent {
+    /// Increases by one with every published event, progress included
+    pub seq: u64,
+    pub at: DateTime<Utc>,
+    /// Frontend event name, e.g. `transfer-complete`
+    pub name: &'static str,
+    pub event: AppEvent,
+}
+
+/// Counts of published, coalesced and dropped events
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EventStats {
+    pub published: u64,
+    /// Progress updates replaced by a newer one before being published
+    pub coalesced: u64,
+    /// Events missed, by consumer (`engine` for the engine event loop)
+    pub dropped: HashMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Counters {
+    published: AtomicU64,
+    coalesced: AtomicU64,
+    dropped: Mutex<HashMap<&'static str, u64>>,
+}
+
+impl Counters {
+    fn record_dropped(&self, consumer: &'static str, skipped: u64) {
+        *self.dropped.lock().unwrap().entry(consumer).or_default() += skipped;
+    }
+}
+
+/// Convert an engine-side pending transfer
+///
+/// A text message is recognized by the name of its single file.
+pub fn pending_from_engine(transfer: &gosh_lan_transfer::PendingTransfer) -> PendingTransfer {
+    let files: Vec<TransferFile> = transfer
+        .files
+        .iter()
+        .map(|f| TransferFile {
+            mime_type: f.mime_type.clone(),
+            ..TransferFile::new(&f.id, &f.name, f.size)
+        })
+        .collect();
+    let text_preview = text::preview(&files);
+
+    PendingTransfer {
+        id: transfer.id.clone(),
+        source_ip: transfer.source_ip.clone(),
+        sender_name: transfer.sender_name.clone(),
+        files,
+        total_size: transfer.total_size,
+        received_at: transfer.received_at,
+        kind: match text_preview {
+            Some(_) => TransferKind::Text,
+            None => TransferKind::Files,
+        },
+        text_preview,
+    }
+}
+
+/// Fan-out bus delivering every published event to every subscriber
+pub struct EventBus {
+    sender: broadcast::Sender<AppEvent>,
+    recent: Mutex<Recent>,
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Recent {
+    next_seq: u64,
+    events: VecDeque<RecordedEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(BUS_CAPACITY);
+        Self {
+            sender,
+            recent: Mutex::new(Recent::default()),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Publish an event to all current subscribers
+    pub fn publish(&self, event: AppEvent) {
+        // Held while sending so replay and live delivery never overlap
+        let mut recent = self.recent.lock().unwrap();
+        let seq = recent.next_seq;
+        recent.next_seq += 1;
+        self.counters.published.fetch_add(1, Ordering::Relaxed);
+
+        // Progress is superseded by the next update; the tracker has the latest
+        if !matches!(event, AppEvent::TransferProgress { .. }) {
+            recent.events.push_back(RecordedEvent {
+                seq,
+                at: Utc::now(),
+                name: event.name(),
+                event: event.clone(),
+            });
+            while recent.events.len() > REPLAY_CAPACITY {
+                recent.events.pop_front();
+            }
+        }
+
+        // Sending only fails when nobody is subscribed yet
+        let _ = self.sender.send(event);
+    }
+
+    /// Receive every event published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<AppEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Recent events, oldest first, followed by every event published from now on
+    pub fn subscribe_with_replay(&self) -> (Vec<RecordedEvent>, broadcast::Receiver<AppEvent>) {
+        let recent = self.recent.lock().unwrap();
+        (
+            recent.events.iter().cloned().collect(),
+            self.sender.subscribe(),
+        )
+    }
+
+    /// Recent events published after sequence number `after`, oldest first
+    pub fn recent(&self, after: Option<u64>) -> Vec<RecordedEvent> {
+        self.recent
+            .lock()
+            .unwrap()
+            .events
+            .iter()
+            .filter(|e| after.is_none_or(|after| e.seq > after))
+            .cloned()
+            .collect()
+    }
+
+    /// Count a progress update replaced before it was published
+    pub fn record_coalesced(&self) {
+        self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count events a consumer missed
+    pub fn record_dropped(&self, consumer: &'static str, skipped: u64) {
+        self.counters.record_dropped(consumer, skipped);
+    }
+
+    pub fn stats(&self) -> EventStats {
+        EventStats {
+            published: self.counters.published.load(Ordering::Relaxed),
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+            dropped: self
+                .counters
+                .dropped
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(consumer, count)| (consumer.to_string(), *count))
+                .collect(),
+        }
+    }
+
+    /// Run `handler` for every event published from now on, in order
+    ///
+    /// If the subscriber falls behind, the missed events are counted and
+    /// `handler` receives an `EventsLagged` event in their place.
+    pub fn spawn_subscriber<F, Fut>(&self, name: &'static str, mut handler: F)
+    where
+        F: FnMut(AppEvent) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let mut rx = self.subscribe();
+        let counters = self.counters.clone();
+        tauri::async_runtime::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(event) => handler(event).await,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Event subscriber '{}' skipped {} events", name, skipped);
+                        counters.record_dropped(name, skipped);
+                        handler(AppEvent::EventsLagged { skipped }).await;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+}
+
+/// Latest progress per transfer, waiting to be published
+///
+/// Updates arriving faster than [`PROGRESS_INTERVAL`] replace each other, so
+/// each transfer publishes at most one update per interval.
+#[derive(Default)]
+pub struct ProgressCoalescer {
+    pending: HashMap<String, TransferProgress>,
+}
+
+impl ProgressCoalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold an update; returns true if it replaced an unpublished one
+    pub fn push(&mut self, progress: TransferProgress) -> bool {
+        self.pending
+            .insert(progress.transfer_id.clone(), progress)
+            .is_some()
+    }
+
+    /// Take the held update of one transfer, e.g. before its completion is
+    /// published so the final progress is not reordered after it
+    pub fn take(&mut self, transfer_id: &str) -> Option<AppEvent> {
+        self.pending
+            .remove(transfer_id)
+            .map(|progress| AppEvent::TransferProgress { progress })
+    }
+
+    /// Take every held update
+    pub fn drain(&mut self) -> Vec<AppEvent> {
+        self.pending
+            .drain()
+            .map(|(_, progress)| AppEvent::TransferProgress { progress })
+            .collect()
+    }
+}
+
+/// Forward an event to the webview
+pub fn emit_to_webview(app_handle: &AppHandle, event: &AppEvent) {
+    if let AppEvent::TransferRequest {
+        auto_accepted: true,
+        ..
+    } = event
+    {
+        return;
+    }
+
+    if let Err(e) = app_handle.emit(event.name(), event) {
+        tracing::warn!("Failed to emit event: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    fn payload(event: &EngineEvent) -> (&'static str, serde_json::Value) {
+        let event = from_engine(event);
+        (event.name(), serde_json::to_value(&event).unwrap())
+    }
+
+    #[test]
+    fn test_transfer_event_mapping() {
+        assert_eq!(
+            payload(&EngineEvent::TransferComplete {
+                transfer_id: "t1".into()
+            }),
+            (
+                "transfer-complete",
+                json!({"type": "transferComplete", "transferId": "t1"})
+            )
+        );
+        assert_eq!(
+            payload(&EngineEvent::TransferFailed {
+                transfer_id: "t1".into(),
+                error: "disk full".into()
+            }),
+            (
+                "transfer-failed",
+                json!({"type": "transferFailed", "transferId": "t1", "error": "disk full"})
+            )
+        );
+        assert_eq!(
+            payload(&EngineEvent::TransferRetry {
+                transfer_id: "t1".into(),
+                attempt: 2,
+                max_attempts: 3,
+                error: "timeout".into()
+            }),
+            (
+                "transfer-retry",
+                json!({
+                    "type": "transferRetry",
+                    "transferId": "t1",
+                    "attempt": 2,
+                    "maxAttempts": 3,
+                    "error": "timeout"
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_server_event_mapping() {
+        assert_eq!(
+            payload(&EngineEvent::ServerStarted { port: 53317 }),
+            (
+                "server-started",
+                json!({"type": "serverStarted", "port": 53317})
+            )
+        );
+        assert_eq!(
+            payload(&EngineEvent::ServerStopped),
+            ("server-stopped", json!({"type": "serverStopped"}))
+        );
+        assert_eq!(
+            payload(&EngineEvent::PortChanged {
+                old_port: 53317,
+                new_port: 53318
+            }),
+            (
+                "port-changed",
+                json!({"type": "portChanged", "oldPort": 53317, "newPort": 53318})
+            )
+        );
+    }
+
+    #[test]
+    fn test_progress_event_mapping() {
+        let event = from_engine(&EngineEvent::TransferProgress(
+            gosh_lan_transfer::TransferProgress {
+                transfer_id: "t1".into(),
+                bytes_transferred: 10,
+                total_bytes: 20,
+                current_file: Some("a.txt".into()),
+                speed_bps: 5,
+            },
+        ));
+        assert_eq!(
+            event,
+            AppEvent::TransferProgress {
+                progress: TransferProgress {
+                    transfer_id: "t1".into(),
+                    bytes_transferred: 10,
+                    total_bytes: 20,
+                    current_file: Some("a.txt".into()),
+                    speed_bps: 5,
+                },
+            }
+        );
+        assert_eq!(event.name(), "transfer-progress");
+        assert_eq!(event.transfer_id(), Some("t1"));
+    }
+
+    #[test]
+    fn test_request_event_mapping() {
+        let received_at = Utc::now();
+        let event = from_engine(&EngineEvent::TransferRequest(
+            gosh_lan_transfer::PendingTransfer {
+                id: "t2".into(),
+                source_ip: "10.0.0.5".into(),
+                sender_name: Some("laptop".into()),
+                files: vec![gosh_lan_transfer::TransferFile {
+                    id: "f1".into(),
+                    name: "report.pdf".into(),
+                    size: 42,
+                    mime_type: Some("application/pdf".into()),
+                }],
+                total_size: 42,
+                received_at,
+            },
+        ));
+        let transfer = match &event {
+            AppEvent::TransferRequest {
+                transfer,
+                held_reason: None,
+                auto_accepted: false,
+            } => transfer,
+            other => panic!("unexpected event {:?}", other),
+        };
+        assert_eq!(event.name(), "transfer-request");
+        assert_eq!(transfer.id, "t2");
+        assert_eq!(transfer.source_ip, "10.0.0.5");
+        assert_eq!(transfer.sender_name.as_deref(), Some("laptop"));
+        assert_eq!(transfer.total_size, 42);
+        assert_eq!(transfer.received_at, received_at);
+        assert_eq!(transfer.kind, TransferKind::Files);
+        assert_eq!(transfer.files.len(), 1);
+        assert_eq!(transfer.files[0].id, "f1");
+        assert_eq!(transfer.files[0].name, "report.pdf");
+        assert_eq!(transfer.files[0].size, 42);
+        assert_eq!(
+            transfer.files[0].mime_type.as_deref(),
+            Some("application/pdf")
+        );
+    }
+
+    #[test]
+    fn test_app_event_payloads() {
+        let progress = AppEvent::TransferProgress {
+            progress: TransferProgress {
+                transfer_id: "t1".into(),
+                bytes_transferred: 10,
+                total_bytes: 20,
+                current_file: Some("a.txt".into()),
+                speed_bps: 5,
+            },
+        };
+        assert_eq!(progress.name(), "transfer-progress");
+        assert_eq!(
+            serde_json::to_value(&progress).unwrap(),
+            json!({
+                "type": "progress",
+                "progress": {
+                    "transferId": "t1",
+                    "bytesTransferred": 10,
+                    "totalBytes": 20,
+                    "currentFile": "a.txt",
+                    "speedBps": 5
+                }
+            })
+        );
+
+        let request = AppEvent::TransferRequest {
+            transfer: PendingTransfer {
+                id: "t2".into(),
+                source_ip: "10.0.0.5".into(),
+                sender_name: None,
+                files: vec![],
+                total_size: 0,
+                received_at: Utc::now(),
+                kind: TransferKind::Files,
+                text_preview: None,
+            },
+            held_reason: Some("Too large".into()),
+            auto_accepted: true,
+        };
+        let value = serde_json::to_value(&request).unwrap();
+        assert_eq!(request.transfer_id(), Some("t2"));
+        assert_eq!(value["type"], "transferRequest");
+        assert_eq!(value["transfer"]["sourceIp"], "10.0.0.5");
+        assert_eq!(value["heldReason"], "Too large");
+        assert_eq!(value["autoAccepted"], true);
+
+        let rejected = AppEvent::TransferRejected {
+            transfer_id: "t3".into(),
+            reason: "Blocked file type: setup.exe".into(),
+        };
+        assert_eq!(rejected.name(), "transfer-rejected");
+        assert_eq!(
+            serde_json::to_value(&rejected).unwrap(),
+            json!({
+                "type": "transferRejected",
+                "transferId": "t3",
+                "reason": "Blocked file type: setup.exe"
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bus_fans_out() {
+        let bus = EventBus::new();
+        let mut webview = bus.subscribe();
+        let mut notifier = bus.subscribe();
+
+        bus.publish(AppEvent::ServerStopped);
+        bus.publish(AppEvent::ServerStarted { port: 1 });
+
+        for rx in [&mut webview, &mut notifier] {
+            assert_eq!(rx.recv().await.unwrap(), AppEvent::ServerStopped);
+            assert_eq!(
+                rx.recv().await.unwrap(),
+                AppEvent::ServerStarted { port: 1 }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bus_replays_recent_events() {
+        let bus = EventBus::new();
+        bus.publish(AppEvent::ServerStarted { port: 53317 });
+        bus.publish(AppEvent::TransferProgress {
+            progress: TransferProgress {
+                transfer_id: "t1".into(),
+                bytes_transferred: 1,
+                total_bytes: 2,
+                current_file: None,
+                speed_bps: 1,
+            },
+        });
+        bus.publish(AppEvent::TransferComplete {
+            transfer_id: "t1".into(),
+        });
+
+        let (replay, mut rx) = bus.subscribe_with_replay();
+        let names: Vec<_> = replay.iter().map(|e| (e.seq, e.name)).collect();
+        assert_eq!(names, [(0, "server-started"), (2, "transfer-complete")]);
+        assert_eq!(bus.recent(Some(0)).len(), 1);
+
+        bus.publish(AppEvent::ServerStopped);
+        assert_eq!(rx.recv().await.unwrap(), AppEvent::ServerStopped);
+    }
+
+    #[test]
+    fn test_progress_coalescing() {
+        let update = |id: &str, bytes| TransferProgress {
+            transfer_id: id.into(),
+            bytes_transferred: bytes,
+            total_bytes: 100,
+            current_file: None,
+            speed_bps: 1,
+        };
+        let mut coalescer = ProgressCoalescer::new();
+        assert!(!coalescer.push(update("t1", 10)));
+        assert!(coalescer.push(update("t1", 20)));
+        assert!(!coalescer.push(update("t2", 5)));
+
+        assert_eq!(
+            coalescer.take("t1"),
+            Some(AppEvent::TransferProgress {
+                progress: update("t1", 20)
+            })
+        );
+        assert_eq!(coalescer.take("t1"), None);
+        assert_eq!(coalescer.drain().len(), 1);
+        assert!(coalescer.drain().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_lagging_subscriber_is_told() {
+        let bus = EventBus::new();
+        let mut rx = bus.subscribe();
+        for _ in 0..BUS_CAPACITY + 3 {
+            bus.publish(AppEvent::ServerStopped);
+        }
+
+        // Mirrors the recovery in `spawn_subscriber`
+        match rx.recv().await {
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                assert_eq!(skipped, 3);
+                bus.record_dropped("test", skipped);
+            }
+            other => panic!("expected lag, got {:?}", other),
+        }
+        assert_eq!(rx.recv().await.unwrap(), AppEvent::ServerStopped);
+
+        let stats = bus.stats();
+        assert_eq!(stats.published, BUS_CAPACITY as u64 + 3);
+        assert_eq!(stats.dropped.get("test"), Some(&3));
+    }
+}
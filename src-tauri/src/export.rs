@@ -0,0 +1,373 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - History export and import
+//
+// History is exported with one row per file (one row with empty file columns
+// for transfers without files), in CSV or JSON Lines. Both formats use the
+// same columns, so either can be imported again: rows are grouped back into
+// records by transfer ID. Details such as routes and hook output are not
+// exported.
+
+use crate::history::HistoryStore;
+use crate::types::{
+    AppError, ExportFormat, HistoryQuery, SortOrder, TransferDirection, TransferFile,
+    TransferRecord, TransferStatus,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// CSV header, in the order of the fields of `ExportRow`
+const CSV_COLUMNS: [&str; 15] = [
+    "transferId",
+    "direction",
+    "status",
+    "peerAddress",
+    "peerName",
+    "fileId",
+    "fileName",
+    "fileSize",
+    "mimeType",
+    "sha256",
+    "totalSize",
+    "bytesTransferred",
+    "startedAt",
+    "completedAt",
+    "error",
+];
+
+/// One exported row: a file and the transfer it belongs to
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRow {
+    pub transfer_id: String,
+    pub direction: TransferDirection,
+    pub status: TransferStatus,
+    pub peer_address: String,
+    pub peer_name: Option<String>,
+    pub file_id: Option<String>,
+    pub file_name: Option<String>,
+    pub file_size: Option<u64>,
+    pub mime_type: Option<String>,
+    pub sha256: Option<String>,
+    pub total_size: u64,
+    pub bytes_transferred: u64,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub error: Option<String>,
+}
+
+/// Split records into export rows
+pub fn to_rows(records: &[TransferRecord]) -> Vec<ExportRow> {
+    let mut rows = Vec::new();
+    for record in records {
+        let row = |file: Option<&TransferFile>| ExportRow {
+            transfer_id: record.id.clone(),
+            direction: record.direction,
+            status: record.status.clone(),
+            peer_address: record.peer_address.clone(),
+            peer_name: record.peer_name.clone(),
+            file_id: file.map(|f| f.id.clone()),
+            file_name: file.map(|f| f.name.clone()),
+            file_size: file.map(|f| f.size),
+            mime_type: file.and_then(|f| f.mime_type.clone()),
+            sha256: file.and_then(|f| f.sha256.clone()),
+            total_size: record.total_size,
+            bytes_transferred: record.bytes_transferred,
+            started_at: record.started_at,
+            completed_at: record.completed_at,
+            error: record.error.clone(),
+        };
+
+        if record.files.is_empty() {
+            rows.push(row(None));
+        } else {
+            rows.extend(record.files.iter().map(Some).map(row));
+        }
+    }
+    rows
+}
+
+/// Group rows back into records, in the order their first row appears
+pub fn from_rows(rows: Vec<ExportRow>) -> Vec<TransferRecord> {
+    let mut records: Vec<TransferRecord> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+
+    for row in rows {
+        let position = *index.entry(row.transfer_id.clone()).or_insert_with(|| {
+            let mut record = TransferRecord::outgoing(&row.peer_address, Vec::new());
+            record.id = row.transfer_id.clone();
+            record.direction = row.direction;
+            record.status = row.status.clone();
+            record.peer_name = row.peer_name.clone();
+            record.total_size = row.total_size;
+            record.bytes_transferred = row.bytes_transferred;
+            record.started_at = row.started_at;
+            record.completed_at = row.completed_at;
+            record.error = row.error.clone();
+            records.push(record);
+            records.len() - 1
+        });
+
+        if let Some(name) = row.file_name {
+            let id = row
+                .file_id
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            records[position].files.push(TransferFile {
+                mime_type: row.mime_type,
+                sha256: row.sha256,
+                ..TransferFile::new(id, name, row.file_size.unwrap_or(0))
+            });
+        }
+    }
+
+    records
+}
+
+/// Write records to `writer`, returning the number of rows written
+pub fn export(
+    records: &[TransferRecord],
+    format: ExportFormat,
+    mut writer: impl Write,
+) -> Result<usize, AppError> {
+    let rows = to_rows(records);
+    let io_error = |e: std::io::Error| AppError::FileIo(format!("Failed to write export: {}", e));
+
+    match format {
+        ExportFormat::Jsonl => {
+            for row in &rows {
+                serde_json::to_writer(&mut writer, row).map_err(|e| {
+                    AppError::Serialization(format!("Failed to serialize history: {}", e))
+                })?;
+                writer.write_all(b"\n").map_err(io_error)?;
+            }
+        }
+        ExportFormat::Csv => {
+            writeln!(writer, "{}", CSV_COLUMNS.join(",")).map_err(io_error)?;
+            for row in &rows {
+                let fields = csv_fields(row)?;
+                let line: Vec<String> = fields.iter().map(|f| csv_escape(f)).collect();
+                writeln!(writer, "{}", line.join(",")).map_err(io_error)?;
+            }
+        }
+    }
+
+    writer.flush().map_err(io_error)?;
+    Ok(rows.len())
+}
+
+/// Read records written by [`export`]
+pub fn import(format: ExportFormat, reader: impl BufRead) -> Result<Vec<TransferRecord>, AppError> {
+    let parse_error = |line: usize, e: String| {
+        AppError::Serialization(format!("Invalid history row on line {}: {}", line, e))
+    };
+
+    let mut rows = Vec::new();
+    match format {
+        ExportFormat::Jsonl => {
+            for (number, line) in reader.lines().enumerate() {
+                let line =
+                    line.map_err(|e| AppError::FileIo(format!("Failed to read import: {}", e)))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let row = serde_json::from_str(&line)
+                    .map_err(|e| parse_error(number + 1, e.to_string()))?;
+                rows.push(row);
+            }
+        }
+        ExportFormat::Csv => {
+            let mut text = String::new();
+            let mut reader = reader;
+            reader
+                .read_to_string(&mut text)
+                .map_err(|e| AppError::FileIo(format!("Failed to read import: {}", e)))?;
+
+            let mut records = parse_csv(&text).into_iter();
+            let header = records
+                .next()
+                .ok_or_else(|| parse_error(1, "missing header".to_string()))?;
+
+            for (number, fields) in records.enumerate() {
+                // Rebuild the row as JSON so both formats share one decoder
+                let object: serde_json::Map<String, serde_json::Value> = header
+                    .iter()
+                    .zip(fields)
+                    .filter(|(_, value)| !value.is_empty())
+                    .map(|(column, value)| (column.clone(), csv_value(column, value)))
+                    .collect();
+                let row = serde_json::from_value(serde_json::Value::Object(object))
+                    .map_err(|e| parse_error(number + 2, e.to_string()))?;
+                rows.push(row);
+            }
+        }
+    }
+
+    Ok(from_rows(rows))
+}
+
+/// Resolve the format to use for `path`
+fn format_for(path: &Path, format: Option<ExportFormat>) -> Result<ExportFormat, AppError> {
+    format
+        .or_else(|| ExportFormat::from_path(path))
+        .ok_or_else(|| {
+            AppError::InvalidConfig(format!(
+                "Cannot tell the format of {:?}; use a .csv or .jsonl file",
+                path
+            ))
+        })
+}
+
+/// Export history started within `[from, to)` to a file, oldest first
+///
+/// The format is taken from the file extension unless given. Returns the
+/// number of rows written.
+pub fn export_to_file(
+    history: &HistoryStore,
+    path: &Path,
+    format: Option<ExportFormat>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<usize, AppError> {
+    let format = format_for(path, format)?;
+    let records = history
+        .query(&HistoryQuery {
+            from,
+            to,
+            order: SortOrder::Asc,
+            ..Default::default()
+        })
+        .records;
+
+    let file = fs::File::create(path)
+        .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", path, e)))?;
+    export(&records, format, BufWriter::new(file))
+}
+
+/// Import history from a file, skipping transfers already in history
+///
+/// Returns the number of records added.
+pub fn import_from_file(
+    history: &HistoryStore,
+    path: &Path,
+    format: Option<ExportFormat>,
+) -> Result<usize, AppError> {
+    let format = format_for(path, format)?;
+    let file = fs::File::open(path)
+        .map_err(|e| AppError::FileIo(format!("Failed to open {:?}: {}", path, e)))?;
+    history.import(import(format, BufReader::new(file))?)
+}
+
+/// Field values of a row, in `CSV_COLUMNS` order
+fn csv_fields(row: &ExportRow) -> Result<Vec<String>, AppError> {
+    let value = serde_json::to_value(row)
+        .map_err(|e| AppError::Serialization(format!("Failed to serialize history: {}", e)))?;
+
+    Ok(CSV_COLUMNS
+        .iter()
+        .map(|column| match &value[*column] {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        })
+        .collect())
+}
+
+/// JSON value of a CSV field, numbers for the size columns
+fn csv_value(column: &str, value: String) -> serde_json::Value {
+    match column {
+        "fileSize" | "totalSize" | "bytesTransferred" => value
+            .parse::<u64>()
+            .map(serde_json::Value::from)
+            .unwrap_or(serde_json::Value::String(value)),
+        _ => serde_json::Value::String(value),
+    }
+}
+
+/// Quote a field if it contains a separator, quote or line break
+fn csv_escape(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Split CSV text into records of fields (RFC 4180 quoting)
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|f| !f.is_empty()) {
+                    records.push(std::mem::take(&mut record));
+                }
+                record.clear();
+            }
+            (c, _) => field.push(c),
+        }
+    }
+
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_export_import_round_trip() {
+        let mut sent = TransferRecord::outgoing(
+            "10.0.0.2",
+            vec![
+                TransferFile::new("f1", "report, final.pdf", 100),
+                TransferFile {
+                    mime_type: Some("text/plain".to_string()),
+                    sha256: Some("ab12".to_string()),
+                    ..TransferFile::new("f2", "notes \"v2\".txt", 20)
+                },
+            ],
+        );
+        sent.status = TransferStatus::Failed;
+        sent.error = Some("Connection reset\nby peer".to_string());
+        let mut empty = TransferRecord::outgoing("10.0.0.3", Vec::new());
+        empty.direction = TransferDirection::Received;
+        empty.peer_name = Some("Laptop".to_string());
+        let records = vec![sent, empty];
+
+        for format in [ExportFormat::Csv, ExportFormat::Jsonl] {
+            let mut out = Vec::new();
+            assert_eq!(export(&records, format, &mut out).unwrap(), 3);
+
+            let imported = import(format, Cursor::new(out)).unwrap();
+            assert_eq!(imported.len(), 2);
+            assert_eq!(imported[0].files, records[0].files);
+            assert_eq!(imported[0].error, records[0].error);
+            assert_eq!(imported[0].status, TransferStatus::Failed);
+            assert_eq!(imported[0].started_at, records[0].started_at);
+            assert!(imported[1].files.is_empty());
+            assert_eq!(imported[1].peer_name.as_deref(), Some("Laptop"));
+        }
+    }
+}
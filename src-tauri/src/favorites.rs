@@ -98,6 +98,7 @@ impl FavoritesStore {
         name: Option<String>,
         address: Option<String>,
         last_resolved_ip: Option<String>,
+        compress: Option<bool>,
     ) -> Result<Favorite, AppError> {
         let updated = {
             let mut favorites = self.favorites.write().unwrap();
@@ -115,6 +116,9 @@ impl FavoritesStore {
             if let Some(ip) = last_resolved_ip {
                 favorite.last_resolved_ip = Some(ip);
             }
+            if let Some(compress) = compress {
+                favorite.compress = compress;
+            }
             favorite.last_used = Some(chrono::Utc::now());
 
             favorite.clone()
@@ -140,6 +144,26 @@ impl FavoritesStore {
         Ok(())
     }
 
+    /// Delete the favorites for a peer, matched by address or last resolved IP
+    ///
+    /// Returns the deleted favorites.
+    pub fn delete_by_address(&self, address: &str) -> Result<Vec<Favorite>, AppError> {
+        let removed = {
+            let mut favorites = self.favorites.write().unwrap();
+            let (removed, kept) = favorites.drain(..).partition(|f: &Favorite| {
+                f.address.eq_ignore_ascii_case(address)
+                    || f.last_resolved_ip.as_deref() == Some(address)
+            });
+            *favorites = kept;
+            removed
+        };
+
+        if !removed.is_empty() {
+            self.persist()?;
+        }
+        Ok(removed)
+    }
+
     /// Get a favorite by ID
     pub fn get(&self, id: &str) -> Option<Favorite> {
         self.favorites
@@ -169,7 +193,6 @@ impl FavoritesStore {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env;
 
     #[test]
     fn test_favorite_creation() {
@@ -0,0 +1,158 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Receive filter and quarantine
+//
+// Incoming files are matched by extension and MIME type. Depending on the
+// configured action, matching transfers are rejected up front or the matching
+// files are moved to a quarantine directory (without execute permission)
+// once the transfer has completed.
+
+use crate::compress;
+use crate::conflict;
+use crate::types::{AppError, ReceiveFilter, TransferFile};
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+/// Check a file name and MIME type against the filter, returning the reason
+/// if it matches
+pub fn match_file(filter: &ReceiveFilter, name: &str, mime_type: Option<&str>) -> Option<String> {
+    if let Some(ext) = Path::new(name).extension().and_then(|e| e.to_str()) {
+        if filter
+            .extensions
+            .iter()
+            .any(|e| e.eq_ignore_ascii_case(ext))
+        {
+            return Some(format!("extension .{}", ext.to_lowercase()));
+        }
+    }
+
+    if let Some(mime) = mime_type {
+        if mime_matches(&filter.mime_types, mime) {
+            return Some(format!("MIME type {}", mime.to_lowercase()));
+        }
+    }
+
+    None
+}
+
+/// Check a MIME type against a list of types, where entries ending in `/`
+/// (e.g. "video/") match every type of that kind
+pub fn mime_matches(patterns: &[String], mime: &str) -> bool {
+    let mime = mime.to_lowercase();
+    patterns.iter().any(|m| {
+        let m = m.to_lowercase();
+        if m.ends_with('/') {
+            mime.starts_with(&m)
+        } else {
+            mime == m
+        }
+    })
+}
+
+/// Find the first file in a transfer that matches the filter
+///
+/// Files compressed for the transfer are matched by their original name.
+pub fn find_blocked(filter: &ReceiveFilter, files: &[TransferFile]) -> Option<AppError> {
+    files.iter().find_map(|f| {
+        match_file(filter, &f.name, f.mime_type.as_deref())
+            .or_else(|| match_file(filter, compress::original_name(&f.name), None))
+            .map(|reason| AppError::BlockedFileType(format!("{} ({})", f.name, reason)))
+    })
+}
+
+/// Detect executable content from the first bytes of a received file
+///
+/// This catches executables and scripts that were renamed to an innocent
+/// extension.
+pub fn sniff_executable(path: &Path) -> Option<String> {
+    let mut header = [0u8; 4];
+    let mut file = fs::File::open(path).ok()?;
+    let read = file.read(&mut header).ok()?;
+    let header = &header[..read];
+
+    let kind = if header.starts_with(b"\x7fELF") {
+        "ELF executable"
+    } else if header.starts_with(b"MZ") {
+        "Windows executable"
+    } else if header.starts_with(b"#!") {
+        "script"
+    } else if matches!(
+        header,
+        [0xfe, 0xed, 0xfa, 0xce | 0xcf]
+            | [0xce | 0xcf, 0xfa, 0xed, 0xfe]
+            | [0xca, 0xfe, 0xba, 0xbe]
+    ) {
+        "Mach-O executable"
+    } else {
+        return None;
+    };
+
+    Some(format!("{} content", kind))
+}
+
+/// Resolve where a received file was written in the download directory
+///
+/// Names that would escape the download directory are ignored.
+pub fn received_path(download_dir: &Path, name: &str) -> Option<PathBuf> {
+    let relative = Path::new(name);
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(download_dir.join(relative))
+}
+
+/// Default quarantine directory in the app data directory
+pub fn default_quarantine_dir() -> Result<PathBuf, AppError> {
+    directories::ProjectDirs::from("com", "gosh", "transfer")
+        .map(|dirs| dirs.data_local_dir().join("quarantine"))
+        .ok_or_else(|| AppError::FileIo("Could not determine data directory".to_string()))
+}
+
+/// Move a file into the quarantine directory and strip its execute permission
+///
+/// Returns the new path. Existing files in quarantine are never overwritten.
+pub fn quarantine(path: &Path, quarantine_dir: &Path) -> Result<PathBuf, AppError> {
+    fs::create_dir_all(quarantine_dir)
+        .map_err(|e| AppError::FileIo(format!("Failed to create quarantine dir: {}", e)))?;
+
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| AppError::FileIo(format!("Invalid file path: {:?}", path)))?;
+    let target = conflict::unique_path(&quarantine_dir.join(file_name));
+    conflict::move_file(path, &target)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(&target)
+            .map_err(|e| AppError::FileIo(format!("Failed to read permissions: {}", e)))?
+            .permissions();
+        perms.set_mode(perms.mode() & 0o666);
+        fs::set_permissions(&target, perms)
+            .map_err(|e| AppError::FileIo(format!("Failed to set permissions: {}", e)))?;
+    }
+
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_file() {
+        let filter = ReceiveFilter {
+            mime_types: vec!["application/x-sh".to_string(), "video/".to_string()],
+            ..Default::default()
+        };
+
+        assert!(match_file(&filter, "setup.EXE", None).is_some());
+        assert!(match_file(&filter, "run", Some("application/x-sh")).is_some());
+        assert!(match_file(&filter, "clip.bin", Some("video/mp4")).is_some());
+        assert!(match_file(&filter, "photo.jpg", Some("image/jpeg")).is_none());
+        assert!(received_path(Path::new("/dl"), "../etc/passwd").is_none());
+    }
+}
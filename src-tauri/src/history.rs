@@ -1,56 +1,209 @@
 // SPDX-License-Identifier: AGPL-3.0
 // Gosh Transfer - Transfer history persistence
 //
-// Transfer history is stored in a local JSON file with a maximum of 100 entries.
-// Oldest entries are automatically removed when the limit is exceeded.
+// Transfer history is stored as an append-only JSON Lines log: every add or
+// update appends the record, every removal appends a delete marker. The log
+// is replayed on startup and rewritten (compacted) once it holds many more
+// lines than live records, so writes stay cheap with large histories.
+// Records are removed according to the retention settings.
+//
+// Only one process may use the log at a time: the store holds a lock on
+// `history.lock` next to it, so the command line refuses to touch history
+// while the app is running.
 
-use crate::types::{AppError, TransferRecord};
+use crate::outcome;
+use crate::types::{
+    AppError, HistoryPage, HistoryQuery, HistoryRetention, HistorySortField, SortOrder,
+    TransferRecord, TransferStatus,
+};
+use chrono::{DateTime, Duration, Utc};
+use fs4::fs_std::FileExt;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::sync::RwLock;
 
-const MAX_HISTORY_ENTRIES: usize = 100;
+/// Stale log lines tolerated before the log is compacted
+const COMPACT_SLACK: usize = 1000;
+
+/// How often age-based retention is applied while adding records
+const AGE_PRUNE_INTERVAL: Duration = Duration::hours(1);
 
 /// In-memory cache of transfer history, persisted to disk on changes
 pub struct HistoryStore {
-    records: RwLock<Vec<TransferRecord>>,
+    inner: RwLock<Inner>,
     file_path: PathBuf,
+    /// Held for as long as the store is open
+    _lock: fs::File,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Records by insertion sequence, oldest first
+    records: BTreeMap<u64, TransferRecord>,
+    /// Record ID to sequence
+    index: HashMap<String, u64>,
+    next_seq: u64,
+    retention: HistoryRetention,
+    /// Lines currently in the log file
+    log_lines: usize,
+    last_age_prune: Option<DateTime<Utc>>,
+}
+
+/// One line of the history log, as written
+#[derive(Serialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum LogEntry<'a> {
+    Put { record: &'a TransferRecord },
+    Delete { id: &'a str },
+}
+
+/// One line of the history log, as read back
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "camelCase")]
+enum LogLine {
+    Put { record: Box<TransferRecord> },
+    Delete { id: String },
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct HistoryFile {
+/// Pre-log history file format
+#[derive(Deserialize)]
+struct LegacyHistoryFile {
     records: Vec<TransferRecord>,
 }
 
+impl Inner {
+    fn insert(&mut self, record: TransferRecord) {
+        match self.index.get(&record.id) {
+            Some(seq) => {
+                self.records.insert(*seq, record);
+            }
+            None => {
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                self.index.insert(record.id.clone(), seq);
+                self.records.insert(seq, record);
+            }
+        }
+    }
+
+    fn remove(&mut self, id: &str) -> Option<TransferRecord> {
+        let seq = self.index.remove(id)?;
+        self.records.remove(&seq)
+    }
+
+    fn get_mut(&mut self, id: &str) -> Option<&mut TransferRecord> {
+        let seq = self.index.get(id)?;
+        self.records.get_mut(seq)
+    }
+
+    /// IDs of the records the retention settings no longer allow
+    ///
+    /// The age limit is only checked when `check_age` is set, as it needs a
+    /// full pass over the records.
+    fn expired(&self, now: DateTime<Utc>, check_age: bool) -> Vec<String> {
+        let retention = &self.retention;
+        let days = |d: u32| Duration::days(d as i64);
+        let exempt = |r: &TransferRecord| {
+            matches!(
+                r.status,
+                TransferStatus::Pending | TransferStatus::InProgress
+            ) || (r.status == TransferStatus::Failed
+                && retention
+                    .keep_failures_days
+                    .is_some_and(|d| now - r.started_at < days(d)))
+        };
+
+        let mut expired = Vec::new();
+        if let Some(max_age) = retention.max_age_days.filter(|_| check_age) {
+            expired.extend(
+                self.records
+                    .values()
+                    .filter(|r| now - r.started_at >= days(max_age) && !exempt(r))
+                    .map(|r| r.id.clone()),
+            );
+        }
+
+        if let Some(max_entries) = retention.max_entries {
+            let mut excess = (self.records.len() - expired.len()).saturating_sub(max_entries);
+            for record in self.records.values() {
+                if excess == 0 {
+                    break;
+                }
+                if !exempt(record) && !expired.contains(&record.id) {
+                    expired.push(record.id.clone());
+                    excess -= 1;
+                }
+            }
+        }
+
+        expired
+    }
+}
+
 impl HistoryStore {
     /// Create a new history store, loading from disk if available
-    pub fn new() -> Result<Self, AppError> {
+    pub fn new(retention: HistoryRetention) -> Result<Self, AppError> {
         let file_path = Self::get_history_path()?;
         tracing::info!("History file path: {:?}", file_path);
+        Self::open(file_path, retention)
+    }
+
+    /// Open the history log at `file_path`, migrating a `history.json` next
+    /// to it if there is no log yet
+    pub fn open(file_path: PathBuf, retention: HistoryRetention) -> Result<Self, AppError> {
+        let lock = lock(&file_path)?;
+        let mut inner = Inner {
+            retention,
+            ..Default::default()
+        };
+        let legacy_path = file_path.with_file_name("history.json");
 
-        let records = if file_path.exists() {
+        if file_path.exists() {
             tracing::info!("Loading transfer history from disk");
-            let content = fs::read_to_string(&file_path)
+            inner.log_lines = replay(&file_path, &mut inner)?;
+        } else if legacy_path.exists() {
+            tracing::info!("Migrating transfer history from {:?}", legacy_path);
+            let content = fs::read_to_string(&legacy_path)
                 .map_err(|e| AppError::FileIo(format!("Failed to read history: {}", e)))?;
-
-            let file: HistoryFile = serde_json::from_str(&content).unwrap_or_else(|e| {
-                tracing::warn!("Failed to parse history, starting fresh: {}", e);
-                HistoryFile { records: Vec::new() }
-            });
-
-            file.records
+            match serde_json::from_str::<LegacyHistoryFile>(&content) {
+                Ok(file) => file.records.into_iter().for_each(|r| inner.insert(r)),
+                Err(e) => tracing::warn!("Failed to parse history, starting fresh: {}", e),
+            }
         } else {
             tracing::info!("No history file found, starting fresh");
-            Vec::new()
-        };
+        }
 
-        Ok(Self {
-            records: RwLock::new(records),
+        let store = Self {
+            inner: RwLock::new(inner),
             file_path,
-        })
+            _lock: lock,
+        };
+
+        {
+            let mut inner = store.inner.write().unwrap();
+            let expired = inner.expired(Utc::now(), true);
+            for id in &expired {
+                inner.remove(id);
+            }
+            inner.last_age_prune = Some(Utc::now());
+            store.compact(&mut inner)?;
+        }
+
+        if legacy_path.exists() {
+            let backup = legacy_path.with_extension("json.bak");
+            if let Err(e) = fs::rename(&legacy_path, &backup) {
+                tracing::warn!("Failed to move old history file aside: {}", e);
+            }
+        }
+
+        Ok(store)
     }
 
-    /// Get the path to the history file
+    /// Get the path to the history log
     fn get_history_path() -> Result<PathBuf, AppError> {
         let config_dir = directories::ProjectDirs::from("com", "gosh", "transfer")
             .ok_or_else(|| AppError::FileIo("Could not determine config directory".to_string()))?
@@ -61,110 +214,544 @@ impl HistoryStore {
         fs::create_dir_all(&config_dir)
             .map_err(|e| AppError::FileIo(format!("Failed to create config dir: {}", e)))?;
 
-        Ok(config_dir.join("history.json"))
+        Ok(config_dir.join("history.jsonl"))
     }
 
-    /// Persist history to disk
-    fn persist(&self) -> Result<(), AppError> {
-        let records = self.records.read().unwrap();
-        let file = HistoryFile {
-            records: records.clone(),
+    /// Append entries to the log, compacting it when it has grown too large
+    fn append(&self, inner: &mut Inner, entries: &[LogEntry]) -> Result<(), AppError> {
+        let mut buf = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut buf, entry).map_err(|e| {
+                AppError::Serialization(format!("Failed to serialize history: {}", e))
+            })?;
+            buf.push(b'\n');
+        }
+
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.file_path)
+            .and_then(|mut file| file.write_all(&buf))
+            .map_err(|e| AppError::FileIo(format!("Failed to write history: {}", e)))?;
+        inner.log_lines += entries.len();
+
+        if inner.log_lines > inner.records.len() + COMPACT_SLACK {
+            // Entries may be logged before the records change in memory, so
+            // the log itself is what gets compacted
+            self.reload(inner)?;
+            self.compact(inner)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the records in memory with those in the log
+    fn reload(&self, inner: &mut Inner) -> Result<(), AppError> {
+        let mut current = Inner {
+            retention: std::mem::take(&mut inner.retention),
+            last_age_prune: inner.last_age_prune,
+            ..Default::default()
         };
+        current.log_lines = replay(&self.file_path, &mut current)?;
+        *inner = current;
+        Ok(())
+    }
 
-        let content = serde_json::to_string_pretty(&file)
-            .map_err(|e| AppError::Serialization(format!("Failed to serialize history: {}", e)))?;
+    /// Rewrite the log with one line per live record
+    fn compact(&self, inner: &mut Inner) -> Result<(), AppError> {
+        let mut buf = Vec::new();
+        for record in inner.records.values() {
+            let entry = LogEntry::Put { record };
+            serde_json::to_writer(&mut buf, &entry).map_err(|e| {
+                AppError::Serialization(format!("Failed to serialize history: {}", e))
+            })?;
+            buf.push(b'\n');
+        }
 
-        fs::write(&self.file_path, content)
+        // Write to a temporary file first so a crash never loses the log
+        let tmp_path = self.file_path.with_extension("jsonl.tmp");
+        fs::write(&tmp_path, buf)
+            .and_then(|_| fs::rename(&tmp_path, &self.file_path))
             .map_err(|e| AppError::FileIo(format!("Failed to write history: {}", e)))?;
-
+        inner.log_lines = inner.records.len();
         Ok(())
     }
 
-    /// List all transfer records
+    /// Remove records the retention settings no longer allow
+    fn prune(&self, inner: &mut Inner) -> Result<(), AppError> {
+        let now = Utc::now();
+        let check_age = inner
+            .last_age_prune
+            .is_none_or(|last| now - last >= AGE_PRUNE_INTERVAL);
+        if check_age {
+            inner.last_age_prune = Some(now);
+        }
+
+        let expired = inner.expired(now, check_age);
+        if expired.is_empty() {
+            return Ok(());
+        }
+        for id in &expired {
+            inner.remove(id);
+        }
+        let entries: Vec<LogEntry> = expired.iter().map(|id| LogEntry::Delete { id }).collect();
+        self.append(inner, &entries)
+    }
+
+    /// List all transfer records, oldest first
     pub fn list(&self) -> Vec<TransferRecord> {
-        self.records.read().unwrap().clone()
+        self.inner
+            .read()
+            .unwrap()
+            .records
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Number of records kept
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Filter, sort and paginate transfer records
+    pub fn query(&self, query: &HistoryQuery) -> HistoryPage {
+        query_records(self.inner.read().unwrap().records.values(), query)
     }
 
     /// Add a new transfer record
     pub fn add(&self, record: TransferRecord) -> Result<(), AppError> {
-        {
-            let mut records = self.records.write().unwrap();
-            records.push(record);
+        let mut inner = self.inner.write().unwrap();
+        self.append(&mut inner, &[LogEntry::Put { record: &record }])?;
+        inner.insert(record);
+        self.prune(&mut inner)
+    }
 
-            // Enforce max limit by removing oldest entries
-            while records.len() > MAX_HISTORY_ENTRIES {
-                records.remove(0);
-            }
+    /// Add records from another machine or a backup, skipping IDs already
+    /// in history; returns the number of records added
+    pub fn import(&self, mut records: Vec<TransferRecord>) -> Result<usize, AppError> {
+        let mut inner = self.inner.write().unwrap();
+        let mut seen = HashSet::new();
+        records.retain(|r| !inner.index.contains_key(&r.id) && seen.insert(r.id.clone()));
+        records.sort_by_key(|r| r.started_at);
+
+        let entries: Vec<LogEntry> = records
+            .iter()
+            .map(|record| LogEntry::Put { record })
+            .collect();
+        self.append(&mut inner, &entries)?;
+
+        let added = records.len();
+        for record in records {
+            inner.insert(record);
         }
+        self.prune(&mut inner)?;
+        Ok(added)
+    }
 
-        self.persist()
+    /// Change the retention settings, removing records they no longer allow
+    pub fn set_retention(&self, retention: HistoryRetention) -> Result<(), AppError> {
+        let mut inner = self.inner.write().unwrap();
+        if inner.retention == retention {
+            return Ok(());
+        }
+        inner.retention = retention;
+        inner.last_age_prune = None;
+        self.prune(&mut inner)
     }
 
     /// Clear all transfer history
     pub fn clear(&self) -> Result<(), AppError> {
-        {
-            let mut records = self.records.write().unwrap();
-            records.clear();
+        let mut inner = self.inner.write().unwrap();
+        inner.records.clear();
+        inner.index.clear();
+        self.compact(&mut inner)
+    }
+
+    /// Delete records by ID, returning the number deleted
+    pub fn delete(&self, ids: &[String]) -> Result<usize, AppError> {
+        let ids: HashSet<&str> = ids.iter().map(String::as_str).collect();
+        self.delete_where(|record| ids.contains(record.id.as_str()))
+    }
+
+    /// Delete every record matching the filters of `query`
+    ///
+    /// Sorting and pagination are ignored.
+    pub fn delete_matching(&self, query: &HistoryQuery) -> Result<usize, AppError> {
+        self.delete_where(|record| matches(record, query))
+    }
+
+    /// Delete the records `predicate` selects, returning the number deleted
+    pub fn delete_where(
+        &self,
+        predicate: impl Fn(&TransferRecord) -> bool,
+    ) -> Result<usize, AppError> {
+        let mut inner = self.inner.write().unwrap();
+        let ids: Vec<String> = inner
+            .records
+            .values()
+            .filter(|record| predicate(record))
+            .map(|record| record.id.clone())
+            .collect();
+        if ids.is_empty() {
+            return Ok(0);
         }
 
-        self.persist()
+        for id in &ids {
+            inner.remove(id);
+        }
+        let entries: Vec<LogEntry> = ids.iter().map(|id| LogEntry::Delete { id }).collect();
+        self.append(&mut inner, &entries)?;
+        Ok(ids.len())
     }
 
     /// Get a transfer record by ID
     pub fn get(&self, id: &str) -> Option<TransferRecord> {
-        self.records
-            .read()
-            .unwrap()
-            .iter()
-            .find(|r| r.id == id)
-            .cloned()
+        let inner = self.inner.read().unwrap();
+        let seq = inner.index.get(id)?;
+        inner.records.get(seq).cloned()
+    }
+
+    /// Set the status of a record, stamping completion and settling the
+    /// files' outcomes for terminal states
+    pub fn set_status(
+        &self,
+        id: &str,
+        status: TransferStatus,
+        error: Option<String>,
+    ) -> Result<bool, AppError> {
+        self.update(id, |record| {
+            if status == TransferStatus::Completed {
+                record.bytes_transferred = record.total_size;
+            }
+            if !matches!(status, TransferStatus::Pending | TransferStatus::InProgress) {
+                record.completed_at = Some(Utc::now());
+                outcome::finish(record, &status, error.as_deref());
+            }
+            record.status = status;
+            record.error = error;
+        })
     }
 
     /// Update an existing record (e.g., when transfer completes or fails)
-    pub fn update(&self, id: &str, update_fn: impl FnOnce(&mut TransferRecord)) -> Result<bool, AppError> {
-        let updated = {
-            let mut records = self.records.write().unwrap();
-            if let Some(record) = records.iter_mut().find(|r| r.id == id) {
+    pub fn update(
+        &self,
+        id: &str,
+        update_fn: impl FnOnce(&mut TransferRecord),
+    ) -> Result<bool, AppError> {
+        let mut inner = self.inner.write().unwrap();
+        let record = match inner.get_mut(id) {
+            Some(record) => {
                 update_fn(record);
-                true
-            } else {
-                false
+                record.clone()
             }
+            None => return Ok(false),
         };
 
-        if updated {
-            self.persist()?;
+        self.append(&mut inner, &[LogEntry::Put { record: &record }])?;
+        Ok(true)
+    }
+}
+
+/// Lock the history log at `path` for this process
+fn lock(path: &Path) -> Result<fs::File, AppError> {
+    let lock_path = path.with_file_name("history.lock");
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|e| AppError::FileIo(format!("Failed to open {:?}: {}", lock_path, e)))?;
+    match file.try_lock_exclusive() {
+        Ok(true) => Ok(file),
+        Ok(false) => Err(AppError::FileIo(
+            "Transfer history is in use by another Gosh Transfer process; close the app first"
+                .to_string(),
+        )),
+        Err(e) => Err(AppError::FileIo(format!(
+            "Failed to lock {:?}: {}",
+            lock_path, e
+        ))),
+    }
+}
+
+/// Replay a history log into `inner`, returning the number of lines read
+///
+/// Unreadable lines (e.g. a write cut short by a crash) are skipped.
+fn replay(path: &Path, inner: &mut Inner) -> Result<usize, AppError> {
+    let file = fs::File::open(path)
+        .map_err(|e| AppError::FileIo(format!("Failed to read history: {}", e)))?;
+
+    let mut lines = 0;
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| AppError::FileIo(format!("Failed to read history: {}", e)))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        lines += 1;
+        match serde_json::from_str::<LogLine>(&line) {
+            Ok(LogLine::Put { record }) => inner.insert(*record),
+            Ok(LogLine::Delete { id }) => {
+                inner.remove(&id);
+            }
+            Err(e) => tracing::warn!("Skipping history line {}: {}", number + 1, e),
         }
+    }
+    Ok(lines)
+}
+
+/// Run a history query over `records`
+pub fn query_records<'a>(
+    records: impl IntoIterator<Item = &'a TransferRecord>,
+    query: &HistoryQuery,
+) -> HistoryPage {
+    let mut matching: Vec<&TransferRecord> =
+        records.into_iter().filter(|r| matches(r, query)).collect();
+
+    matching.sort_by(|a, b| {
+        let ordering = compare(a, b, query.sort_by).then_with(|| a.started_at.cmp(&b.started_at));
+        match query.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    });
+
+    let total = matching.len();
+    let records = matching
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+
+    HistoryPage {
+        records,
+        total,
+        offset: query.offset,
+    }
+}
 
-        Ok(updated)
+fn matches(record: &TransferRecord, query: &HistoryQuery) -> bool {
+    let contains =
+        |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+
+    query.direction.is_none_or(|d| record.direction == d)
+        && (query.status.is_empty() || query.status.contains(&record.status))
+        && query.peer.as_deref().is_none_or(|peer| {
+            contains(&record.peer_address, peer)
+                || record
+                    .peer_name
+                    .as_deref()
+                    .is_some_and(|name| contains(name, peer))
+        })
+        && query
+            .file_name
+            .as_deref()
+            .is_none_or(|name| record.files.iter().any(|f| contains(&f.name, name)))
+        && query.from.is_none_or(|from| record.started_at >= from)
+        && query.to.is_none_or(|to| record.started_at < to)
+        && query.min_size.is_none_or(|min| record.total_size >= min)
+        && query.max_size.is_none_or(|max| record.total_size <= max)
+}
+
+fn compare(a: &TransferRecord, b: &TransferRecord, field: HistorySortField) -> Ordering {
+    let peer = |r: &TransferRecord| {
+        r.peer_name
+            .as_deref()
+            .unwrap_or(&r.peer_address)
+            .to_lowercase()
+    };
+    let duration = |r: &TransferRecord| r.completed_at.map(|c| c - r.started_at);
+
+    match field {
+        HistorySortField::StartedAt => a.started_at.cmp(&b.started_at),
+        HistorySortField::CompletedAt => a.completed_at.cmp(&b.completed_at),
+        HistorySortField::Direction => (a.direction as u8).cmp(&(b.direction as u8)),
+        HistorySortField::Status => (a.status.clone() as u8).cmp(&(b.status.clone() as u8)),
+        HistorySortField::Peer => peer(a).cmp(&peer(b)),
+        HistorySortField::Size => a.total_size.cmp(&b.total_size),
+        HistorySortField::Files => a.files.len().cmp(&b.files.len()),
+        HistorySortField::Duration => duration(a).cmp(&duration(b)),
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{TransferDirection, TransferStatus};
-    use chrono::Utc;
+    use crate::types::TransferDirection;
 
     fn create_test_record(id: &str) -> TransferRecord {
-        TransferRecord {
-            id: id.to_string(),
-            direction: TransferDirection::Received,
-            status: TransferStatus::Completed,
-            peer_address: "192.168.1.100".to_string(),
-            files: vec![],
-            total_size: 1024,
-            bytes_transferred: 1024,
-            started_at: Utc::now(),
-            completed_at: Some(Utc::now()),
-            error: None,
+        let mut record = TransferRecord::outgoing("192.168.1.100", Vec::new());
+        record.id = id.to_string();
+        record.direction = TransferDirection::Received;
+        record.status = TransferStatus::Completed;
+        record.total_size = 1024;
+        record.bytes_transferred = 1024;
+        record.completed_at = Some(Utc::now());
+        record
+    }
+
+    #[test]
+    fn test_query_records() {
+        let mut records: Vec<TransferRecord> = (0..5)
+            .map(|i| {
+                let mut record = create_test_record(&format!("t{}", i));
+                record.started_at += chrono::Duration::minutes(i);
+                record.total_size = 1000 * i as u64;
+                record
+            })
+            .collect();
+        records[1].direction = TransferDirection::Sent;
+        records[2].status = TransferStatus::Failed;
+        records[3].peer_name = Some("Office-PC".to_string());
+
+        let ids =
+            |page: HistoryPage| -> Vec<String> { page.records.into_iter().map(|r| r.id).collect() };
+
+        // Newest first by default
+        assert_eq!(
+            ids(query_records(&records, &HistoryQuery::default())),
+            ["t4", "t3", "t2", "t1", "t0"]
+        );
+
+        let query = HistoryQuery {
+            direction: Some(TransferDirection::Received),
+            status: vec![TransferStatus::Completed],
+            min_size: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(ids(query_records(&records, &query)), ["t4", "t3"]);
+
+        let query = HistoryQuery {
+            peer: Some("office".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(ids(query_records(&records, &query)), ["t3"]);
+
+        let query = HistoryQuery {
+            sort_by: HistorySortField::Size,
+            order: SortOrder::Asc,
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = query_records(&records, &query);
+        assert_eq!(page.total, 5);
+        assert_eq!(ids(page), ["t1", "t2"]);
+    }
+
+    fn temp_log() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gosh-history-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("history.jsonl")
+    }
+
+    #[test]
+    fn test_log_replay_and_compaction() {
+        let path = temp_log();
+        let store = HistoryStore::open(path.clone(), HistoryRetention::default()).unwrap();
+        store.add(create_test_record("a")).unwrap();
+        store.add(create_test_record("b")).unwrap();
+        store
+            .set_status("a", TransferStatus::Failed, Some("timeout".to_string()))
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert!(HistoryStore::open(path.clone(), HistoryRetention::default()).is_err());
+
+        // Replaying the log restores the latest state, in insertion order
+        drop(store);
+        let reopened = HistoryStore::open(path.clone(), HistoryRetention::default()).unwrap();
+        let records = reopened.list();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].id, "a");
+        assert_eq!(records[0].error.as_deref(), Some("timeout"));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+
+        // A record whose add triggers compaction is kept
+        for _ in 0..COMPACT_SLACK {
+            reopened
+                .update("b", |record| record.bytes_transferred += 1)
+                .unwrap();
         }
+        reopened.add(create_test_record("c")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert_eq!(reopened.len(), 3);
+        drop(reopened);
+        let reopened = HistoryStore::open(path.clone(), HistoryRetention::default()).unwrap();
+        assert!(reopened.get("c").is_some());
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
     }
 
     #[test]
-    fn test_max_history_limit() {
-        // This would require a temp dir for proper testing
-        // Just verify the constant is set correctly
-        assert_eq!(MAX_HISTORY_ENTRIES, 100);
+    fn test_retention() {
+        let path = temp_log();
+        let retention = HistoryRetention {
+            max_entries: Some(2),
+            max_age_days: None,
+            keep_failures_days: Some(30),
+        };
+        let store = HistoryStore::open(path.clone(), retention.clone()).unwrap();
+
+        let mut failed = create_test_record("failed");
+        failed.status = TransferStatus::Failed;
+        store.add(failed).unwrap();
+        for id in ["a", "b", "c"] {
+            store.add(create_test_record(id)).unwrap();
+        }
+        let ids: Vec<String> = store.list().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, ["failed", "c"]);
+
+        let mut old = create_test_record("old");
+        old.started_at -= Duration::days(10);
+        store.add(old).unwrap();
+        store
+            .set_retention(HistoryRetention {
+                max_age_days: Some(7),
+                ..retention
+            })
+            .unwrap();
+        assert!(store.get("old").is_none());
+        assert!(store.get("failed").is_some());
+
+        let len = store.len();
+        drop(store);
+        let reopened = HistoryStore::open(path.clone(), HistoryRetention::default()).unwrap();
+        assert_eq!(reopened.len(), len);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_delete() {
+        let path = temp_log();
+        let store = HistoryStore::open(path.clone(), HistoryRetention::default()).unwrap();
+        for id in ["a", "b", "c", "d"] {
+            store.add(create_test_record(id)).unwrap();
+        }
+        store
+            .set_status("b", TransferStatus::Failed, Some("Timed out".to_string()))
+            .unwrap();
+
+        assert_eq!(
+            store.delete(&["a".to_string(), "x".to_string()]).unwrap(),
+            1
+        );
+        let failed = HistoryQuery {
+            status: vec![TransferStatus::Failed],
+            ..Default::default()
+        };
+        assert_eq!(store.delete_matching(&failed).unwrap(), 1);
+        assert_eq!(store.delete_where(|r| r.id == "c").unwrap(), 1);
+
+        drop(store);
+        let reopened = HistoryStore::open(path.clone(), HistoryRetention::default()).unwrap();
+        let ids: Vec<String> = reopened.list().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, ["d"]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
     }
 }
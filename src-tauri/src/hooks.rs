@@ -0,0 +1,619 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Post-receive hooks
+//
+// Hooks run in order once a received transfer has been placed on disk. Each
+// hook has a time limit, and its outcome and captured output are stored with
+// the transfer record. Hooks only run for trusted hosts unless they opt in.
+//
+// Moving and extracting files runs on a blocking thread, which a timeout
+// cannot stop; it checks a cancellation flag between files (and archive
+// entries and chunks), so a timed-out hook stops there and its run ends once
+// it has. Extraction stays within the receive limits.
+
+use crate::conflict;
+use crate::types::{AppError, HookAction, HookRun, PostReceiveHook};
+use chrono::Utc;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/// Maximum captured output kept per hook run
+const MAX_OUTPUT_BYTES: usize = 16 * 1024;
+
+/// Maximum archive entries extracted per hook run
+const MAX_ARCHIVE_ENTRIES: u64 = 100_000;
+
+/// Bytes extracted from a zip entry between cancellation checks
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// The received transfer a hook runs for
+pub struct HookContext {
+    pub transfer_id: String,
+    pub sender_address: String,
+    pub sender_name: Option<String>,
+    /// Final paths of the received files
+    pub files: Vec<PathBuf>,
+    /// Bytes extracting archives may still write, within the receive limits
+    /// (see `quota::unpack_limit`); lowered as archives are extracted
+    pub extract_limit: u64,
+}
+
+/// What a single hook produced
+struct HookOutcome {
+    exit_code: Option<i32>,
+    output: String,
+}
+
+/// Run the enabled hooks in order
+///
+/// Hooks that move files update the paths seen by the hooks after them, and
+/// `ctx.files` holds the final paths afterwards.
+pub async fn run_hooks(
+    hooks: &[PostReceiveHook],
+    trusted: bool,
+    ctx: &mut HookContext,
+) -> Vec<HookRun> {
+    let mut runs = Vec::new();
+    for hook in hooks.iter().filter(|h| h.enabled) {
+        if !trusted && !hook.allow_untrusted {
+            tracing::info!(
+                "Skipping hook '{}' for untrusted sender {}",
+                hook.name,
+                ctx.sender_address
+            );
+            continue;
+        }
+
+        let started_at = Utc::now();
+        let start = Instant::now();
+        let timeout = Duration::from_secs(hook.timeout_secs);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut action = std::pin::pin!(run_action(&hook.action, ctx, cancel.clone()));
+        let result = match tokio::time::timeout(timeout, action.as_mut()).await {
+            Ok(result) => result,
+            Err(_) => {
+                cancel.store(true, Ordering::Relaxed);
+                // File work stops at its next check; wait for it so that the
+                // paths of files already moved are kept
+                if is_file_work(&hook.action) {
+                    let _ = action.await;
+                }
+                Err(AppError::Hook(format!(
+                    "Timed out after {} seconds",
+                    hook.timeout_secs
+                )))
+            }
+        };
+
+        let run = match result {
+            Ok(outcome) => HookRun {
+                hook: hook.name.clone(),
+                success: outcome.exit_code.map(|code| code == 0).unwrap_or(true),
+                exit_code: outcome.exit_code,
+                output: outcome.output,
+                error: None,
+                started_at,
+                duration_ms: start.elapsed().as_millis() as u64,
+            },
+            Err(e) => HookRun {
+                hook: hook.name.clone(),
+                success: false,
+                exit_code: None,
+                output: String::new(),
+                error: Some(e.to_string()),
+                started_at,
+                duration_ms: start.elapsed().as_millis() as u64,
+            },
+        };
+
+        if run.success {
+            tracing::info!("Hook '{}' finished in {} ms", run.hook, run.duration_ms);
+        } else {
+            tracing::warn!(
+                "Hook '{}' failed: {:?} {:?}",
+                run.hook,
+                run.exit_code,
+                run.error
+            );
+        }
+        runs.push(run);
+    }
+
+    runs
+}
+
+/// Whether a hook action works on files on a blocking thread
+fn is_file_work(action: &HookAction) -> bool {
+    matches!(
+        action,
+        HookAction::MoveFiles { .. } | HookAction::ExtractArchives { .. }
+    )
+}
+
+async fn run_action(
+    action: &HookAction,
+    ctx: &mut HookContext,
+    cancel: Arc<AtomicBool>,
+) -> Result<HookOutcome, AppError> {
+    match action {
+        HookAction::RunCommand { program, args } => run_command(program, args, ctx).await,
+        HookAction::MoveFiles { directory } => {
+            let directory = directory.clone();
+            let files = ctx.files.clone();
+            let (files, result) =
+                tokio::task::spawn_blocking(move || move_files(files, &directory, &cancel))
+                    .await
+                    .map_err(|e| AppError::Hook(format!("Hook task failed: {}", e)))?;
+            // Files moved before a failure are still recorded at their new paths
+            ctx.files = files;
+            result?;
+            Ok(HookOutcome {
+                exit_code: None,
+                output: describe(&ctx.files),
+            })
+        }
+        HookAction::ExtractArchives { directory } => {
+            let directory = directory.clone();
+            let files = ctx.files.clone();
+            let limit = ctx.extract_limit;
+            let (extraction, result) = tokio::task::spawn_blocking(move || {
+                let mut extraction = Extraction::default();
+                let result = extract_archives(
+                    &files,
+                    directory.as_deref(),
+                    &mut extraction,
+                    limit,
+                    &cancel,
+                );
+                (extraction, result)
+            })
+            .await
+            .map_err(|e| AppError::Hook(format!("Hook task failed: {}", e)))?;
+            // Archives extracted before a failure are kept and count
+            ctx.extract_limit = limit.saturating_sub(extraction.bytes);
+            result?;
+            Ok(HookOutcome {
+                exit_code: None,
+                output: describe(&extraction.dirs),
+            })
+        }
+        HookAction::OpenFolder => {
+            let folder = ctx
+                .files
+                .first()
+                .and_then(|f| f.parent())
+                .ok_or_else(|| AppError::FileIo("No received files to open".to_string()))?;
+            open_folder(folder)?;
+            Ok(HookOutcome {
+                exit_code: None,
+                output: folder.display().to_string(),
+            })
+        }
+    }
+}
+
+/// Run a program with the file paths as trailing arguments
+async fn run_command(
+    program: &str,
+    args: &[String],
+    ctx: &HookContext,
+) -> Result<HookOutcome, AppError> {
+    let files: Vec<String> = ctx.files.iter().map(|f| f.display().to_string()).collect();
+    let output = Command::new(program)
+        .args(args)
+        .args(&files)
+        .env("GOSH_TRANSFER_ID", &ctx.transfer_id)
+        .env("GOSH_SENDER_ADDRESS", &ctx.sender_address)
+        .env("GOSH_SENDER_NAME", ctx.sender_name.as_deref().unwrap_or(""))
+        .env("GOSH_FILES", files.join("\n"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| AppError::Hook(format!("Failed to run {}: {}", program, e)))?;
+
+    let mut captured = String::from_utf8_lossy(&output.stdout).to_string();
+    captured.push_str(&String::from_utf8_lossy(&output.stderr));
+
+    Ok(HookOutcome {
+        exit_code: output.status.code(),
+        output: truncate(captured),
+    })
+}
+
+/// Fail once a timed-out hook's file work is to stop
+fn check_cancelled(cancel: &AtomicBool) -> Result<(), AppError> {
+    if cancel.load(Ordering::Relaxed) {
+        return Err(AppError::Hook("Stopped after the time limit".to_string()));
+    }
+    Ok(())
+}
+
+/// Move the files into `directory`, replacing each path with its new one
+///
+/// Stops at the first failure, or before the next file once cancelled; the
+/// paths returned are correct either way.
+fn move_files(
+    mut files: Vec<PathBuf>,
+    directory: &Path,
+    cancel: &AtomicBool,
+) -> (Vec<PathBuf>, Result<(), AppError>) {
+    if let Err(e) = fs::create_dir_all(directory) {
+        let error = AppError::FileIo(format!("Failed to create {:?}: {}", directory, e));
+        return (files, Err(error));
+    }
+
+    for file in files.iter_mut() {
+        if let Err(e) = check_cancelled(cancel) {
+            return (files, Err(e));
+        }
+        let name = match file.file_name() {
+            Some(name) => name,
+            None => {
+                let error = AppError::FileIo(format!("Invalid file path: {:?}", file));
+                return (files, Err(error));
+            }
+        };
+        let target = conflict::unique_path(&directory.join(name));
+        if let Err(e) = conflict::move_file(file, &target) {
+            return (files, Err(e));
+        }
+        *file = target;
+    }
+    (files, Ok(()))
+}
+
+/// What extracting archives wrote so far
+#[derive(Default)]
+struct Extraction {
+    /// Directories the archives were extracted to
+    dirs: Vec<PathBuf>,
+    bytes: u64,
+    entries: u64,
+}
+
+impl Extraction {
+    /// Count one more archive entry
+    fn add_entry(&mut self, cancel: &AtomicBool) -> Result<(), AppError> {
+        check_cancelled(cancel)?;
+        self.entries += 1;
+        if self.entries > MAX_ARCHIVE_ENTRIES {
+            return Err(AppError::Hook(format!(
+                "The archives hold more than {} entries",
+                MAX_ARCHIVE_ENTRIES
+            )));
+        }
+        Ok(())
+    }
+
+    /// Count `size` more bytes written, failing past `limit`
+    fn add_bytes(&mut self, size: u64, limit: u64) -> Result<(), AppError> {
+        self.bytes = self.bytes.saturating_add(size);
+        if self.bytes > limit {
+            return Err(AppError::Hook(format!(
+                "Extracting the archives would write more than the {} bytes the receive limits allow",
+                limit
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Extract every supported archive, writing at most `limit` bytes in all
+///
+/// An archive that fails (or would go past the limits) is removed from
+/// where it was being extracted; those extracted before it stay.
+fn extract_archives(
+    files: &[PathBuf],
+    directory: Option<&Path>,
+    extraction: &mut Extraction,
+    limit: u64,
+    cancel: &AtomicBool,
+) -> Result<(), AppError> {
+    for file in files {
+        let name = file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let stem = [".tar.gz", ".tgz", ".tar", ".zip"]
+            .iter()
+            .find_map(|ext| name.strip_suffix(ext));
+        let stem = match stem {
+            Some(stem) => stem,
+            None => continue,
+        };
+
+        check_cancelled(cancel)?;
+        let parent = file.parent().unwrap_or(Path::new("."));
+        let target = conflict::unique_path(&directory.unwrap_or(parent).join(stem));
+        fs::create_dir_all(&target)
+            .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", target, e)))?;
+
+        let archive = fs::File::open(file)
+            .map_err(|e| AppError::FileIo(format!("Failed to open {:?}: {}", file, e)))?;
+        let before = extraction.bytes;
+        let result = if name.ends_with(".zip") {
+            extract_zip(archive, &target, extraction, limit, cancel)
+        } else if name.ends_with(".tar") {
+            extract_tar(archive, &target, extraction, limit, cancel)
+        } else {
+            let decoder = flate2::read::GzDecoder::new(archive);
+            extract_tar(decoder, &target, extraction, limit, cancel)
+        };
+        if let Err(e) = result {
+            let _ = fs::remove_dir_all(&target);
+            extraction.bytes = before;
+            return Err(match e {
+                AppError::FileIo(e) => {
+                    AppError::FileIo(format!("Failed to extract {:?}: {}", file, e))
+                }
+                e => e,
+            });
+        }
+
+        extraction.dirs.push(target);
+    }
+
+    Ok(())
+}
+
+/// Extract a tar archive entry by entry, skipping entries that would escape
+/// `target`
+fn extract_tar(
+    reader: impl Read,
+    target: &Path,
+    extraction: &mut Extraction,
+    limit: u64,
+    cancel: &AtomicBool,
+) -> Result<(), AppError> {
+    let io_error = |e: std::io::Error| AppError::FileIo(e.to_string());
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(io_error)? {
+        let mut entry = entry.map_err(io_error)?;
+        extraction.add_entry(cancel)?;
+        // The header size is what unpacking writes
+        extraction.add_bytes(entry.size(), limit)?;
+        entry.unpack_in(target).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// Extract a zip archive, skipping entries that would escape `target`
+///
+/// Entry sizes are counted as they are written, not as the archive states
+/// them.
+fn extract_zip(
+    archive: fs::File,
+    target: &Path,
+    extraction: &mut Extraction,
+    limit: u64,
+    cancel: &AtomicBool,
+) -> Result<(), AppError> {
+    let io_error = |e: std::io::Error| AppError::FileIo(e.to_string());
+    let mut zip = zip::ZipArchive::new(archive).map_err(|e| AppError::FileIo(e.to_string()))?;
+    for i in 0..zip.len() {
+        let mut entry = zip
+            .by_index(i)
+            .map_err(|e| AppError::FileIo(e.to_string()))?;
+        let path = match entry.enclosed_name() {
+            Some(path) => target.join(path),
+            None => continue,
+        };
+        extraction.add_entry(cancel)?;
+
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(io_error)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        let mut out = fs::File::create(&path).map_err(io_error)?;
+        let mut buf = vec![0; CHUNK_BYTES];
+        loop {
+            let read = entry.read(&mut buf).map_err(io_error)?;
+            if read == 0 {
+                break;
+            }
+            check_cancelled(cancel)?;
+            extraction.add_bytes(read as u64, limit)?;
+            out.write_all(&buf[..read]).map_err(io_error)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Open a folder in the platform file manager
+fn open_folder(folder: &Path) -> Result<(), AppError> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(target_os = "windows") {
+        "explorer"
+    } else {
+        "xdg-open"
+    };
+
+    std::process::Command::new(opener)
+        .arg(folder)
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| AppError::FileIo(format!("Failed to open {:?}: {}", folder, e)))
+}
+
+fn describe(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Keep at most `MAX_OUTPUT_BYTES` of output, cutting on a char boundary
+fn truncate(mut output: String) -> String {
+    if output.len() > MAX_OUTPUT_BYTES {
+        let mut end = MAX_OUTPUT_BYTES;
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        output.truncate(end);
+        output.push_str("\n[output truncated]");
+    }
+    output
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_run_command_hook() {
+        let hooks = vec![
+            PostReceiveHook {
+                name: "echo".to_string(),
+                enabled: true,
+                action: HookAction::RunCommand {
+                    program: "sh".to_string(),
+                    args: vec![
+                        "-c".to_string(),
+                        "echo \"$GOSH_SENDER_ADDRESS $1\"".to_string(),
+                        "hook".to_string(),
+                    ],
+                },
+                timeout_secs: 10,
+                allow_untrusted: true,
+            },
+            PostReceiveHook {
+                name: "slow".to_string(),
+                enabled: true,
+                action: HookAction::RunCommand {
+                    program: "sh".to_string(),
+                    args: vec!["-c".to_string(), "sleep 5".to_string()],
+                },
+                timeout_secs: 0,
+                allow_untrusted: true,
+            },
+            PostReceiveHook {
+                name: "trusted only".to_string(),
+                enabled: true,
+                action: HookAction::OpenFolder,
+                timeout_secs: 10,
+                allow_untrusted: false,
+            },
+        ];
+        let mut ctx = HookContext {
+            transfer_id: "t1".to_string(),
+            sender_address: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![PathBuf::from("/tmp/report.pdf")],
+            extract_limit: 0,
+        };
+
+        let runs = run_hooks(&hooks, false, &mut ctx).await;
+        assert_eq!(runs.len(), 2);
+        assert!(runs[0].success);
+        assert_eq!(runs[0].output.trim(), "10.0.0.5 /tmp/report.pdf");
+        assert!(!runs[1].success);
+        assert!(runs[1].error.as_deref().unwrap().contains("Timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_move_files_hook() {
+        let dir = std::env::temp_dir().join(format!("gosh-hooks-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        let hooks = vec![PostReceiveHook {
+            name: "move".to_string(),
+            enabled: true,
+            action: HookAction::MoveFiles {
+                directory: dir.join("sorted"),
+            },
+            timeout_secs: 10,
+            allow_untrusted: true,
+        }];
+        let mut ctx = HookContext {
+            transfer_id: "t1".to_string(),
+            sender_address: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![dir.join("a.txt"), dir.join("missing.txt")],
+            extract_limit: 0,
+        };
+
+        let runs = run_hooks(&hooks, true, &mut ctx).await;
+        assert!(!runs[0].success);
+        assert_eq!(
+            ctx.files,
+            [dir.join("sorted/a.txt"), dir.join("missing.txt")]
+        );
+        assert!(dir.join("sorted/a.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_move_files_stops_once_cancelled() {
+        let dir = std::env::temp_dir().join(format!("gosh-hooks-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "a").unwrap();
+
+        let cancel = AtomicBool::new(true);
+        let (files, result) = move_files(vec![dir.join("a.txt")], &dir.join("sorted"), &cancel);
+        assert!(result.is_err());
+        assert_eq!(files, [dir.join("a.txt")]);
+        assert!(dir.join("a.txt").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extract_archives_within_limit() {
+        let dir = std::env::temp_dir().join(format!("gosh-hooks-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut builder = tar::Builder::new(fs::File::create(dir.join("pack.tar")).unwrap());
+        for name in ["a.bin", "b.bin"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(100);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, &[0u8; 100][..])
+                .unwrap();
+        }
+        builder.finish().unwrap();
+        drop(builder);
+
+        let hooks = vec![PostReceiveHook {
+            name: "extract".to_string(),
+            enabled: true,
+            action: HookAction::ExtractArchives { directory: None },
+            timeout_secs: 10,
+            allow_untrusted: true,
+        }];
+        let mut ctx = HookContext {
+            transfer_id: "t1".to_string(),
+            sender_address: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![dir.join("pack.tar")],
+            extract_limit: 150,
+        };
+
+        // Past the limit, nothing is left behind
+        let runs = run_hooks(&hooks, true, &mut ctx).await;
+        assert!(!runs[0].success);
+        assert!(!dir.join("pack").exists());
+        assert_eq!(ctx.extract_limit, 150);
+
+        ctx.extract_limit = 1000;
+        let runs = run_hooks(&hooks, true, &mut ctx).await;
+        assert!(runs[0].success);
+        assert!(dir.join("pack/b.bin").exists());
+        assert_eq!(ctx.extract_limit, 800);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,36 +1,161 @@
 // SPDX-License-Identifier: AGPL-3.0
 // Gosh Transfer - Library exports
 
+pub mod checksum;
+pub mod cli;
 pub mod commands;
+pub mod compress;
+pub mod conflict;
+pub mod events;
+pub mod export;
 pub mod favorites;
+pub mod filter;
 pub mod history;
+pub mod hooks;
+pub mod manifest;
+pub mod metadata;
+pub mod notifications;
+pub mod outcome;
+pub mod quota;
+pub mod receive;
+pub mod resume;
+pub mod routing;
+pub mod select;
+pub mod send;
 pub mod settings;
+pub mod sidecar;
+pub mod staging;
+pub mod stats;
+pub mod text;
+pub mod throttle;
+pub mod tracker;
 pub mod types;
+pub mod webhooks;
 
 use commands::AppState;
+use events::{AppEvent, EventBus, ProgressCoalescer, PROGRESS_INTERVAL};
 use favorites::FavoritesStore;
-use gosh_lan_transfer::{EngineConfig, EngineEvent, GoshTransferEngine};
+use gosh_lan_transfer::{EngineConfig, GoshTransferEngine};
 use history::HistoryStore;
+use quota::QuotaLedger;
 use settings::SettingsStore;
+use staging::ReceiveQueue;
+use std::path::Path;
 use std::sync::Arc;
-use tauri::{Emitter, Manager};
+use tauri::Manager;
+use throttle::IncomingRelay;
 use tokio::sync::Mutex;
+use tracker::TransferTracker;
+use types::AppSettings;
+use webhooks::WebhookStore;
+
+/// Build the engine configuration from app settings
+///
+/// Trusted hosts are not handed to the engine: auto-accept is done by the app
+/// after the receive limits have been checked (see `receive::handle_incoming`).
+/// The engine writes into `staging_dir`, the directory shared by the
+/// transfers being received (see `staging`); files are moved into place by
+/// `receive::process_received` once a transfer completes. The server
+/// listens on `port`, the app's port unless a relay limiting downloads is in
+/// front of it (see `throttle`).
+pub fn build_engine_config(settings: &AppSettings, staging_dir: &Path, port: u16) -> EngineConfig {
+    EngineConfig::builder()
+        .port(port)
+        .device_name(&settings.device_name)
+        .download_dir(staging_dir.to_path_buf())
+        .trusted_hosts(Vec::new())
+        .receive_only(settings.receive_only)
+        .build()
+}
+
+/// Translate engine events and publish them on the bus
+///
+/// Progress is coalesced per transfer and published every
+/// `PROGRESS_INTERVAL`. If this loop falls behind the engine, the missed
+/// events are counted, `EventsLagged` is published and pending requests are
+/// resynchronized with the engine.
+async fn forward_engine_events(
+    app_handle: tauri::AppHandle,
+    mut rx: tokio::sync::broadcast::Receiver<gosh_lan_transfer::EngineEvent>,
+) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    let mut progress = ProgressCoalescer::new();
+    let mut flush = tokio::time::interval(PROGRESS_INTERVAL);
+    flush.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        let state = app_handle.state::<AppState>();
+        tokio::select! {
+            received = rx.recv() => match received {
+                Ok(event) => match events::from_engine(&event) {
+                    AppEvent::TransferProgress { progress: update } => {
+                        if progress.push(update) {
+                            state.events.record_coalesced();
+                        }
+                    }
+                    // Screening may wait on the engine lock, so it runs
+                    // outside this loop and publishes the outcome itself.
+                    // Track the request now so early progress has a home.
+                    AppEvent::TransferRequest { transfer, held_reason, auto_accepted } => {
+                        let transfer = state.incoming_relay.restore_source(transfer);
+                        state.tracker.apply(&AppEvent::TransferRequest {
+                            transfer: transfer.clone(),
+                            held_reason,
+                            auto_accepted,
+                        });
+                        tauri::async_runtime::spawn(receive::handle_incoming(
+                            app_handle.clone(),
+                            transfer,
+                        ));
+                    }
+                    // The relay limiting downloads takes the app's port in
+                    // the server's place
+                    event @ (AppEvent::ServerStarted { .. } | AppEvent::PortChanged { .. }) => {
+                        let port = state.settings.read().await.port;
+                        if let Some(event) = state.incoming_relay.public_event(event, port) {
+                            state.publish(event);
+                        }
+                    }
+                    event => {
+                        // Keep the last progress ahead of the event that follows it
+                        if let Some(update) = event.transfer_id().and_then(|id| progress.take(id)) {
+                            state.publish(update);
+                        }
+                        state.publish(event);
+                    }
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Engine event loop skipped {} events", skipped);
+                    state.events.record_dropped("engine", skipped);
+                    state.publish(AppEvent::EventsLagged { skipped });
+                    tauri::async_runtime::spawn(receive::resync_pending(app_handle.clone()));
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = flush.tick() => {
+                for update in progress.drain() {
+                    state.publish(update);
+                }
+            }
+        }
+    }
+}
 
 /// Initialize the application state
 pub fn init_app_state() -> Result<AppState, types::AppError> {
     let settings_store = SettingsStore::new()?;
     let settings = settings_store.get();
     let favorites = FavoritesStore::new()?;
-    let history_store = HistoryStore::new()?;
+    let history_store = HistoryStore::new(settings.history_retention.clone())?;
+    let webhooks = WebhookStore::new()?;
 
     // Build engine config from app settings
-    let engine_config = EngineConfig::builder()
-        .port(settings.port)
-        .device_name(&settings.device_name)
-        .download_dir(&settings.download_dir)
-        .trusted_hosts(settings.trusted_hosts.clone())
-        .receive_only(settings.receive_only)
-        .build();
+    let engine_config = build_engine_config(
+        &settings,
+        &staging::prepare_receiving(&settings.download_dir),
+        settings.port,
+    );
 
     // Create a channel for engine events
     let (engine, event_rx) = GoshTransferEngine::with_channel_events(engine_config);
@@ -42,6 +167,12 @@ pub fn init_app_state() -> Result<AppState, types::AppError> {
         settings_store,
         settings: tokio::sync::RwLock::new(settings),
         history_store,
+        webhooks,
+        events: EventBus::new(),
+        tracker: TransferTracker::new(),
+        receive_queue: ReceiveQueue::new(),
+        incoming_relay: IncomingRelay::new(),
+        quota: QuotaLedger::new()?,
     })
 }
 
@@ -74,6 +205,7 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_os::init())
         .plugin(tauri_plugin_notification::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
         .manage(app_state)
         .invoke_handler(tauri::generate_handler![
             // Favorites
@@ -89,14 +221,34 @@ pub fn run() {
             // Transfers
             commands::send_files,
             commands::send_directory,
+            commands::preview_directory,
+            commands::prepare_send,
+            commands::send_text,
+            commands::resend_from_history,
+            commands::resume_transfer,
+            commands::discard_partial_files,
             commands::accept_transfer,
             commands::reject_transfer,
             commands::cancel_transfer,
             commands::accept_all_transfers,
             commands::reject_all_transfers,
             commands::get_pending_transfers,
+            commands::resolve_conflict,
+            commands::verify_file,
             commands::get_transfer_history,
+            commands::query_transfer_history,
             commands::clear_transfer_history,
+            commands::delete_history_entries,
+            commands::delete_history_matching,
+            commands::forget_peer,
+            commands::export_history,
+            commands::import_history,
+            commands::get_transfer_stats,
+            commands::get_webhook_deliveries,
+            commands::clear_webhook_deliveries,
+            commands::get_active_transfers,
+            commands::get_recent_events,
+            commands::get_event_stats,
             // Settings
             commands::get_settings,
             commands::update_settings,
@@ -124,23 +276,57 @@ pub fn run() {
                 let _ = apply_mica(&window, None);
             }
 
-            // Start the engine server
-            let engine = app.state::<AppState>().engine.clone();
+            // Start the engine server, behind the relay limiting downloads if
+            // any are limited
+            let app_handle = app.handle().clone();
             tauri::async_runtime::spawn(async move {
-                let mut engine = engine.lock().await;
+                let state = app_handle.state::<AppState>();
+                let mut engine = state.engine.lock().await;
                 if let Err(e) = engine.start_server().await {
                     tracing::error!("Failed to start server: {}", e);
+                    return;
+                }
+                let settings = state.settings.read().await.clone();
+                if let Err(e) = state
+                    .incoming_relay
+                    .apply(&app_handle, &mut engine, &settings, settings.port)
+                    .await
+                {
+                    tracing::error!("Failed to start the download relay: {}", e);
                 }
             });
 
-            // Set up event forwarding from engine to frontend
+            // Subscribe the event consumers before any engine event arrives
+            let bus = &app.state::<AppState>().events;
+            let app_handle = app.handle().clone();
+            bus.spawn_subscriber("webview", move |event| {
+                events::emit_to_webview(&app_handle, &event);
+                std::future::ready(())
+            });
+            let app_handle = app.handle().clone();
+            bus.spawn_subscriber("history", move |event| {
+                receive::handle_event(app_handle.clone(), event)
+            });
+            let app_handle = app.handle().clone();
+            bus.spawn_subscriber("notifications", move |event| {
+                notifications::handle_event(app_handle.clone(), event)
+            });
+            let app_handle = app.handle().clone();
+            bus.spawn_subscriber("webhooks", move |event| {
+                webhooks::dispatch(&app_handle, &event);
+                std::future::ready(())
+            });
+
+            // Deliver what transfers cut short by the last exit received and
+            // clear out staging
+            tauri::async_runtime::spawn(receive::recover_interrupted(app.handle().clone()));
+
+            // Translate engine events and publish them on the bus
             let app_handle = app.handle().clone();
             let event_rx = app.state::<AppState>().event_rx.clone();
 
             tauri::async_runtime::spawn(async move {
-                use tauri_plugin_notification::NotificationExt;
-
-                let mut rx = {
+                let rx = {
                     let mut guard = event_rx.lock().await;
                     match guard.take() {
                         Some(rx) => rx,
@@ -148,113 +334,7 @@ pub fn run() {
                     }
                 };
 
-                while let Ok(event) = rx.recv().await {
-                    let (event_name, payload) = match &event {
-                        EngineEvent::TransferRequest(transfer) => {
-                            // Send notification for incoming transfer
-                            let state = app_handle.state::<AppState>();
-                            let settings = state.settings.read().await;
-                            if settings.notifications_enabled {
-                                let sender = transfer.sender_name.as_deref().unwrap_or("Unknown Device");
-                                let file_count = transfer.files.len();
-                                let body = if file_count == 1 {
-                                    format!("{} wants to send you a file", sender)
-                                } else {
-                                    format!("{} wants to send you {} files", sender, file_count)
-                                };
-                                let _ = app_handle.notification()
-                                    .builder()
-                                    .title("Incoming Transfer")
-                                    .body(&body)
-                                    .show();
-                            }
-
-                            (
-                                "transfer-request",
-                                serde_json::json!({
-                                    "type": "transferRequest",
-                                    "transfer": transfer
-                                }),
-                            )
-                        }
-                        EngineEvent::TransferProgress(progress) => (
-                            "transfer-progress",
-                            serde_json::json!({
-                                "type": "progress",
-                                "progress": {
-                                    "transferId": progress.transfer_id,
-                                    "bytesTransferred": progress.bytes_transferred,
-                                    "totalBytes": progress.total_bytes,
-                                    "currentFile": progress.current_file,
-                                    "speedBps": progress.speed_bps
-                                }
-                            }),
-                        ),
-                        EngineEvent::TransferComplete { transfer_id } => {
-                            // Send notification for completed transfer
-                            let state = app_handle.state::<AppState>();
-                            let settings = state.settings.read().await;
-                            if settings.notifications_enabled {
-                                let _ = app_handle.notification()
-                                    .builder()
-                                    .title("Transfer Complete")
-                                    .body("Files received successfully")
-                                    .show();
-                            }
-
-                            (
-                                "transfer-complete",
-                                serde_json::json!({
-                                    "type": "transferComplete",
-                                    "transferId": transfer_id
-                                }),
-                            )
-                        }
-                        EngineEvent::TransferFailed { transfer_id, error } => (
-                            "transfer-failed",
-                            serde_json::json!({
-                                "type": "transferFailed",
-                                "transferId": transfer_id,
-                                "error": error
-                            }),
-                        ),
-                        EngineEvent::ServerStarted { port } => (
-                            "server-started",
-                            serde_json::json!({
-                                "type": "serverStarted",
-                                "port": port
-                            }),
-                        ),
-                        EngineEvent::ServerStopped => (
-                            "server-stopped",
-                            serde_json::json!({
-                                "type": "serverStopped"
-                            }),
-                        ),
-                        EngineEvent::TransferRetry { transfer_id, attempt, max_attempts, error } => (
-                            "transfer-retry",
-                            serde_json::json!({
-                                "type": "transferRetry",
-                                "transferId": transfer_id,
-                                "attempt": attempt,
-                                "maxAttempts": max_attempts,
-                                "error": error
-                            }),
-                        ),
-                        EngineEvent::PortChanged { old_port, new_port } => (
-                            "port-changed",
-                            serde_json::json!({
-                                "type": "portChanged",
-                                "oldPort": old_port,
-                                "newPort": new_port
-                            }),
-                        ),
-                    };
-
-                    if let Err(e) = app_handle.emit(event_name, payload) {
-                        tracing::warn!("Failed to emit event: {}", e);
-                    }
-                }
+                forward_engine_events(app_handle, rx).await;
             });
 
             Ok(())
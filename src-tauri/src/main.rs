@@ -5,5 +5,34 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    #[cfg(windows)]
+    if gosh_transfer::cli::is_subcommand(&args) {
+        attach_console();
+    }
+    if let Some(code) = gosh_transfer::cli::run(&args) {
+        std::process::exit(code);
+    }
+
     gosh_transfer::run()
 }
+
+/// Write subcommand output to the console the app was started from
+///
+/// Release builds have no console of their own, so without this their
+/// output would be lost. Started outside a console, output stays discarded.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // SAFETY: AttachConsole takes no pointers and only fails when there is
+    // no parent console or one is attached already
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Send manifests
+//
+// Before a send, the files it would transfer can be listed without sending
+// anything: their names as the peer will see them, sizes and MIME types
+// (guessed from the extension), along with paths that cannot be read and
+// symbolic links met on the way. The send follows links, so their targets
+// are listed like any other file or directory (each directory only once, in
+// case links form a loop).
+//
+// The `prepare_send` command adds a check of the peer, and the command line
+// has the same as `gosh-transfer prepare-send`.
+
+use crate::select;
+use crate::types::{
+    AppError, DirectoryFilter, ManifestFile, ManifestSymlink, PeerCheck, PreparedSend,
+    SendManifest, UnreadablePath,
+};
+use gosh_lan_transfer::GoshTransferEngine;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+struct Builder {
+    /// Base the file names are relative to
+    base: PathBuf,
+    /// Files a directory filter selects, if there is one
+    selected: Option<HashSet<PathBuf>>,
+    /// Directories walked so far, canonicalized
+    visited: HashSet<PathBuf>,
+    manifest: SendManifest,
+}
+
+impl Builder {
+    fn name(&self, path: &Path) -> String {
+        path.strip_prefix(&self.base)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    fn unreadable(&mut self, path: &Path, error: std::io::Error) {
+        self.manifest.unreadable.push(UnreadablePath {
+            path: path.to_path_buf(),
+            error: error.to_string(),
+        });
+    }
+
+    fn add(&mut self, path: &Path) {
+        let link = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata.file_type().is_symlink(),
+            Err(e) => return self.unreadable(path, e),
+        };
+        if link {
+            let target = fs::read_link(path).unwrap_or_default();
+            self.manifest.symlinks.push(ManifestSymlink {
+                name: self.name(path),
+                target,
+                is_dir: path.is_dir(),
+            });
+        }
+
+        if path.is_dir() {
+            self.add_dir(path);
+        } else {
+            self.add_file(path);
+        }
+    }
+
+    fn add_dir(&mut self, dir: &Path) {
+        if let Ok(canonical) = dir.canonicalize() {
+            if !self.visited.insert(canonical) {
+                return;
+            }
+        }
+        let mut entries: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+            Err(e) => return self.unreadable(dir, e),
+        };
+        entries.sort();
+        for path in entries {
+            self.add(&path);
+        }
+    }
+
+    fn add_file(&mut self, path: &Path) {
+        if let Some(selected) = &self.selected {
+            if !selected.contains(path) {
+                return;
+            }
+        }
+        let size = match fs::File::open(path).and_then(|file| file.metadata()) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => return self.unreadable(path, e),
+        };
+
+        self.manifest.total_size += size;
+        self.manifest.files.push(ManifestFile {
+            name: self.name(path),
+            size,
+            mime_type: mime_guess::from_path(path).first().map(|m| m.to_string()),
+        });
+    }
+}
+
+/// List what sending `paths` would transfer
+///
+/// With `directory`, the single path is sent as a directory and `filter`
+/// selects its files.
+pub fn build(
+    paths: &[PathBuf],
+    directory: bool,
+    filter: Option<&DirectoryFilter>,
+) -> Result<SendManifest, AppError> {
+    let mut builder = Builder {
+        base: PathBuf::new(),
+        selected: None,
+        visited: HashSet::new(),
+        manifest: SendManifest::default(),
+    };
+
+    if directory {
+        let root = match paths {
+            [root] => root,
+            _ => {
+                return Err(AppError::InvalidConfig(
+                    "A directory send takes exactly one directory".to_string(),
+                ))
+            }
+        };
+        if let Some(filter) = filter.filter(|f| !f.is_empty()) {
+            let (selected, excluded) = select::select(root, filter)?;
+            builder.selected = Some(selected.into_iter().collect());
+            builder.manifest.excluded_count = excluded;
+        }
+        builder.base = root.parent().unwrap_or(root).to_path_buf();
+        builder.add(root);
+    } else {
+        for path in paths {
+            builder.base = path.parent().unwrap_or(path).to_path_buf();
+            builder.add(path);
+        }
+    }
+
+    builder.manifest.file_count = builder.manifest.files.len();
+    Ok(builder.manifest)
+}
+
+/// Check that a peer answers, and fetch its `/info` if it does
+pub async fn check_peer(engine: &GoshTransferEngine, address: &str, port: u16) -> PeerCheck {
+    match engine.check_peer(address, port).await {
+        Ok(true) => match engine.get_peer_info(address, port).await {
+            Ok(info) => PeerCheck {
+                reachable: true,
+                info: Some(info),
+                error: None,
+            },
+            Err(e) => PeerCheck {
+                reachable: true,
+                info: None,
+                error: Some(e.to_string()),
+            },
+        },
+        Ok(false) => PeerCheck {
+            reachable: false,
+            info: None,
+            error: None,
+        },
+        Err(e) => PeerCheck {
+            reachable: false,
+            info: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+/// List what a send would transfer and check the peer, without sending
+pub async fn prepare(
+    engine: &GoshTransferEngine,
+    address: &str,
+    port: u16,
+    paths: Vec<PathBuf>,
+    directory: bool,
+    filter: Option<DirectoryFilter>,
+) -> Result<PreparedSend, AppError> {
+    let manifest = tokio::task::spawn_blocking(move || build(&paths, directory, filter.as_ref()))
+        .await
+        .map_err(|e| AppError::FileIo(e.to_string()))??;
+    Ok(PreparedSend {
+        manifest,
+        peer: check_peer(engine, address, port).await,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let dir = std::env::temp_dir().join(format!("gosh-manifest-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("project");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/main.rs"), "fn main() {}").unwrap();
+        fs::write(root.join("notes.txt"), "hello").unwrap();
+        fs::write(root.join("debug.log"), "log").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(root.join("notes.txt"), root.join("link.txt")).unwrap();
+
+        let filter = DirectoryFilter {
+            exclude: vec!["*.log".to_string()],
+            ..Default::default()
+        };
+        let manifest = build(std::slice::from_ref(&root), true, Some(&filter)).unwrap();
+        #[cfg(unix)]
+        {
+            let names: Vec<&str> = manifest.files.iter().map(|f| f.name.as_str()).collect();
+            assert_eq!(
+                names,
+                [
+                    "project/link.txt",
+                    "project/notes.txt",
+                    "project/src/main.rs"
+                ]
+            );
+            assert_eq!(manifest.symlinks.len(), 1);
+            assert_eq!(manifest.symlinks[0].target, root.join("notes.txt"));
+        }
+        assert_eq!(manifest.excluded_count, 1);
+        assert_eq!(manifest.file_count, manifest.files.len());
+        assert_eq!(
+            manifest.total_size,
+            manifest.files.iter().map(|f| f.size).sum::<u64>()
+        );
+        assert_eq!(
+            manifest.files.last().unwrap().mime_type.as_deref(),
+            Some("text/x-rust")
+        );
+
+        let manifest = build(
+            &[root.join("notes.txt"), root.join("missing.txt")],
+            false,
+            None,
+        )
+        .unwrap();
+        assert_eq!(manifest.files[0].name, "notes.txt");
+        assert_eq!(manifest.files[0].mime_type.as_deref(), Some("text/plain"));
+        assert_eq!(manifest.unreadable.len(), 1);
+
+        assert!(build(&[root.clone(), root.clone()], true, None).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,488 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Preserving file metadata of directory sends
+//
+// The engine only transfers file contents, so a directory send that
+// preserves metadata carries it in a `.gosh-metadata.json` file at the top of
+// the sent directory: the modification time and Unix permission bits of each
+// file and directory, the empty directories, and the symbolic links. The
+// receiver always takes the file out of staging before anything else looks
+// at the transfer, but applies it only if `apply_received_metadata` is set,
+// once the files are placed.
+//
+// Links are sent as links only if they point into the sent directory by a
+// relative path; links with an absolute target, or one leaving the
+// directory, are skipped (neither the link nor its target is sent). The
+// receiver checks links the same way before creating them. It only touches
+// directories the transfer created, wherever routing put them, and the
+// files placed in them: existing directories and quarantined files keep
+// their metadata. Received files never get execute (or setuid and setgid)
+// bits, and the user keeps read and write access.
+
+use crate::filter;
+use crate::select::{self, Selection};
+use crate::types::{AppError, DirectoryFilter, TransferRecord};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the metadata file in a sent directory
+pub const FILE_NAME: &str = ".gosh-metadata.json";
+
+/// Permission bits sent: everything but setuid and setgid
+const MODE_MASK: u32 = 0o1777;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+}
+
+/// Metadata of one entry, named as in the transfer (`<directory>/<path>`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    name: String,
+    kind: EntryKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mode: Option<u32>,
+    /// Target of a symlink, relative to the link's directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<PathBuf>,
+}
+
+/// Metadata of a sent directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeMetadata {
+    entries: Vec<Entry>,
+}
+
+#[cfg(unix)]
+fn mode_of(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & MODE_MASK)
+}
+
+#[cfg(not(unix))]
+fn mode_of(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Whether a link named `name` (starting with the sent directory) pointing
+/// to `target` stays inside the sent directory
+fn inside_tree(name: &str, target: &Path) -> bool {
+    // Depth below the directory holding the sent directory
+    let mut depth = Path::new(name).components().count() as isize - 1;
+    if depth < 1 {
+        return false;
+    }
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                depth -= 1;
+                if depth < 1 {
+                    return false;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Add the directories between `root` and `path` to `dirs`
+fn add_parents(dirs: &mut BTreeSet<PathBuf>, root: &Path, path: &Path) {
+    for parent in path.ancestors().skip(1) {
+        if !parent.starts_with(root) || !dirs.insert(parent.to_path_buf()) {
+            break;
+        }
+    }
+}
+
+/// Lay out the files of `root` selected for sending in a temporary
+/// directory, along with the metadata of the tree
+pub fn stage(
+    transfer_id: &str,
+    root: &Path,
+    files: &[PathBuf],
+    filter: &DirectoryFilter,
+) -> Result<Selection, AppError> {
+    let tree = select::select_tree(root, filter, true)?;
+    let selection = select::mirror(transfer_id, root, files)?;
+
+    let base = root.parent().unwrap_or(root);
+    let name = |path: &Path| {
+        path.strip_prefix(base)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .replace('\\', "/")
+    };
+    let entry = |path: &Path, kind, target| {
+        let metadata = fs::symlink_metadata(path).ok();
+        Entry {
+            name: name(path),
+            kind,
+            // Link times are not restored
+            modified: match kind {
+                EntryKind::Symlink => None,
+                _ => metadata
+                    .as_ref()
+                    .and_then(|m| m.modified().ok())
+                    .map(DateTime::<Utc>::from),
+            },
+            mode: match kind {
+                EntryKind::Symlink => None,
+                _ => metadata.as_ref().and_then(mode_of),
+            },
+            target,
+        }
+    };
+
+    let mut entries = Vec::new();
+    let mut dirs = BTreeSet::from([root.to_path_buf()]);
+    for file in files {
+        add_parents(&mut dirs, root, file);
+        entries.push(entry(file, EntryKind::File, None));
+    }
+    for dir in &tree.empty_dirs {
+        add_parents(&mut dirs, root, dir);
+        dirs.insert(dir.clone());
+    }
+    for link in &tree.links {
+        let target = match fs::read_link(link) {
+            Ok(target) if inside_tree(&name(link), &target) => target,
+            Ok(target) => {
+                tracing::warn!("Skipping {:?}: it points outside to {:?}", link, target);
+                continue;
+            }
+            Err(e) => {
+                tracing::warn!("Skipping {:?}: {}", link, e);
+                continue;
+            }
+        };
+        add_parents(&mut dirs, root, link);
+        entries.push(entry(link, EntryKind::Symlink, Some(target)));
+    }
+    entries.extend(
+        dirs.iter()
+            .map(|dir| entry(dir, EntryKind::Directory, None)),
+    );
+
+    let json = serde_json::to_vec_pretty(&TreeMetadata { entries })
+        .map_err(|e| AppError::Serialization(e.to_string()))?;
+    let path = selection.path.join(FILE_NAME);
+    fs::write(&path, json)
+        .map_err(|e| AppError::FileIo(format!("Failed to write {:?}: {}", path, e)))?;
+    Ok(selection)
+}
+
+/// Take the metadata file of a received directory out of staging and of the
+/// record
+pub fn extract(staging: &Path, record: &mut TransferRecord) -> Option<TreeMetadata> {
+    let index = record.files.iter().position(|file| {
+        let mut components = Path::new(&file.name).components();
+        matches!(
+            (components.next(), components.next(), components.next()),
+            (Some(Component::Normal(_)), Some(Component::Normal(name)), None) if name == FILE_NAME
+        )
+    })?;
+    let path = filter::received_path(staging, &record.files[index].name)?;
+    let metadata = fs::read(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()));
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            tracing::warn!("Ignoring {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    let _ = fs::remove_file(&path);
+    let file = record.files.remove(index);
+    record.total_size = record.total_size.saturating_sub(file.size);
+    record.preserve_metadata = true;
+    Some(metadata)
+}
+
+/// Directories of a received tree that the transfer creates, by entry name
+///
+/// Taken before the files are placed, so directories that existed already
+/// are told apart; metadata is only applied inside the created ones.
+#[derive(Debug, Default)]
+pub struct Placement {
+    created: BTreeMap<String, Vec<PathBuf>>,
+}
+
+impl Placement {
+    fn contains(&self, dir: &Path) -> bool {
+        self.created
+            .values()
+            .flatten()
+            .any(|created| created == dir)
+    }
+}
+
+/// Work out where the directories of a received tree go, before its files
+/// are placed
+///
+/// Files go to the directory of their route, or else to `download_dir`;
+/// quarantined files do not count. A directory goes wherever the files below
+/// it go, or those below its closest parent that has any.
+pub fn plan(metadata: &TreeMetadata, record: &TransferRecord, download_dir: &Path) -> Placement {
+    let roots: Vec<(&str, &Path)> = record
+        .files
+        .iter()
+        .filter(|f| !record.quarantined.iter().any(|q| q.name == f.name))
+        .map(|f| {
+            let route = record.routes.iter().find(|r| r.name == f.name);
+            let root = route.map_or(download_dir, |r| r.directory.as_path());
+            (f.name.as_str(), root)
+        })
+        .collect();
+
+    let mut placement = Placement::default();
+    for entry in metadata
+        .entries
+        .iter()
+        .filter(|e| e.kind == EntryKind::Directory)
+    {
+        let created = roots_of(&roots, &entry.name, download_dir)
+            .into_iter()
+            .filter_map(|root| filter::received_path(root, &entry.name))
+            .filter(|path| fs::symlink_metadata(path).is_err())
+            .collect();
+        placement.created.insert(entry.name.clone(), created);
+    }
+    placement
+}
+
+/// Directories the files under the directory `name` go to
+fn roots_of<'a>(
+    roots: &[(&str, &'a Path)],
+    name: &str,
+    download_dir: &'a Path,
+) -> BTreeSet<&'a Path> {
+    for dir in Path::new(name).ancestors() {
+        if dir.as_os_str().is_empty() {
+            break;
+        }
+        let found: BTreeSet<&Path> = roots
+            .iter()
+            .filter(|(file, _)| Path::new(file).starts_with(dir))
+            .map(|(_, root)| *root)
+            .collect();
+        if !found.is_empty() {
+            return found;
+        }
+    }
+    BTreeSet::from([download_dir])
+}
+
+/// Set the modification time and permissions of `path`
+///
+/// Files never become executable, and the user keeps access to what was
+/// received.
+fn set_metadata(path: &Path, entry: &Entry) {
+    if let Some(modified) = entry.modified {
+        if let Err(e) = fs::File::open(path).and_then(|file| file.set_modified(modified.into())) {
+            tracing::warn!("Failed to set the modification time of {:?}: {}", path, e);
+        }
+    }
+    #[cfg(unix)]
+    if let Some(mode) = entry.mode {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = match entry.kind {
+            EntryKind::Directory => (mode & 0o777) | 0o700,
+            _ => (mode & 0o666) | 0o600,
+        };
+        if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+            tracing::warn!("Failed to set the permissions of {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Apply received metadata once the files of `record` are placed
+///
+/// Only the directories in `placement` are created or changed, and only
+/// links and files inside them; existing entries are never replaced by
+/// links. Quarantined files are left alone.
+pub fn apply(metadata: &TreeMetadata, placement: &Placement, record: &TransferRecord) {
+    let entries = |kind| metadata.entries.iter().filter(move |e| e.kind == kind);
+
+    for path in placement.created.values().flatten() {
+        if let Err(e) = fs::create_dir_all(path) {
+            tracing::warn!("Failed to create {:?}: {}", path, e);
+        }
+    }
+
+    for entry in entries(EntryKind::Symlink) {
+        let target = match &entry.target {
+            Some(target) if inside_tree(&entry.name, target) => target,
+            _ => {
+                tracing::warn!("Not creating link {:?}: it points outside", entry.name);
+                continue;
+            }
+        };
+        let link = Path::new(&entry.name);
+        let (parent, file_name) = match (link.parent(), link.file_name()) {
+            (Some(parent), Some(file_name)) => (parent.to_string_lossy(), file_name),
+            _ => continue,
+        };
+        let dirs = placement.created.get(parent.as_ref()).into_iter().flatten();
+        for path in dirs.map(|dir| dir.join(file_name)) {
+            if fs::symlink_metadata(&path).is_ok() {
+                continue;
+            }
+            #[cfg(unix)]
+            if let Err(e) = std::os::unix::fs::symlink(target, &path) {
+                tracing::warn!("Failed to create link {:?}: {}", path, e);
+            }
+            #[cfg(not(unix))]
+            tracing::warn!("Not creating link {:?} to {:?}", path, target);
+        }
+    }
+
+    let placed = record
+        .files
+        .iter()
+        .filter(|f| !record.quarantined.iter().any(|q| q.name == f.name))
+        .filter_map(|f| Some((f.name.as_str(), f.path.as_deref()?)))
+        .filter(|(_, path)| path.parent().is_some_and(|dir| placement.contains(dir)));
+    for (name, path) in placed {
+        if let Some(entry) = entries(EntryKind::File).find(|e| e.name == name) {
+            set_metadata(path, entry);
+        }
+    }
+
+    // Deepest first, as setting a time inside a directory changes its own
+    let mut dirs: Vec<&Entry> = entries(EntryKind::Directory).collect();
+    dirs.sort_by(|a, b| b.name.cmp(&a.name));
+    for entry in dirs {
+        for path in placement.created.get(&entry.name).into_iter().flatten() {
+            set_metadata(path, entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{FileRoute, PendingTransfer, TransferFile, TransferKind};
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stage_and_apply() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("gosh-metadata-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("project");
+        fs::create_dir_all(root.join("bin")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("bin/run.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(root.join("bin/run.sh"), fs::Permissions::from_mode(0o4755)).unwrap();
+        let modified = DateTime::parse_from_rfc3339("2024-05-01T12:00:00Z").unwrap();
+        fs::File::open(root.join("bin/run.sh"))
+            .unwrap()
+            .set_modified(modified.into())
+            .unwrap();
+        symlink("bin/run.sh", root.join("run")).unwrap();
+        symlink("../../etc/passwd", root.join("bin/passwd")).unwrap();
+
+        assert!(inside_tree("project/a/link", Path::new("../b")));
+        assert!(!inside_tree("project/link", Path::new("../other")));
+        assert!(!inside_tree("project/link", Path::new("/etc")));
+
+        let files = vec![root.join("bin/run.sh")];
+        let selection = stage("t1", &root, &files, &DirectoryFilter::default()).unwrap();
+        assert!(selection.path.join("bin/run.sh").is_file());
+
+        // Receive the staged directory
+        let staging = dir.join("staging");
+        fs::create_dir_all(staging.join("project")).unwrap();
+        fs::copy(
+            selection.path.join(FILE_NAME),
+            staging.join("project").join(FILE_NAME),
+        )
+        .unwrap();
+        let file = |name: &str, path: Option<PathBuf>| TransferFile {
+            path,
+            ..TransferFile::new(name, name, 1)
+        };
+        let download_dir = dir.join("downloads");
+        let mut record = TransferRecord::incoming(&PendingTransfer {
+            id: "t1".to_string(),
+            source_ip: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![
+                file(
+                    "project/bin/run.sh",
+                    Some(download_dir.join("project/bin/run.sh")),
+                ),
+                file(&format!("project/{}", FILE_NAME), None),
+            ],
+            total_size: 2,
+            received_at: Utc::now(),
+            kind: TransferKind::Files,
+            text_preview: None,
+        });
+
+        let metadata = extract(&staging, &mut record).unwrap();
+        assert_eq!(record.files.len(), 1);
+        assert!(record.preserve_metadata);
+        assert!(!staging.join("project").join(FILE_NAME).exists());
+
+        // Plan before placing the file, as receiving does
+        let placement = plan(&metadata, &record, &download_dir);
+        fs::create_dir_all(download_dir.join("project/bin")).unwrap();
+        fs::write(download_dir.join("project/bin/run.sh"), "#!/bin/sh\n").unwrap();
+        apply(&metadata, &placement, &record);
+        let received = download_dir.join("project");
+        let run = fs::metadata(received.join("bin/run.sh")).unwrap();
+        assert_eq!(run.permissions().mode() & 0o7777, 0o644);
+        assert_eq!(DateTime::<Utc>::from(run.modified().unwrap()), modified);
+        assert!(received.join("empty").is_dir());
+        assert_eq!(
+            fs::read_link(received.join("run")).unwrap(),
+            PathBuf::from("bin/run.sh")
+        );
+        assert!(fs::symlink_metadata(received.join("bin/passwd")).is_err());
+
+        // Directories that existed already are left alone
+        fs::set_permissions(
+            received.join("bin/run.sh"),
+            fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+        let placement = plan(&metadata, &record, &download_dir);
+        assert!(placement.created.values().all(|created| created.is_empty()));
+        apply(&metadata, &placement, &record);
+        let run = fs::metadata(received.join("bin/run.sh")).unwrap();
+        assert_eq!(run.permissions().mode() & 0o7777, 0o600);
+
+        // Routed files take their directories along
+        let routed = dir.join("routed");
+        record.routes.push(FileRoute {
+            name: "project/bin/run.sh".to_string(),
+            rule: "scripts".to_string(),
+            directory: routed.clone(),
+            conflict_policy: None,
+            path: None,
+        });
+        let placement = plan(&metadata, &record, &download_dir);
+        assert!(placement.contains(&routed.join("project/bin")));
+        assert!(placement.contains(&routed.join("project/empty")));
+        assert!(!placement.contains(&received.join("empty")));
+
+        drop(selection);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - System notifications
+//
+// Each kind of notification can be switched off in settings, and none are
+// shown during quiet hours. Texts are built from the history record so they
+// name the files, the peer and the direction of the transfer.
+
+use crate::commands::AppState;
+use crate::events::AppEvent;
+use crate::types::{
+    NotificationSettings, QuietHours, TransferDirection, TransferKind, TransferRecord,
+    TransferStatus,
+};
+use chrono::{Local, NaiveTime, Utc};
+use tauri::{AppHandle, Manager};
+
+/// Kinds of notification that can be toggled in settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationKind {
+    IncomingRequest,
+    TransferComplete,
+    TransferFailed,
+    TransferRejected,
+    TransferRetry,
+    PortChanged,
+}
+
+/// Whether a notification of `kind` may be shown at local time `now`
+pub fn is_allowed(settings: &NotificationSettings, kind: NotificationKind, now: NaiveTime) -> bool {
+    let enabled = match kind {
+        NotificationKind::IncomingRequest => settings.incoming_request,
+        NotificationKind::TransferComplete => settings.transfer_complete,
+        NotificationKind::TransferFailed => settings.transfer_failed,
+        NotificationKind::TransferRejected => settings.transfer_rejected,
+        NotificationKind::TransferRetry => settings.transfer_retry,
+        NotificationKind::PortChanged => settings.port_changed,
+    };
+
+    enabled && !in_quiet_hours(&settings.quiet_hours, now)
+}
+
+/// Whether `now` falls within the quiet hours
+///
+/// Unparseable times disable quiet hours rather than silencing everything.
+fn in_quiet_hours(quiet_hours: &QuietHours, now: NaiveTime) -> bool {
+    if !quiet_hours.enabled {
+        return false;
+    }
+
+    in_daily_period(&quiet_hours.start, &quiet_hours.end, now)
+}
+
+/// Whether `now` falls between two "HH:MM" times, the period running past
+/// midnight if `end` is before `start`
+///
+/// False if either time cannot be parsed.
+pub(crate) fn in_daily_period(start: &str, end: &str, now: NaiveTime) -> bool {
+    let parse = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").ok();
+    match (parse(start), parse(end)) {
+        (Some(start), Some(end)) if start <= end => now >= start && now < end,
+        (Some(start), Some(end)) => now >= start || now < end,
+        _ => false,
+    }
+}
+
+/// Show a notification if settings and quiet hours allow it
+pub async fn notify(app_handle: &AppHandle, kind: NotificationKind, title: &str, body: &str) {
+    use tauri_plugin_notification::NotificationExt;
+
+    let state = app_handle.state::<AppState>();
+    let allowed = is_allowed(
+        &state.settings.read().await.notifications,
+        kind,
+        Local::now().time(),
+    );
+    if !allowed {
+        return;
+    }
+
+    if let Err(e) = app_handle
+        .notification()
+        .builder()
+        .title(title)
+        .body(body)
+        .show()
+    {
+        tracing::warn!("Failed to show notification: {}", e);
+    }
+}
+
+/// Show the notification for a published event, if any
+///
+/// Outgoing sends are notified by the send commands, since engine events
+/// for them are not keyed by a history record.
+pub async fn handle_event(app_handle: AppHandle, event: AppEvent) {
+    match event {
+        AppEvent::TransferRequest {
+            transfer,
+            held_reason,
+            auto_accepted: false,
+        } => {
+            let record = received_record(&app_handle, &transfer.id)
+                .unwrap_or_else(|| TransferRecord::incoming(&transfer));
+            let mut body = request_body(&record);
+            if let Some(reason) = held_reason {
+                body = format!("{}\n{}", body, reason);
+            }
+            notify(
+                &app_handle,
+                NotificationKind::IncomingRequest,
+                "Incoming Transfer",
+                &body,
+            )
+            .await;
+        }
+        AppEvent::TransferRejected { transfer_id, .. } => {
+            if let Some(record) = received_record(&app_handle, &transfer_id) {
+                notify_finished(&app_handle, &record).await;
+            }
+        }
+        AppEvent::TransferComplete { transfer_id } => {
+            if let Some(mut record) = received_record(&app_handle, &transfer_id) {
+                // History is updated by another subscriber; don't wait for it
+                record.status = TransferStatus::Completed;
+                record.completed_at.get_or_insert_with(Utc::now);
+                notify_finished(&app_handle, &record).await;
+            }
+        }
+        AppEvent::TransferFailed { transfer_id, error } => {
+            if let Some(mut record) = received_record(&app_handle, &transfer_id) {
+                record.status = TransferStatus::Failed;
+                record.error = Some(error);
+                notify_finished(&app_handle, &record).await;
+            }
+        }
+        AppEvent::TransferRetry {
+            attempt,
+            max_attempts,
+            error,
+            ..
+        } => {
+            notify(
+                &app_handle,
+                NotificationKind::TransferRetry,
+                "Retrying Transfer",
+                &format!("Attempt {} of {}: {}", attempt, max_attempts, error),
+            )
+            .await;
+        }
+        AppEvent::PortChanged { old_port, new_port } => {
+            notify(
+                &app_handle,
+                NotificationKind::PortChanged,
+                "Port Changed",
+                &format!("Now listening on port {} (was {})", new_port, old_port),
+            )
+            .await;
+        }
+        _ => {}
+    }
+}
+
+/// Look up the history record of a transfer received from a peer
+fn received_record(app_handle: &AppHandle, transfer_id: &str) -> Option<TransferRecord> {
+    app_handle
+        .state::<AppState>()
+        .history_store
+        .get(transfer_id)
+        .filter(|record| record.direction == TransferDirection::Received)
+}
+
+/// Notify about a transfer that has finished, failed or been rejected
+pub async fn notify_finished(app_handle: &AppHandle, record: &TransferRecord) {
+    let (kind, title) = match record.status {
+        TransferStatus::Completed => (NotificationKind::TransferComplete, "Transfer Complete"),
+        TransferStatus::Failed => (NotificationKind::TransferFailed, "Transfer Failed"),
+        TransferStatus::Rejected => (NotificationKind::TransferRejected, "Transfer Rejected"),
+        _ => return,
+    };
+
+    notify(app_handle, kind, title, &finished_body(record)).await;
+}
+
+/// Describe a finished transfer, e.g. "Received report.pdf (1.2 MB) from Laptop in 3s"
+pub fn finished_body(record: &TransferRecord) -> String {
+    let files = describe_files(record);
+    let peer = peer_label(record);
+    let reason = record.error.as_deref().unwrap_or("unknown error");
+
+    match (record.direction, &record.status) {
+        (TransferDirection::Received, TransferStatus::Completed) => {
+            format!("Received {} from {}{}", files, peer, duration(record))
+        }
+        (TransferDirection::Sent, TransferStatus::Completed) => {
+            format!("Sent {} to {}{}", files, peer, duration(record))
+        }
+        (TransferDirection::Received, TransferStatus::Rejected) => {
+            format!("Rejected {} from {}: {}", files, peer, reason)
+        }
+        (TransferDirection::Received, _) => {
+            format!("Receiving {} from {} failed: {}", files, peer, reason)
+        }
+        (TransferDirection::Sent, _) => {
+            format!("Sending {} to {} failed: {}", files, peer, reason)
+        }
+    }
+}
+
+/// Describe an incoming request, e.g. "Laptop wants to send you 3 files (4.0 MB)"
+pub fn request_body(record: &TransferRecord) -> String {
+    format!(
+        "{} wants to send you {}",
+        peer_label(record),
+        describe_files(record)
+    )
+}
+
+/// Name the peer of a transfer, preferring its device name
+pub fn peer_label(record: &TransferRecord) -> &str {
+    record.peer_name.as_deref().unwrap_or(&record.peer_address)
+}
+
+/// "report.pdf (1.2 MB)" for one file, "3 files (4.0 MB)" for several
+pub fn describe_files(record: &TransferRecord) -> String {
+    if record.kind == TransferKind::Text {
+        return "a text message".to_string();
+    }
+    let size = format_size(record.total_size);
+    match record.files.as_slice() {
+        [file] => format!("{} ({})", file.name, size),
+        files => format!("{} files ({})", files.len(), size),
+    }
+}
+
+/// Human-readable byte count
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// " in 1m 5s", or nothing if the transfer has not finished
+fn duration(record: &TransferRecord) -> String {
+    let secs = match record.duration() {
+        Some(duration) => duration.num_seconds().max(0),
+        None => return String::new(),
+    };
+    match secs {
+        0 => " in under a second".to_string(),
+        s if s < 60 => format!(" in {}s", s),
+        s if s < 3600 => format!(" in {}m {}s", s / 60, s % 60),
+        s => format!(" in {}h {}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TransferFile;
+
+    #[test]
+    fn test_quiet_hours() {
+        let mut settings = NotificationSettings::default();
+        settings.quiet_hours.enabled = true;
+        let at = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let complete = |now| is_allowed(&settings, NotificationKind::TransferComplete, now);
+
+        assert!(!complete(at(23, 30)));
+        assert!(!complete(at(6, 59)));
+        assert!(complete(at(7, 0)));
+        assert!(!is_allowed(
+            &settings,
+            NotificationKind::TransferRetry,
+            at(12, 0)
+        ));
+    }
+
+    #[test]
+    fn test_finished_body() {
+        let mut record = TransferRecord::outgoing(
+            "192.168.1.20",
+            vec![TransferFile::new("f1", "report.pdf", 1_258_291)],
+        );
+        record.status = TransferStatus::Completed;
+        record.completed_at = Some(record.started_at + chrono::Duration::seconds(65));
+        assert_eq!(
+            finished_body(&record),
+            "Sent report.pdf (1.2 MB) to 192.168.1.20 in 1m 5s"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Per-file outcomes
+//
+// The engine reports progress for a whole transfer: total bytes so far and
+// the file being transferred. Each file's status and bytes are derived from
+// that here. Files are transferred one after another, so when the current
+// file changes, the files before it (in the transfer's order) and any file
+// seen earlier are complete. When the transfer ends, the file in progress
+// takes the outcome of the transfer and files never started are skipped.
+// A resumed file counts the bytes an interrupted transfer delivered as
+// transferred, while the engine only counts those it sends.
+
+use crate::compress;
+use crate::types::{FileStatus, TransferFile, TransferRecord, TransferStatus};
+
+/// Index of the file a progress update names
+///
+/// The engine may report a file by its name or by the last component of it,
+/// and names compressed files with the compression suffix.
+fn find_file(files: &[TransferFile], name: &str) -> Option<usize> {
+    let find = |name: &str| {
+        files.iter().position(|f| f.name == name).or_else(|| {
+            files
+                .iter()
+                .position(|f| f.name.rsplit('/').next() == Some(name))
+        })
+    };
+    find(name).or_else(|| find(compress::original_name(name)))
+}
+
+/// Apply a progress update to the files of `record`
+///
+/// Returns true if a file changed status, i.e. the update crossed a file
+/// boundary and is worth persisting.
+pub fn apply_progress(
+    record: &mut TransferRecord,
+    current_file: Option<&str>,
+    bytes_transferred: u64,
+) -> bool {
+    record.bytes_transferred = bytes_transferred;
+    let current = match current_file.and_then(|name| find_file(&record.files, name)) {
+        Some(index) => index,
+        None => return false,
+    };
+
+    let mut changed = false;
+    for (index, file) in record.files.iter_mut().enumerate() {
+        let done = index < current || file.status == Some(FileStatus::InProgress);
+        if index != current && done && file.status != Some(FileStatus::Completed) {
+            file.status = Some(FileStatus::Completed);
+            file.bytes_transferred = Some(file.size);
+            changed = true;
+        }
+    }
+
+    let done: u64 = record
+        .files
+        .iter()
+        .filter(|f| f.status == Some(FileStatus::Completed))
+        .map(|f| f.transfer_size())
+        .sum();
+    let file = &mut record.files[current];
+    if file.status != Some(FileStatus::InProgress) {
+        file.status = Some(FileStatus::InProgress);
+        changed = true;
+    }
+    let sent = bytes_transferred
+        .saturating_sub(done)
+        .min(file.transfer_size());
+    file.bytes_transferred = Some(file.offset.unwrap_or(0) + sent);
+    changed
+}
+
+/// Settle the files of a record that reached `status`
+///
+/// Completed transfers complete every file. Otherwise files not started are
+/// skipped, and the file in progress fails with `error` (or is skipped if
+/// the transfer was cancelled or rejected).
+pub fn finish(record: &mut TransferRecord, status: &TransferStatus, error: Option<&str>) {
+    for file in &mut record.files {
+        if *status == TransferStatus::Completed {
+            file.status = Some(FileStatus::Completed);
+            file.bytes_transferred = Some(file.size);
+            continue;
+        }
+
+        match file.status {
+            Some(FileStatus::InProgress) if *status == TransferStatus::Failed => {
+                file.status = Some(FileStatus::Failed);
+                file.error = error.map(str::to_string);
+            }
+            Some(FileStatus::InProgress) | None => file.status = Some(FileStatus::Skipped),
+            Some(FileStatus::Completed | FileStatus::Failed | FileStatus::Skipped) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: u64) -> TransferFile {
+        TransferFile::new(name, name, size)
+    }
+
+    fn record() -> TransferRecord {
+        TransferRecord::outgoing(
+            "10.0.0.2",
+            vec![
+                file("dir/a.txt", 10),
+                file("dir/b.txt", 10),
+                file("dir/c.txt", 10),
+                file("dir/d.txt", 10),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_apply_progress() {
+        let mut record = record();
+        assert!(apply_progress(&mut record, Some("dir/a.txt"), 5));
+        assert!(!apply_progress(&mut record, Some("dir/a.txt"), 8));
+        assert_eq!(record.files[0].bytes_transferred, Some(8));
+
+        // b.txt was never reported, but c.txt (named without its directory)
+        // comes after it
+        assert!(apply_progress(&mut record, Some("c.txt"), 24));
+        let statuses: Vec<_> = record.files.iter().map(|f| f.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                Some(FileStatus::Completed),
+                Some(FileStatus::Completed),
+                Some(FileStatus::InProgress),
+                None
+            ]
+        );
+        assert_eq!(record.files[2].bytes_transferred, Some(4));
+    }
+
+    #[test]
+    fn test_apply_progress_resumed() {
+        let mut record = record();
+        record.files[0].offset = Some(4);
+        apply_progress(&mut record, Some("dir/a.txt"), 3);
+        assert_eq!(record.files[0].bytes_transferred, Some(7));
+
+        // a.txt took 6 bytes of the transfer
+        apply_progress(&mut record, Some("dir/b.txt"), 8);
+        assert_eq!(record.files[0].bytes_transferred, Some(10));
+        assert_eq!(record.files[1].bytes_transferred, Some(2));
+    }
+
+    #[test]
+    fn test_finish_failed() {
+        let mut record = record();
+        apply_progress(&mut record, Some("dir/c.txt"), 24);
+        finish(
+            &mut record,
+            &TransferStatus::Failed,
+            Some("Connection reset"),
+        );
+        assert_eq!(record.files[1].status, Some(FileStatus::Completed));
+        assert_eq!(record.files[1].error, None);
+        assert_eq!(record.files[2].status, Some(FileStatus::Failed));
+        assert_eq!(record.files[2].error.as_deref(), Some("Connection reset"));
+        assert_eq!(record.files[3].status, Some(FileStatus::Skipped));
+    }
+
+    #[test]
+    fn test_finish_completed() {
+        let mut record = record();
+        apply_progress(&mut record, Some("dir/b.txt"), 12);
+        finish(&mut record, &TransferStatus::Completed, None);
+        assert!(record
+            .files
+            .iter()
+            .all(|f| f.status == Some(FileStatus::Completed)));
+    }
+}
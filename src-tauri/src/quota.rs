@@ -0,0 +1,317 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Receive limits
+//
+// Incoming transfers are checked against the per-transfer limit, the daily
+// quota and the free space in the download directory before they are accepted.
+// The daily quota is kept in a ledger of its own (`quota.json`): accepting a
+// transfer reserves its size for the day, and a transfer that ends early
+// gives back what it did not receive. Clearing or trimming history does not
+// touch it.
+
+use crate::types::{AppError, ReceiveLimits};
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Check an incoming transfer of `size` bytes against the configured limits
+///
+/// `received_today` is the number of bytes already accepted today and
+/// `available` the free space in the download directory.
+pub fn check_incoming(
+    limits: &ReceiveLimits,
+    size: u64,
+    received_today: u64,
+    available: u64,
+) -> Result<(), AppError> {
+    if let Some(limit) = limits.max_transfer_bytes {
+        if size > limit {
+            return Err(AppError::TransferTooLarge { size, limit });
+        }
+    }
+
+    if let Some(limit) = limits.daily_quota_bytes {
+        if received_today.saturating_add(size) > limit {
+            return Err(AppError::DailyQuotaExceeded {
+                size,
+                used: received_today,
+                limit,
+            });
+        }
+    }
+
+    let required = size.saturating_add(limits.min_free_space_bytes);
+    if required > available {
+        return Err(AppError::InsufficientSpace {
+            required,
+            available,
+        });
+    }
+
+    Ok(())
+}
+
+/// Most bytes a received transfer of `size` bytes may take up once its
+/// files are decompressed, within the configured limits
+///
+/// `used_today` includes the transfer's own reservation.
+pub fn unpack_limit(limits: &ReceiveLimits, size: u64, used_today: u64, available: u64) -> u64 {
+    let mut limit = size.saturating_add(available.saturating_sub(limits.min_free_space_bytes));
+    if let Some(max) = limits.max_transfer_bytes {
+        limit = limit.min(max);
+    }
+    if let Some(quota) = limits.daily_quota_bytes {
+        limit = limit.min(quota.saturating_sub(used_today).saturating_add(size));
+    }
+    limit
+}
+
+/// Get the free space available to the user in the file system holding `dir`
+///
+/// The download directory may not exist yet, so the closest existing
+/// ancestor is queried instead.
+pub fn available_space(dir: &Path) -> Result<u64, AppError> {
+    let existing = dir
+        .ancestors()
+        .find(|p| p.exists())
+        .ok_or_else(|| AppError::FileIo(format!("Download directory not found: {:?}", dir)))?;
+
+    fs4::available_space(existing)
+        .map_err(|e| AppError::FileIo(format!("Failed to query free space: {}", e)))
+}
+
+/// Bytes reserved against the daily quota, persisted to disk on changes
+pub struct QuotaLedger {
+    inner: Mutex<LedgerFile>,
+    file_path: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LedgerFile {
+    /// Local calendar day the reservations count towards
+    day: Option<NaiveDate>,
+    /// Bytes reserved per accepted transfer
+    reservations: BTreeMap<String, u64>,
+}
+
+impl LedgerFile {
+    /// Start afresh once the day is over
+    fn roll_over(&mut self) {
+        let today = Local::now().date_naive();
+        if self.day != Some(today) {
+            self.day = Some(today);
+            self.reservations.clear();
+        }
+    }
+
+    fn used(&self) -> u64 {
+        self.reservations.values().sum()
+    }
+}
+
+impl QuotaLedger {
+    /// Open the ledger in the config directory
+    pub fn new() -> Result<Self, AppError> {
+        let config_dir = directories::ProjectDirs::from("com", "gosh", "transfer")
+            .ok_or_else(|| AppError::FileIo("Could not determine config directory".to_string()))?
+            .config_dir()
+            .to_path_buf();
+
+        fs::create_dir_all(&config_dir)
+            .map_err(|e| AppError::FileIo(format!("Failed to create config dir: {}", e)))?;
+
+        Self::open(config_dir.join("quota.json"))
+    }
+
+    /// Open the ledger at `file_path`, starting empty if it does not exist
+    /// or cannot be parsed
+    pub fn open(file_path: PathBuf) -> Result<Self, AppError> {
+        let ledger = if file_path.exists() {
+            let content = fs::read_to_string(&file_path)
+                .map_err(|e| AppError::FileIo(format!("Failed to read quota: {}", e)))?;
+            serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse quota, starting fresh: {}", e);
+                LedgerFile::default()
+            })
+        } else {
+            LedgerFile::default()
+        };
+
+        Ok(Self {
+            inner: Mutex::new(ledger),
+            file_path,
+        })
+    }
+
+    fn persist(&self, ledger: &LedgerFile) {
+        let result = serde_json::to_string_pretty(ledger)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(&self.file_path, content).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            tracing::warn!("Failed to write quota: {}", e);
+        }
+    }
+
+    /// Bytes reserved today
+    pub fn used_today(&self) -> u64 {
+        let mut ledger = self.inner.lock().unwrap();
+        ledger.roll_over();
+        ledger.used()
+    }
+
+    /// Reserve `size` bytes of today's quota for an accepted transfer,
+    /// unless that would exceed `limit`
+    ///
+    /// Reserving again for the same transfer changes nothing.
+    pub fn reserve(
+        &self,
+        transfer_id: &str,
+        size: u64,
+        limit: Option<u64>,
+    ) -> Result<(), AppError> {
+        let mut ledger = self.inner.lock().unwrap();
+        ledger.roll_over();
+        if ledger.reservations.contains_key(transfer_id) {
+            return Ok(());
+        }
+
+        let used = ledger.used();
+        if let Some(limit) = limit {
+            if used.saturating_add(size) > limit {
+                return Err(AppError::DailyQuotaExceeded { size, used, limit });
+            }
+        }
+        ledger.reservations.insert(transfer_id.to_string(), size);
+        self.persist(&ledger);
+        Ok(())
+    }
+
+    /// Give back what a transfer that ended early did not receive
+    pub fn settle(&self, transfer_id: &str, received: u64) {
+        let mut ledger = self.inner.lock().unwrap();
+        ledger.roll_over();
+        if let Some(reserved) = ledger.reservations.get_mut(transfer_id) {
+            if received < *reserved {
+                *reserved = received;
+                self.persist(&ledger);
+            }
+        }
+    }
+
+    /// Raise a transfer's reservation to `size` bytes, once its files were
+    /// decompressed or its archives extracted
+    pub fn grow(&self, transfer_id: &str, size: u64) {
+        let mut ledger = self.inner.lock().unwrap();
+        ledger.roll_over();
+        if let Some(reserved) = ledger.reservations.get_mut(transfer_id) {
+            if size > *reserved {
+                *reserved = size;
+                self.persist(&ledger);
+            }
+        }
+    }
+
+    /// Drop the reservation of a transfer the engine did not accept after all
+    pub fn release(&self, transfer_id: &str) {
+        let mut ledger = self.inner.lock().unwrap();
+        if ledger.reservations.remove(transfer_id).is_some() {
+            self.persist(&ledger);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ledger_path() -> PathBuf {
+        std::env::temp_dir().join(format!("gosh-quota-{}.json", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_check_incoming_limits() {
+        let limits = ReceiveLimits {
+            max_transfer_bytes: Some(1000),
+            daily_quota_bytes: Some(1500),
+            min_free_space_bytes: 100,
+            ..Default::default()
+        };
+
+        assert!(check_incoming(&limits, 500, 0, 10_000).is_ok());
+        assert!(matches!(
+            check_incoming(&limits, 1001, 0, 10_000),
+            Err(AppError::TransferTooLarge { .. })
+        ));
+        assert!(matches!(
+            check_incoming(&limits, 600, 1000, 10_000),
+            Err(AppError::DailyQuotaExceeded { used: 1000, .. })
+        ));
+        assert!(matches!(
+            check_incoming(&limits, 500, 0, 550),
+            Err(AppError::InsufficientSpace {
+                required: 600,
+                available: 550
+            })
+        ));
+
+        assert_eq!(unpack_limit(&limits, 500, 700, 10_000), 1000);
+        assert_eq!(unpack_limit(&limits, 500, 1200, 10_000), 800);
+        assert_eq!(unpack_limit(&limits, 500, 700, 550), 950);
+    }
+
+    #[test]
+    fn test_ledger_reserves_within_limit() {
+        let path = ledger_path();
+        let ledger = QuotaLedger::open(path.clone()).unwrap();
+        ledger.reserve("a", 600, Some(1000)).unwrap();
+        ledger.reserve("a", 600, Some(1000)).unwrap();
+        assert!(matches!(
+            ledger.reserve("b", 600, Some(1000)),
+            Err(AppError::DailyQuotaExceeded { used: 600, .. })
+        ));
+        ledger.reserve("b", 600, None).unwrap();
+
+        // Reservations survive a restart
+        let reopened = QuotaLedger::open(path.clone()).unwrap();
+        assert_eq!(reopened.used_today(), 1200);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ledger_settles_and_releases() {
+        let path = ledger_path();
+        let ledger = QuotaLedger::open(path.clone()).unwrap();
+        ledger.reserve("a", 600, None).unwrap();
+        ledger.reserve("b", 400, None).unwrap();
+
+        ledger.settle("a", 100);
+        ledger.release("b");
+        assert_eq!(ledger.used_today(), 100);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ledger_rolls_over_at_midnight() {
+        let path = ledger_path();
+        fs::write(&path, r#"{"day":"2020-01-01","reservations":{"old":5000}}"#).unwrap();
+
+        let ledger = QuotaLedger::open(path.clone()).unwrap();
+        assert_eq!(ledger.used_today(), 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_ledger_starts_fresh_when_corrupt() {
+        let path = ledger_path();
+        fs::write(&path, "{\"day\":").unwrap();
+
+        let ledger = QuotaLedger::open(path.clone()).unwrap();
+        assert_eq!(ledger.used_today(), 0);
+        ledger.reserve("a", 100, None).unwrap();
+        assert_eq!(QuotaLedger::open(path.clone()).unwrap().used_today(), 100);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,1155 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Receive pipeline
+//
+// Incoming transfer requests are screened (receive filter, receive limits,
+// trusted-host auto-accept) before the user sees them. The engine writes
+// received files into a staging directory per transfer, receiving one
+// transfer at a time (see `staging`); once a transfer completes, files
+// are quarantined or moved to their final destination here, applying routing
+// rules and the name-conflict policy (restoring the metadata of directories
+// sent with it), and post-receive hooks are run. Files resumed from an
+// interrupted transfer are joined to its parts first, and a failed transfer
+// keeps the files it received in part for resuming (see `resume`). Text
+// messages are kept in history instead, and copied to the clipboard if set
+// up to.
+
+use crate::commands::AppState;
+use crate::events::{self, AppEvent, TransferProgress};
+use crate::hooks::{self, HookContext};
+use crate::types::{
+    AppError, AppSettings, ConflictPolicy, ConflictResolution, FileConflict, FileStatus,
+    FilterAction, OverLimitAction, PendingTransfer, QuarantinedFile, TransferDirection,
+    TransferKind, TransferRecord, TransferStatus,
+};
+use crate::{
+    checksum, compress, conflict, filter, metadata, outcome, quota, resume, sidecar, staging, text,
+};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tauri::{AppHandle, Manager};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+/// Error recorded for transfers cut short by the app closing
+const INTERRUPTED: &str = "Interrupted: the app was closed during the transfer";
+
+/// Bytes after which the progress of a file is written to history
+const PROGRESS_STEP: u64 = 64 * 1024 * 1024;
+
+/// Outcome of screening an incoming transfer request
+enum IncomingDecision {
+    /// Accepted automatically (trusted host within limits)
+    AutoAccepted,
+    /// Rejected automatically by the receive filter or a receive limit
+    Rejected(AppError),
+    /// Waiting for the user, optionally held because a limit was exceeded
+    NeedsApproval(Option<AppError>),
+}
+
+/// Reject an incoming transfer on the user's behalf and record the reason
+///
+/// The reason cannot be sent back: the engine's rejection carries none, so
+/// the sender sees the same rejection as when the user declines. It is kept
+/// in history and published in the `transfer-rejected` event on this side.
+async fn reject_incoming(state: &AppState, transfer_id: &str, reason: &AppError) {
+    tracing::info!("Rejecting transfer {}: {}", transfer_id, reason);
+    let engine = state.engine.lock().await;
+    if let Err(e) = engine.reject_transfer(transfer_id).await {
+        tracing::warn!("Failed to reject transfer {}: {}", transfer_id, e);
+    }
+    drop(engine);
+    let _ = state.history_store.set_status(
+        transfer_id,
+        TransferStatus::Rejected,
+        Some(reason.to_string()),
+    );
+}
+
+/// Record an incoming transfer in history and apply the receive filter,
+/// receive limits and trusted-host auto-accept
+async fn screen_incoming(app_handle: &AppHandle, transfer: &PendingTransfer) -> IncomingDecision {
+    let state = app_handle.state::<AppState>();
+    let settings = state.settings.read().await.clone();
+
+    if let Err(e) = state.history_store.add(TransferRecord::incoming(transfer)) {
+        tracing::warn!("Failed to record transfer {}: {}", transfer.id, e);
+    }
+    if settings.receive_filter.action == FilterAction::Block {
+        if let Some(reason) = filter::find_blocked(&settings.receive_filter, &transfer.files) {
+            reject_incoming(&state, &transfer.id, &reason).await;
+            return IncomingDecision::Rejected(reason);
+        }
+    }
+
+    let available = quota::available_space(&settings.download_dir).unwrap_or_else(|e| {
+        tracing::warn!("Skipping free space check: {}", e);
+        u64::MAX
+    });
+    let limits = &settings.receive_limits;
+    let verdict = quota::check_incoming(
+        limits,
+        transfer.total_size,
+        state.quota.used_today(),
+        available,
+    )
+    .and_then(|()| {
+        if !settings.trusted_hosts.contains(&transfer.source_ip) {
+            return Ok(());
+        }
+        // Another transfer may have been accepted since; reserve now
+        state
+            .quota
+            .reserve(&transfer.id, transfer.total_size, limits.daily_quota_bytes)
+    });
+
+    match verdict {
+        Err(reason) if limits.over_limit_action == OverLimitAction::Reject => {
+            reject_incoming(&state, &transfer.id, &reason).await;
+            IncomingDecision::Rejected(reason)
+        }
+        Err(reason) => {
+            tracing::info!("Holding transfer {} for approval: {}", transfer.id, reason);
+            IncomingDecision::NeedsApproval(Some(reason))
+        }
+        Ok(()) if settings.trusted_hosts.contains(&transfer.source_ip) => {
+            match accept_incoming(&state, &transfer.id).await {
+                Ok(()) => IncomingDecision::AutoAccepted,
+                Err(e) => {
+                    tracing::warn!("Failed to auto-accept transfer {}: {}", transfer.id, e);
+                    IncomingDecision::NeedsApproval(None)
+                }
+            }
+        }
+        Ok(()) => IncomingDecision::NeedsApproval(None),
+    }
+}
+
+/// Accept an incoming transfer into a staging directory of its own,
+/// reserving its size against the daily quota
+///
+/// If its files would be written where those of another accepted transfer
+/// are, this one waits and is accepted by the engine once that one ends.
+pub async fn accept_incoming(state: &AppState, transfer_id: &str) -> Result<(), AppError> {
+    let dir = {
+        let settings = state.settings.read().await;
+        staging::transfer_dir(&settings.download_dir, transfer_id)
+    };
+    let record = state.history_store.get(transfer_id);
+    if let Some(record) = &record {
+        // Accepted by the user, so even over the quota
+        state.quota.reserve(transfer_id, record.total_size, None)?;
+    }
+    state.record_accepted(transfer_id, &dir).await;
+    let names = record
+        .iter()
+        .flat_map(|r| r.files.iter().map(|f| f.name.as_str()));
+    if !state.receive_queue.claim(transfer_id, dir.clone(), names) {
+        tracing::info!(
+            "Transfer {} waits for a receive of the same names",
+            transfer_id
+        );
+        return Ok(());
+    }
+
+    if let Err(e) = start_receive(state, transfer_id, &dir).await {
+        state.record_accept_failed(transfer_id);
+        state.quota.release(transfer_id);
+        finish_receive(state, transfer_id).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Point the engine at the shared receiving directory of a transfer staged
+/// in `dir` and accept it, and record when it was accepted
+async fn start_receive(state: &AppState, transfer_id: &str, dir: &Path) -> Result<(), AppError> {
+    let receiving = staging::receiving_dir(dir);
+    std::fs::create_dir_all(&receiving)
+        .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", receiving, e)))?;
+    let settings = state.settings.read().await;
+    let port = state.incoming_relay.engine_port(settings.port);
+    let config = crate::build_engine_config(&settings, &receiving, port);
+    drop(settings);
+
+    let mut engine = state.engine.lock().await;
+    engine.update_config(config).await;
+    engine
+        .accept_transfer(transfer_id)
+        .await
+        .map_err(|e| AppError::Network(e.to_string()))?;
+    drop(engine);
+
+    // Durations are measured from here, not from the request
+    let _ = state.history_store.update(transfer_id, |record| {
+        record.accepted_at = Some(chrono::Utc::now());
+    });
+    Ok(())
+}
+
+/// Once a received transfer ended, move what it received into its staging
+/// directory, give back the quota it did not use and start the waiting
+/// transfers its names held up
+///
+/// Does nothing for transfers that are not being received.
+pub async fn finish_receive(state: &AppState, transfer_id: &str) {
+    let record = state.history_store.get(transfer_id);
+    if let Some(record) = &record {
+        if record.status != TransferStatus::Completed {
+            state.quota.settle(transfer_id, record.bytes_transferred);
+        }
+        if let (true, Some(dir)) = (
+            state.receive_queue.is_receiving(transfer_id),
+            &record.staging_dir,
+        ) {
+            staging::collect(dir, record.files.iter().map(|f| f.name.as_str()));
+        }
+    }
+
+    let mut next: VecDeque<_> = state.receive_queue.finish(transfer_id).into();
+    while let Some((id, dir)) = next.pop_front() {
+        if let Err(e) = start_receive(state, &id, &dir).await {
+            tracing::warn!("Failed to accept waiting transfer {}: {}", id, e);
+            next.extend(state.receive_queue.finish(&id));
+            state.publish(AppEvent::TransferFailed {
+                transfer_id: id,
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+/// Screen an incoming transfer request and publish the outcome
+///
+/// Auto-accepted requests are still published (for webhooks) but are not
+/// shown to the user.
+pub async fn handle_incoming(app_handle: AppHandle, transfer: PendingTransfer) {
+    let decision = screen_incoming(&app_handle, &transfer).await;
+    let event = match decision {
+        IncomingDecision::AutoAccepted => AppEvent::TransferRequest {
+            transfer,
+            held_reason: None,
+            auto_accepted: true,
+        },
+        IncomingDecision::Rejected(reason) => AppEvent::TransferRejected {
+            transfer_id: transfer.id,
+            reason: reason.to_string(),
+        },
+        IncomingDecision::NeedsApproval(held) => AppEvent::TransferRequest {
+            transfer,
+            held_reason: held.map(|reason| reason.to_string()),
+            auto_accepted: false,
+        },
+    };
+
+    app_handle.state::<AppState>().publish(event);
+}
+
+/// Bring tracked requests back in line with the engine after events were lost
+///
+/// Requests the engine no longer holds are dropped from the snapshot, and
+/// requests whose event was missed are screened as if just received.
+pub async fn resync_pending(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let pending: Vec<PendingTransfer> = state
+        .engine
+        .lock()
+        .await
+        .get_pending_transfers()
+        .await
+        .iter()
+        .map(|t| {
+            state
+                .incoming_relay
+                .restore_source(events::pending_from_engine(t))
+        })
+        .collect();
+
+    let ids: Vec<String> = pending.iter().map(|t| t.id.clone()).collect();
+    state.tracker.retain_pending(&ids);
+
+    for transfer in pending {
+        if state.tracker.get(&transfer.id).is_none() {
+            tracing::info!("Recovering missed transfer request {}", transfer.id);
+            state.tracker.apply(&AppEvent::TransferRequest {
+                transfer: transfer.clone(),
+                held_reason: None,
+                auto_accepted: false,
+            });
+            tauri::async_runtime::spawn(handle_incoming(app_handle.clone(), transfer));
+        }
+    }
+}
+
+/// Record the outcome of incoming transfers and per-file progress in
+/// history, and post-process completed transfers
+pub async fn handle_event(app_handle: AppHandle, event: AppEvent) {
+    let state = app_handle.state::<AppState>();
+    match event {
+        AppEvent::TransferProgress { progress } => record_progress(&state, &progress),
+        AppEvent::TransferComplete { transfer_id } => {
+            record_complete(&app_handle, transfer_id).await
+        }
+        AppEvent::TransferFailed { transfer_id, error } => {
+            record_failed(&app_handle, transfer_id, error).await
+        }
+        AppEvent::EventsLagged { .. } => resync_history(&app_handle).await,
+        _ => {}
+    }
+}
+
+/// Record a completed transfer and post-process it
+///
+/// Outcomes already recorded (e.g. replayed after lagging) are skipped.
+async fn record_complete(app_handle: &AppHandle, transfer_id: String) {
+    let state = app_handle.state::<AppState>();
+    if state
+        .history_store
+        .get(&transfer_id)
+        .is_some_and(|r| is_terminal(&r.status))
+    {
+        return;
+    }
+
+    let _ = state
+        .history_store
+        .set_status(&transfer_id, TransferStatus::Completed, None);
+    finish_receive(&state, &transfer_id).await;
+    // Hooks may run for a while; don't hold up later events
+    tauri::async_runtime::spawn(process_received(app_handle.clone(), transfer_id));
+}
+
+/// Record a failed transfer, delivering what an incoming one received
+/// complete
+///
+/// A completed transfer fails when its files do not verify afterwards.
+async fn record_failed(app_handle: &AppHandle, transfer_id: String, error: String) {
+    let state = app_handle.state::<AppState>();
+    let status = state
+        .history_store
+        .get(&transfer_id)
+        .map(|record| (record.direction, record.status));
+    if matches!(
+        status,
+        Some((
+            _,
+            TransferStatus::Failed | TransferStatus::Cancelled | TransferStatus::Rejected
+        ))
+    ) {
+        return;
+    }
+
+    let _ = state
+        .history_store
+        .set_status(&transfer_id, TransferStatus::Failed, Some(error));
+    finish_receive(&state, &transfer_id).await;
+    if status == Some((TransferDirection::Received, TransferStatus::InProgress)) {
+        tauri::async_runtime::spawn(process_received(app_handle.clone(), transfer_id));
+    }
+}
+
+/// Catch history up on outcomes missed while this subscriber lagged
+///
+/// The tracker is updated as events are published, so a received transfer
+/// it no longer lists has ended; its outcome is taken from the tracker.
+async fn resync_history(app_handle: &AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let open: Vec<String> = state
+        .history_store
+        .list()
+        .into_iter()
+        .filter(|r| r.direction == TransferDirection::Received && !is_terminal(&r.status))
+        .map(|r| r.id)
+        .collect();
+
+    for id in open {
+        if state.tracker.get(&id).is_some() {
+            continue;
+        }
+        match state.tracker.outcome(&id) {
+            Some(AppEvent::TransferComplete { transfer_id }) => {
+                tracing::info!("Recovering missed completion of {}", transfer_id);
+                record_complete(app_handle, transfer_id).await;
+            }
+            Some(AppEvent::TransferFailed { transfer_id, error }) => {
+                tracing::info!("Recovering missed failure of {}", transfer_id);
+                record_failed(app_handle, transfer_id, error).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Whether a transfer has ended
+fn is_terminal(status: &TransferStatus) -> bool {
+    !matches!(status, TransferStatus::Pending | TransferStatus::InProgress)
+}
+
+/// Close out transfers a previous run left pending or in progress
+///
+/// The engine does not keep requests or connections across restarts, so
+/// they are marked failed, the files received complete are delivered and
+/// those received in part are kept for resuming. Whatever else is left in
+/// staging is removed, as are the parts of transfers no longer in history.
+pub async fn recover_interrupted(app_handle: AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let interrupted = state.history_store.list().into_iter().filter(|r| {
+        matches!(
+            r.status,
+            TransferStatus::Pending | TransferStatus::InProgress
+        )
+    });
+
+    for record in interrupted {
+        tracing::info!("Transfer {} was interrupted", record.id);
+        let _ = state.history_store.set_status(
+            &record.id,
+            TransferStatus::Failed,
+            Some(INTERRUPTED.to_string()),
+        );
+        if record.direction == TransferDirection::Received
+            && record.status == TransferStatus::InProgress
+        {
+            // Transfers still waiting for a clash to end received nothing
+            if let (Some(_), Some(dir)) = (record.accepted_at, &record.staging_dir) {
+                staging::collect(dir, record.files.iter().map(|f| f.name.as_str()));
+            }
+            process_received(app_handle.clone(), record.id).await;
+        }
+    }
+
+    let mut keep: Vec<PathBuf> = state
+        .history_store
+        .list()
+        .into_iter()
+        .flat_map(|record| record.conflicts)
+        .filter(|c| c.resolution == ConflictResolution::Pending)
+        .map(|c| c.path)
+        .collect();
+    keep.extend(state.receive_queue.dirs());
+    let root = staging::staging_root(&state.settings.read().await.download_dir);
+    keep.push(resume::dir(&root));
+    staging::remove_stale(&root, &keep);
+
+    let partial: HashSet<String> = state
+        .history_store
+        .list()
+        .into_iter()
+        .filter(|record| !record.partial_files.is_empty())
+        .map(|record| record.id)
+        .collect();
+    resume::retain(&resume::dir(&root), &partial);
+}
+
+/// Update the per-file outcomes of a running transfer
+///
+/// History is written when the transfer moves to another file, and every
+/// `PROGRESS_STEP` bytes, which is how far back a send cut short by the app
+/// closing resumes from.
+fn record_progress(state: &AppState, progress: &TransferProgress) {
+    // Sends are recorded under the app's ID, not the engine's
+    let id = state
+        .tracker
+        .get(&progress.transfer_id)
+        .and_then(|t| t.record_id)
+        .unwrap_or_else(|| progress.transfer_id.clone());
+    let mut record = match state.history_store.get(&id) {
+        Some(record) if record.status == TransferStatus::InProgress => record,
+        _ => return,
+    };
+
+    let current_file = progress.current_file.as_deref();
+    let stored = record.bytes_transferred;
+    if outcome::apply_progress(&mut record, current_file, progress.bytes_transferred)
+        || progress.bytes_transferred >= stored + PROGRESS_STEP
+    {
+        let _ = state.history_store.update(&id, |stored| {
+            if stored.status == TransferStatus::InProgress {
+                stored.files = record.files;
+                stored.bytes_transferred = record.bytes_transferred;
+            }
+        });
+    }
+}
+
+/// Post-process a completed incoming transfer: quarantine files matching
+/// the receive filter, move the rest from staging to their destination, then
+/// run the post-receive hooks
+///
+/// Of a failed or cancelled transfer, only the files that arrived complete
+/// are delivered, and no hooks are run. The transfer's staging directory is
+/// removed once no file in it waits for a name conflict to be resolved.
+pub async fn process_received(app_handle: AppHandle, transfer_id: String) {
+    let state = app_handle.state::<AppState>();
+    let record = match state.history_store.get(&transfer_id) {
+        Some(record) if record.direction == TransferDirection::Received => record,
+        _ => return,
+    };
+    let settings = state.settings.read().await.clone();
+    // Transfers accepted before staging was per transfer share the root
+    let staging = record
+        .staging_dir
+        .clone()
+        .unwrap_or_else(|| staging::staging_root(&settings.download_dir));
+    let failed = record.status != TransferStatus::Completed;
+    if record.kind == TransferKind::Text {
+        match failed {
+            true => discard_incomplete(&staging, &record),
+            false => receive_text(&app_handle, &settings, &staging, record.clone()),
+        }
+        return remove_staging(&record);
+    }
+
+    let (record, sidecar) = extract_sidecar(&state, &staging, record);
+    let (record, mut errors) = resume_received(&state, &staging, record, sidecar.as_ref()).await;
+    if failed {
+        keep_partials(&state, &staging, &record);
+        discard_incomplete(&staging, &record);
+    }
+    let (record, unpack_errors) =
+        unpack_received(&state, &settings, &staging, record, sidecar.as_ref()).await;
+    errors.extend(unpack_errors);
+    let (record, tree) = extract_metadata(&state, &staging, record);
+    let record = record_staged(&state, &staging, record);
+    let (record, mismatches) = check_received(&state, &staging, record, sidecar).await;
+    errors.extend(mismatches);
+
+    if settings.receive_filter.action == FilterAction::Quarantine {
+        quarantine_received(&app_handle, &settings, &staging, &record);
+    }
+    let metadata = match tree {
+        Some(tree) if settings.apply_received_metadata => {
+            plan_metadata(&state, &settings.download_dir, &record, tree)
+        }
+        _ => None,
+    };
+    let files = place_received(&app_handle, &settings, &staging, &record);
+    if !state
+        .history_store
+        .get(&record.id)
+        .is_some_and(|r| has_pending_conflicts(&r))
+    {
+        remove_staging(&record);
+    }
+    if let Some((tree, placement)) = metadata {
+        apply_metadata(&state, &record.id, tree, placement).await;
+    }
+    if let Err(error) = verify_received(&state, &record.id).await {
+        errors.push(error);
+    }
+    if !errors.is_empty() {
+        state.publish(AppEvent::TransferFailed {
+            transfer_id: record.id.clone(),
+            error: errors.join("; "),
+        });
+        return;
+    }
+
+    if failed || settings.post_receive_hooks.is_empty() || files.is_empty() {
+        return;
+    }
+    let trusted = settings.trusted_hosts.contains(&record.peer_address);
+    // Extracted archives must fit the receive limits like decompressed files
+    let available = quota::available_space(&settings.download_dir).unwrap_or(u64::MAX);
+    let extract_limit = quota::unpack_limit(
+        &settings.receive_limits,
+        record.total_size,
+        state.quota.used_today(),
+        available,
+    )
+    .saturating_sub(record.total_size);
+    let mut ctx = HookContext {
+        transfer_id: record.id.clone(),
+        sender_address: record.peer_address.clone(),
+        sender_name: record.peer_name.clone(),
+        files: files.clone(),
+        extract_limit,
+    };
+    let runs = hooks::run_hooks(&settings.post_receive_hooks, trusted, &mut ctx).await;
+    let extracted = extract_limit - ctx.extract_limit;
+    if extracted > 0 {
+        state
+            .quota
+            .grow(&record.id, record.total_size.saturating_add(extracted));
+    }
+    if !runs.is_empty() {
+        let _ = state.history_store.update(&record.id, |record| {
+            record.hook_runs = runs;
+            // Hooks may have moved files; the record points at where they are now
+            for (placed, now) in files.iter().zip(&ctx.files) {
+                for file in record.files.iter_mut() {
+                    if file.path.as_ref() == Some(placed) {
+                        file.path = Some(now.clone());
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Keep a received text message in history, copying it to the clipboard if
+/// it comes from a trusted host and that is turned on
+fn receive_text(
+    app_handle: &AppHandle,
+    settings: &AppSettings,
+    staging: &Path,
+    record: TransferRecord,
+) {
+    let state = app_handle.state::<AppState>();
+    let message = match text::take(staging, &record) {
+        Ok(message) => message,
+        Err(e) => {
+            state.publish(AppEvent::TransferFailed {
+                transfer_id: record.id,
+                error: e.to_string(),
+            });
+            return;
+        }
+    };
+    let _ = state.history_store.update(&record.id, |record| {
+        record.text = Some(message.clone());
+    });
+
+    let copied = settings.auto_copy_text
+        && settings.trusted_hosts.contains(&record.peer_address)
+        && match app_handle.clipboard().write_text(message.clone()) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("Failed to copy text to the clipboard: {}", e);
+                false
+            }
+        };
+    state.publish(AppEvent::TextReceived {
+        transfer_id: record.id,
+        text: message,
+        copied,
+    });
+}
+
+/// Take the sender's sidecar out of staging
+fn extract_sidecar(
+    state: &AppState,
+    staging: &Path,
+    mut record: TransferRecord,
+) -> (TransferRecord, Option<sidecar::Sidecar>) {
+    let sidecar = sidecar::extract(staging, &mut record);
+    if sidecar.is_some() {
+        let _ = state.history_store.update(&record.id, |stored| {
+            stored.files = record.files.clone();
+            stored.total_size = record.total_size;
+        });
+    }
+    (record, sidecar)
+}
+
+/// Join the files the sender resumed to the parts kept of them, and record
+/// which interrupted transfer this one resumes
+async fn resume_received(
+    state: &AppState,
+    staging: &Path,
+    record: TransferRecord,
+    sidecar: Option<&sidecar::Sidecar>,
+) -> (TransferRecord, Vec<String>) {
+    let offsets = sidecar.map(|s| s.offsets()).unwrap_or_default();
+    if offsets.is_empty() {
+        return (record, Vec::new());
+    }
+
+    let root = resume::dir(staging::root_of(staging).unwrap_or(staging));
+    let staging = staging.to_path_buf();
+    let fallback = record.clone();
+    let (record, errors, taken) = match tokio::task::spawn_blocking(move || {
+        let mut record = record;
+        let (errors, taken) = resume::resume_received(&root, &staging, &mut record, &offsets);
+        (record, errors, taken)
+    })
+    .await
+    {
+        Ok(resumed) => resumed,
+        Err(_) => return (fallback, Vec::new()),
+    };
+
+    let _ = state.history_store.update(&record.id, |stored| {
+        stored.files = record.files.clone();
+        stored.total_size = record.total_size;
+        stored.resumed_from = taken.last().map(|(id, _)| id.clone());
+    });
+    for (id, name) in &taken {
+        let _ = state.history_store.update(id, |interrupted| {
+            interrupted.partial_files.retain(|p| p.name != *name);
+        });
+    }
+    (record, errors)
+}
+
+/// Keep the files a failed transfer received in part for resuming
+fn keep_partials(state: &AppState, staging: &Path, record: &TransferRecord) {
+    let root = resume::dir(staging::root_of(staging).unwrap_or(staging));
+    match resume::keep_partials(&root, staging, record) {
+        Ok(partials) if !partials.is_empty() => {
+            let _ = state.history_store.update(&record.id, |record| {
+                record.partial_files = partials;
+            });
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to keep partial files of {}: {}", record.id, e),
+    }
+}
+
+/// Hash the staged files and check them against the sender's digests, and
+/// take the sender's modification times
+///
+/// Files that do not match are marked failed and removed from staging; the
+/// errors name them. Without a sidecar the digests are only recorded.
+async fn check_received(
+    state: &AppState,
+    staging: &Path,
+    mut record: TransferRecord,
+    sidecar: Option<sidecar::Sidecar>,
+) -> (TransferRecord, Vec<String>) {
+    let staged = record
+        .files
+        .iter()
+        .filter_map(|f| Some((f.name.clone(), f.path.clone()?)))
+        .collect();
+    let digests = checksum::digests_blocking(staged).await;
+
+    let mut errors = Vec::new();
+    for file in &mut record.files {
+        let actual = match digests.get(&file.name) {
+            Some(actual) => actual.clone(),
+            None => continue,
+        };
+        let entry = sidecar.as_ref().and_then(|s| s.entry(&file.name));
+        file.modified = entry.and_then(|entry| entry.modified);
+        match entry.map(|entry| entry.sha256.as_str()) {
+            Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+                let error = AppError::ChecksumMismatch {
+                    name: file.name.clone(),
+                    expected: expected.to_string(),
+                    actual,
+                }
+                .to_string();
+                if let Some(path) = file.path.take() {
+                    let _ = std::fs::remove_file(&path);
+                    conflict::remove_empty_parents(&path, staging);
+                }
+                file.status = Some(FileStatus::Failed);
+                file.error = Some(error.clone());
+                errors.push(error);
+            }
+            _ => file.sha256 = Some(actual),
+        }
+    }
+
+    let _ = state.history_store.update(&record.id, |stored| {
+        stored.files = record.files.clone();
+    });
+    (record, errors)
+}
+
+/// Record where each received file was staged
+fn record_staged(state: &AppState, staging: &Path, mut record: TransferRecord) -> TransferRecord {
+    for file in &mut record.files {
+        file.path = filter::received_path(staging, &file.name).filter(|path| path.is_file());
+    }
+    let _ = state.history_store.update(&record.id, |stored| {
+        stored.files = record.files.clone();
+    });
+    record
+}
+
+/// Remove a received transfer's own staging directory
+fn remove_staging(record: &TransferRecord) {
+    if let Some(dir) = &record.staging_dir {
+        staging::remove(dir);
+    }
+}
+
+/// Whether a received file still waits for a name conflict to be resolved
+fn has_pending_conflicts(record: &TransferRecord) -> bool {
+    record
+        .conflicts
+        .iter()
+        .any(|c| c.resolution == ConflictResolution::Pending)
+}
+
+/// Decompress the files the sender's sidecar lists as compressed, before
+/// anything else looks at them
+///
+/// The decompressed files must fit the receive limits; the quota reserved
+/// for the transfer grows to their size.
+async fn unpack_received(
+    state: &AppState,
+    settings: &AppSettings,
+    staging: &Path,
+    record: TransferRecord,
+    sidecar: Option<&sidecar::Sidecar>,
+) -> (TransferRecord, Vec<String>) {
+    let compressed = sidecar.map(|s| s.compressed()).unwrap_or_default();
+    if compressed.is_empty() {
+        return (record, Vec::new());
+    }
+
+    let available = quota::available_space(&settings.download_dir).unwrap_or(u64::MAX);
+    let limit = quota::unpack_limit(
+        &settings.receive_limits,
+        record.total_size,
+        state.quota.used_today(),
+        available,
+    );
+    let staging = staging.to_path_buf();
+    let fallback = record.clone();
+    let (record, errors) = match tokio::task::spawn_blocking(move || {
+        let mut record = record;
+        let errors = compress::unpack(&staging, &mut record, &compressed, limit);
+        (record, errors)
+    })
+    .await
+    {
+        Ok(unpacked) => unpacked,
+        Err(_) => return (fallback, Vec::new()),
+    };
+
+    state.quota.grow(&record.id, record.total_size);
+    let _ = state.history_store.update(&record.id, |stored| {
+        stored.files = record.files.clone();
+        stored.routes = record.routes.clone();
+        stored.total_size = record.total_size;
+        stored.compression = record.compression.clone();
+    });
+    (record, errors)
+}
+
+/// Take the metadata of a directory sent with it out of staging
+fn extract_metadata(
+    state: &AppState,
+    staging: &Path,
+    mut record: TransferRecord,
+) -> (TransferRecord, Option<metadata::TreeMetadata>) {
+    let tree = metadata::extract(staging, &mut record);
+    if tree.is_some() {
+        let _ = state.history_store.update(&record.id, |stored| {
+            stored.files = record.files.clone();
+            stored.total_size = record.total_size;
+            stored.preserve_metadata = true;
+        });
+    }
+    (record, tree)
+}
+
+/// Work out which directories a received tree's metadata may touch, before
+/// its files are placed
+fn plan_metadata(
+    state: &AppState,
+    download_dir: &Path,
+    record: &TransferRecord,
+    tree: metadata::TreeMetadata,
+) -> Option<(metadata::TreeMetadata, metadata::Placement)> {
+    // Quarantine recorded which files it took
+    let record = state.history_store.get(&record.id)?;
+    let placement = metadata::plan(&tree, &record, download_dir);
+    Some((tree, placement))
+}
+
+/// Restore times, permissions, empty directories and links of a placed
+/// directory, in the directories the transfer created
+async fn apply_metadata(
+    state: &AppState,
+    transfer_id: &str,
+    tree: metadata::TreeMetadata,
+    placement: metadata::Placement,
+) {
+    // Placing the files recorded their final paths
+    let record = match state.history_store.get(transfer_id) {
+        Some(record) => record,
+        None => return,
+    };
+    let _ = tokio::task::spawn_blocking(move || metadata::apply(&tree, &placement, &record)).await;
+}
+
+/// Remove what is left in staging of the files of a failed transfer that
+/// did not arrive complete
+///
+/// Those received in part were kept for resuming before.
+fn discard_incomplete(staging: &Path, record: &TransferRecord) {
+    let incomplete = record
+        .files
+        .iter()
+        .filter(|f| f.status != Some(FileStatus::Completed))
+        .filter_map(|f| filter::received_path(staging, &f.name));
+    for path in incomplete.filter(|path| path.is_file()) {
+        match std::fs::remove_file(&path) {
+            Ok(()) => conflict::remove_empty_parents(&path, staging),
+            Err(e) => tracing::warn!("Failed to remove incomplete file {:?}: {}", path, e),
+        }
+    }
+}
+
+/// Check received files at their final path against the digests checked in
+/// staging
+///
+/// Files that do not match are marked failed; the error names them.
+async fn verify_received(state: &AppState, transfer_id: &str) -> Result<(), String> {
+    let record = match state.history_store.get(transfer_id) {
+        Some(record) => record,
+        None => return Ok(()),
+    };
+    let files: Vec<(String, String, PathBuf, String)> = record
+        .files
+        .into_iter()
+        .filter_map(|f| Some((f.id, f.name, f.path?, f.sha256?)))
+        .collect();
+
+    let mismatches = tokio::task::spawn_blocking(move || {
+        files
+            .into_iter()
+            .filter_map(|(id, name, path, expected)| {
+                match checksum::verify(&name, &path, &expected) {
+                    Err(e @ AppError::ChecksumMismatch { .. }) => Some((id, e.to_string())),
+                    Err(e) => {
+                        tracing::warn!("Cannot verify {:?}: {}", path, e);
+                        None
+                    }
+                    Ok(_) => None,
+                }
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .unwrap_or_default();
+    if mismatches.is_empty() {
+        return Ok(());
+    }
+
+    let _ = state.history_store.update(transfer_id, |record| {
+        for file in &mut record.files {
+            if let Some((_, error)) = mismatches.iter().find(|(id, _)| *id == file.id) {
+                file.status = Some(FileStatus::Failed);
+                file.error = Some(error.clone());
+            }
+        }
+    });
+    let errors: Vec<String> = mismatches.into_iter().map(|(_, e)| e).collect();
+    Err(errors.join("; "))
+}
+
+/// Move received files that match the receive filter (by name, MIME type or
+/// executable content) into quarantine and record them in history
+fn quarantine_received(
+    app_handle: &AppHandle,
+    settings: &AppSettings,
+    staging: &Path,
+    record: &TransferRecord,
+) {
+    let receive_filter = &settings.receive_filter;
+    let quarantine_dir = match &receive_filter.quarantine_dir {
+        Some(dir) => dir.clone(),
+        None => match filter::default_quarantine_dir() {
+            Ok(dir) => dir,
+            Err(e) => {
+                tracing::error!("Cannot quarantine files: {}", e);
+                return;
+            }
+        },
+    };
+
+    let mut quarantined = Vec::new();
+    for file in &record.files {
+        let path = match &file.path {
+            Some(path) if path.starts_with(staging) && path.is_file() => path.clone(),
+            _ => continue,
+        };
+        let reason = filter::match_file(receive_filter, &file.name, file.mime_type.as_deref())
+            .or_else(|| filter::sniff_executable(&path));
+        let reason = match reason {
+            Some(reason) => reason,
+            None => continue,
+        };
+
+        match filter::quarantine(&path, &quarantine_dir) {
+            Ok(target) => {
+                tracing::info!("Quarantined {:?} ({}) to {:?}", path, reason, target);
+                conflict::remove_empty_parents(&path, staging);
+                quarantined.push(QuarantinedFile {
+                    name: file.name.clone(),
+                    path: target,
+                    reason,
+                });
+            }
+            Err(e) => tracing::error!("Failed to quarantine {:?}: {}", path, e),
+        }
+    }
+
+    if quarantined.is_empty() {
+        return;
+    }
+
+    let state = app_handle.state::<AppState>();
+    let _ = state.history_store.update(&record.id, |record| {
+        for q in &quarantined {
+            set_file_path(record, &q.name, &q.path);
+        }
+        record.quarantined.extend(quarantined.iter().cloned());
+    });
+
+    state.publish(AppEvent::FilesQuarantined {
+        transfer_id: record.id.clone(),
+        files: quarantined,
+    });
+}
+
+/// Move the remaining staged files to the download directory (or the
+/// directory chosen by the routing rules), applying the name-conflict policy,
+/// and record routes and conflicts in history
+///
+/// Returns the final paths of the placed files.
+fn place_received(
+    app_handle: &AppHandle,
+    settings: &AppSettings,
+    staging: &Path,
+    record: &TransferRecord,
+) -> Vec<PathBuf> {
+    let mut routes = record.routes.clone();
+    let mut conflicts = Vec::new();
+    let mut placed = Vec::new();
+    let mut paths = Vec::new();
+
+    for file in &record.files {
+        // Quarantined files are no longer in staging
+        let source = match &file.path {
+            Some(path) if path.starts_with(staging) && path.is_file() => path.clone(),
+            _ => continue,
+        };
+
+        let route = routes.iter_mut().find(|r| r.name == file.name);
+        let (directory, policy) = match &route {
+            Some(route) => (
+                route.directory.clone(),
+                route.conflict_policy.unwrap_or(settings.conflict_policy),
+            ),
+            None => (settings.download_dir.clone(), settings.conflict_policy),
+        };
+        let target = directory.join(&file.name);
+
+        let modified = file.modified.map(SystemTime::from);
+        match conflict::place(&source, &target, policy, modified) {
+            Ok((path, resolution)) => {
+                if resolution != Some(ConflictResolution::Pending) {
+                    conflict::remove_empty_parents(&source, staging);
+                    placed.push(path.clone());
+                }
+                if let Some(resolution) = resolution {
+                    tracing::info!("Name conflict for {:?}: {:?}", target, resolution);
+                    conflicts.push(FileConflict {
+                        name: file.name.clone(),
+                        target,
+                        resolution,
+                        path: path.clone(),
+                    });
+                }
+                paths.push((file.name.clone(), path.clone()));
+                if let Some(route) = route {
+                    route.path = Some(path);
+                }
+            }
+            Err(e) => tracing::error!("Failed to place {:?}: {}", source, e),
+        }
+    }
+
+    let state = app_handle.state::<AppState>();
+    let _ = state.history_store.update(&record.id, |record| {
+        for (name, path) in &paths {
+            set_file_path(record, name, path);
+        }
+        record.routes = routes;
+        record.conflicts = conflicts.clone();
+    });
+
+    let pending: Vec<FileConflict> = conflicts
+        .into_iter()
+        .filter(|c| c.resolution == ConflictResolution::Pending)
+        .collect();
+    if !pending.is_empty() {
+        state.publish(AppEvent::FileConflicts {
+            transfer_id: record.id.clone(),
+            conflicts: pending,
+        });
+    }
+
+    placed
+}
+
+/// Record where a received file ended up
+fn set_file_path(record: &mut TransferRecord, name: &str, path: &Path) {
+    for file in record.files.iter_mut().filter(|f| f.name == name) {
+        file.path = Some(path.to_path_buf());
+    }
+}
+
+/// Resolve a pending name conflict with the given policy
+///
+/// Returns where the received file ended up.
+pub fn resolve_conflict(
+    state: &AppState,
+    transfer_id: &str,
+    name: &str,
+    policy: ConflictPolicy,
+) -> Result<PathBuf, AppError> {
+    if policy == ConflictPolicy::Ask {
+        return Err(AppError::InvalidConfig(
+            "A conflict cannot be resolved by asking again".to_string(),
+        ));
+    }
+
+    let record = state
+        .history_store
+        .get(transfer_id)
+        .ok_or_else(|| AppError::InvalidConfig(format!("Transfer not found: {}", transfer_id)))?;
+    let pending = record
+        .conflicts
+        .iter()
+        .find(|c| c.name == name && c.resolution == ConflictResolution::Pending)
+        .ok_or_else(|| AppError::InvalidConfig(format!("No pending conflict for {}", name)))?;
+
+    let modified = record
+        .files
+        .iter()
+        .find(|f| f.name == name)
+        .and_then(|f| f.modified)
+        .map(SystemTime::from);
+    let (path, resolution) = conflict::place(&pending.path, &pending.target, policy, modified)?;
+    let staged = pending.path.clone();
+
+    state.history_store.update(transfer_id, |record| {
+        match resolution {
+            Some(resolution) => {
+                for c in record.conflicts.iter_mut().filter(|c| c.name == name) {
+                    c.resolution = resolution;
+                    c.path = path.clone();
+                }
+            }
+            // The existing file went away in the meantime
+            None => record.conflicts.retain(|c| c.name != name),
+        }
+        for route in record.routes.iter_mut().filter(|r| r.name == name) {
+            route.path = Some(path.clone());
+        }
+        set_file_path(record, name, &path);
+    })?;
+
+    let pending_left = state
+        .history_store
+        .get(transfer_id)
+        .is_some_and(|r| has_pending_conflicts(&r));
+    match &record.staging_dir {
+        Some(dir) if !pending_left => staging::remove(dir),
+        Some(dir) => conflict::remove_empty_parents(&staged, dir),
+        None => {
+            if let Some(root) = staging::root_of(&staged) {
+                conflict::remove_empty_parents(&staged, root);
+            }
+        }
+    }
+
+    Ok(path)
+}
@@ -0,0 +1,569 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Resuming interrupted transfers
+//
+// The engine can only send whole files, so resuming is done by the app at
+// both ends. When an incoming transfer fails, or the app closes during one,
+// each file that arrived in part is kept as `<name>.part` in
+// `.gosh-incoming/.resume/<transfer id>/`, with a state file
+// (`.resume/<transfer id>.json`) recording the peer, the full size of each
+// file and the bytes kept; the record lists them as partial files.
+//
+// The sender resumes a failed send by offering the files that did not
+// complete again (see `send::resume_record`). A file the peer got part of
+// goes out from an offset, as a temporary file holding the rest of it, and
+// the sidecar (see `sidecar`) tells the receiver the offset and the digest of
+// the whole file. The receiver cuts the newest matching part (same peer,
+// name and full size) to the offset, appends what arrived, and checks the
+// joined file like any other.
+//
+// The sender only knows what it handed to the engine, not what the peer
+// wrote to disk, so offsets are kept `MARGIN` behind it. Files first sent
+// compressed are sent again in full, as are all files to a peer that takes
+// no sidecar.
+
+use crate::types::{AppError, FileStatus, PartialFile, TransferFile, TransferRecord};
+use crate::{compress, conflict, filter, select, sidecar};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Bytes an offset stays behind what the sender handed to the engine, for
+/// data still on its way when the transfer broke
+const MARGIN: u64 = 16 * 1024 * 1024;
+
+/// Offsets are rounded down to a multiple of this
+const ALIGN: u64 = 1024 * 1024;
+
+/// Name of the directory holding parts and state files, inside the staging
+/// root
+const RESUME_DIR_NAME: &str = ".resume";
+
+/// Extension appended to partially received files
+const PART_EXTENSION: &str = "part";
+
+/// Held while state files are read and written back
+static STATE_LOCK: Mutex<()> = Mutex::new(());
+
+/// State of an interrupted incoming transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ResumeState {
+    transfer_id: String,
+    peer_address: String,
+    interrupted_at: DateTime<Utc>,
+    files: Vec<PartialFile>,
+}
+
+/// Directory holding the parts kept in the staging root `staging_root`
+pub fn dir(staging_root: &Path) -> PathBuf {
+    staging_root.join(RESUME_DIR_NAME)
+}
+
+fn state_path(root: &Path, transfer_id: &str) -> PathBuf {
+    root.join(format!("{}.json", transfer_id))
+}
+
+fn read_state(path: &Path) -> Option<ResumeState> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content)
+        .map_err(|e| tracing::warn!("Ignoring {:?}: {}", path, e))
+        .ok()
+}
+
+fn write_state(path: &Path, state: &ResumeState) -> Result<(), AppError> {
+    let content = serde_json::to_string_pretty(state)
+        .map_err(|e| AppError::Serialization(format!("Failed to serialize resume state: {}", e)))?;
+    fs::write(path, content)
+        .map_err(|e| AppError::FileIo(format!("Failed to write {:?}: {}", path, e)))
+}
+
+/// Files of a failed send to offer again, each from the offset the peer is
+/// known to have
+///
+/// Without per-file outcomes (records from older versions) files are taken
+/// to have been sent in order.
+pub fn unfinished(record: &TransferRecord) -> Vec<TransferFile> {
+    // Compressed files went out at another size than their own
+    let resumable = record.compression.is_none();
+    let outcomes = record.files.iter().any(|f| f.status.is_some());
+    let done: u64 = record
+        .files
+        .iter()
+        .filter(|f| f.status == Some(FileStatus::Completed))
+        .map(|f| f.transfer_size())
+        .sum();
+    let mut sent = record.bytes_transferred.saturating_sub(done);
+
+    let mut files = Vec::new();
+    for file in &record.files {
+        // Bytes of the file the transfer carried
+        let carried = match file.status {
+            Some(FileStatus::Completed) => continue,
+            Some(FileStatus::InProgress | FileStatus::Failed) => sent.min(file.transfer_size()),
+            Some(FileStatus::Skipped) => 0,
+            None if outcomes => 0,
+            None => {
+                let carried = sent.min(file.transfer_size());
+                sent -= carried;
+                if carried == file.transfer_size() {
+                    continue;
+                }
+                carried
+            }
+        };
+        let received = file.offset.unwrap_or(0) + carried;
+        let offset = received.saturating_sub(MARGIN) / ALIGN * ALIGN;
+
+        let mut again = TransferFile::new(file.id.clone(), file.name.clone(), file.size);
+        again.mime_type = file.mime_type.clone();
+        again.path = file.path.clone();
+        again.offset = Some(offset).filter(|&offset| resumable && offset > 0);
+        files.push(again);
+    }
+    files
+}
+
+/// Files of a resumed send, laid out for sending
+///
+/// The temporary directory is removed when dropped.
+pub struct Tails {
+    dir: PathBuf,
+    /// Paths to send: the files, or the directory holding them
+    pub paths: Vec<PathBuf>,
+}
+
+impl Drop for Tails {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            tracing::warn!("Failed to remove {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+/// Lay out the files of a resumed send in a temporary directory under their
+/// names, each one sent from an offset holding only the rest of its source
+///
+/// A directory send goes out as the directory its names start with.
+pub fn stage(
+    transfer_id: &str,
+    files: &[TransferFile],
+    directory: bool,
+) -> Result<Tails, AppError> {
+    let dir = std::env::temp_dir().join(format!("gosh-transfer-{}-resume", transfer_id));
+    let mut tails = Tails {
+        dir,
+        paths: Vec::new(),
+    };
+
+    for file in files {
+        let (source, target) = match (&file.path, filter::received_path(&tails.dir, &file.name)) {
+            (Some(source), Some(target)) => (source, target),
+            _ => {
+                return Err(AppError::FileIo(format!(
+                    "The source of {} is not known",
+                    file.name
+                )))
+            }
+        };
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", parent, e)))?;
+        }
+        match file.offset {
+            Some(offset) => copy_tail(source, &target, offset)
+                .map_err(|e| AppError::FileIo(format!("Failed to copy {:?}: {}", source, e)))?,
+            None => select::link_or_copy(source, &target)?,
+        }
+        if !directory {
+            tails.paths.push(target);
+        }
+    }
+
+    let top = files
+        .first()
+        .and_then(|file| Path::new(&file.name).components().next());
+    if let (true, Some(Component::Normal(top))) = (directory, top) {
+        tails.paths.push(tails.dir.join(top));
+    }
+    Ok(tails)
+}
+
+/// Copy what follows `offset` in `source` to `target`
+fn copy_tail(source: &Path, target: &Path, offset: u64) -> io::Result<()> {
+    let mut source = fs::File::open(source)?;
+    source.seek(SeekFrom::Start(offset))?;
+    io::copy(&mut source, &mut fs::File::create(target)?)?;
+    Ok(())
+}
+
+fn part_path(root: &Path, transfer_id: &str, name: &str) -> Option<PathBuf> {
+    let path = filter::received_path(&root.join(transfer_id), name)?;
+    let mut file_name = path.file_name()?.to_os_string();
+    file_name.push(".");
+    file_name.push(PART_EXTENSION);
+    Some(path.with_file_name(file_name))
+}
+
+/// Keep the files a failed incoming transfer received in part, moving them
+/// from `staging` into `root`, and write its state
+///
+/// Returns the partial files kept. Files that arrived complete, are empty or
+/// were sent compressed are left where they are.
+pub fn keep_partials(
+    root: &Path,
+    staging: &Path,
+    record: &TransferRecord,
+) -> Result<Vec<PartialFile>, AppError> {
+    let mut partials = Vec::new();
+    for file in &record.files {
+        let sidecar = sidecar::is_file_name(file.name.rsplit('/').next().unwrap_or_default());
+        if file.status == Some(FileStatus::Completed)
+            || sidecar
+            || compress::original_name(&file.name) != file.name
+        {
+            continue;
+        }
+        let (path, target) = match (
+            filter::received_path(staging, &file.name),
+            part_path(root, &record.id, &file.name),
+        ) {
+            (Some(path), Some(target)) if path.is_file() => (path, target),
+            _ => continue,
+        };
+        let bytes_received = fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+        if bytes_received == 0 {
+            continue;
+        }
+
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", parent, e)))?;
+        }
+        conflict::move_file(&path, &target)?;
+        conflict::remove_empty_parents(&path, staging);
+        partials.push(PartialFile {
+            name: file.name.clone(),
+            size: file.size,
+            bytes_received,
+            path: target,
+        });
+    }
+    if partials.is_empty() {
+        return Ok(partials);
+    }
+
+    let state = ResumeState {
+        transfer_id: record.id.clone(),
+        peer_address: record.peer_address.clone(),
+        interrupted_at: Utc::now(),
+        files: partials.clone(),
+    };
+    let _lock = STATE_LOCK.lock().unwrap();
+    write_state(&state_path(root, &record.id), &state)?;
+    Ok(partials)
+}
+
+/// Take the newest part from `peer_address` of the file `name` of `size`
+/// bytes holding at least `offset` bytes out of its state
+///
+/// Returns the ID of the interrupted transfer and the part's path.
+fn take_part(
+    root: &Path,
+    peer_address: &str,
+    name: &str,
+    size: u64,
+    offset: u64,
+) -> Option<(String, PathBuf)> {
+    let _lock = STATE_LOCK.lock().unwrap();
+    let entries = fs::read_dir(root).ok()?;
+    let mut states: Vec<(PathBuf, ResumeState)> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| Some((path.clone(), read_state(&path)?)))
+        .filter(|(_, state)| state.peer_address == peer_address)
+        .collect();
+    states.sort_by_key(|(_, state)| Reverse(state.interrupted_at));
+
+    for (path, mut state) in states {
+        let index = state.files.iter().position(|f| {
+            f.name == name && f.size == size && f.bytes_received >= offset && f.path.is_file()
+        });
+        let index = match index {
+            Some(index) => index,
+            None => continue,
+        };
+        let part = state.files.remove(index).path;
+        let saved = match state.files.is_empty() {
+            true => fs::remove_file(&path).map_err(|e| AppError::FileIo(e.to_string())),
+            false => write_state(&path, &state),
+        };
+        if let Err(e) = saved {
+            tracing::warn!("Failed to update {:?}: {}", path, e);
+        }
+        return Some((state.transfer_id, part));
+    }
+    None
+}
+
+/// Cut `part` to `offset`, append `rest` to it and put the joined file in
+/// place of `rest`
+fn join(part: &Path, offset: u64, rest: &Path) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new().write(true).open(part)?;
+    file.set_len(offset)?;
+    file.seek(SeekFrom::End(0))?;
+    io::copy(&mut fs::File::open(rest)?, &mut file)?;
+    drop(file);
+    fs::rename(part, rest)
+}
+
+/// Join the files a received transfer resumed to the parts kept of them
+///
+/// `offsets` are the offsets the sender's sidecar gives, by file name. Each
+/// joined file takes its full size and offset in `record`; one with no part
+/// to join is removed from `staging` and marked failed. Files that did not
+/// arrive at all are left alone, and their parts kept. Returns the errors,
+/// and the interrupted transfer and name of each part taken.
+pub fn resume_received(
+    root: &Path,
+    staging: &Path,
+    record: &mut TransferRecord,
+    offsets: &HashMap<String, u64>,
+) -> (Vec<String>, Vec<(String, String)>) {
+    let mut errors = Vec::new();
+    let mut taken = Vec::new();
+    for file in &mut record.files {
+        let (offset, rest) = match (
+            offsets.get(&file.name),
+            filter::received_path(staging, &file.name),
+        ) {
+            (Some(&offset), Some(rest)) if rest.is_file() => (offset, rest),
+            _ => continue,
+        };
+        let size = offset + file.size;
+
+        let joined = match take_part(root, &record.peer_address, &file.name, size, offset) {
+            Some((transfer_id, part)) => {
+                let joined = join(&part, offset, &rest).map_err(|e| e.to_string());
+                if joined.is_err() {
+                    let _ = fs::remove_file(&part);
+                }
+                conflict::remove_empty_parents(&part, root);
+                taken.push((transfer_id, file.name.clone()));
+                joined
+            }
+            None => Err("no part of it is kept".to_string()),
+        };
+        match joined {
+            Ok(()) => {
+                record.total_size += offset;
+                file.size = size;
+                file.offset = Some(offset);
+            }
+            Err(e) => {
+                let error = format!("Cannot resume {}: {}", file.name, e);
+                let _ = fs::remove_file(&rest);
+                conflict::remove_empty_parents(&rest, staging);
+                file.status = Some(FileStatus::Failed);
+                file.error = Some(error.clone());
+                errors.push(error);
+            }
+        }
+    }
+    (errors, taken)
+}
+
+/// Remove the parts and state of an interrupted transfer
+pub fn discard(root: &Path, transfer_id: &str) -> Result<(), AppError> {
+    let _lock = STATE_LOCK.lock().unwrap();
+    let parts = root.join(transfer_id);
+    if parts.exists() {
+        fs::remove_dir_all(&parts)
+            .map_err(|e| AppError::FileIo(format!("Failed to remove {:?}: {}", parts, e)))?;
+    }
+    let path = state_path(root, transfer_id);
+    if path.exists() {
+        fs::remove_file(&path)
+            .map_err(|e| AppError::FileIo(format!("Failed to remove {:?}: {}", path, e)))?;
+    }
+    Ok(())
+}
+
+/// Remove the parts and state of interrupted transfers not in `keep`, e.g.
+/// those deleted from history
+pub fn retain(root: &Path, keep: &HashSet<String>) {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let ids: HashSet<String> = entries
+        .flatten()
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.strip_suffix(".json").unwrap_or(&name).to_string()
+        })
+        .collect();
+
+    for id in ids.difference(keep) {
+        tracing::info!("Removing the parts of {}", id);
+        if let Err(e) = discard(root, id) {
+            tracing::warn!("{}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CompressionInfo, PendingTransfer, TransferKind, TransferStatus};
+
+    const MIB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_unfinished() {
+        let mut record = TransferRecord::outgoing(
+            "10.0.0.2",
+            vec![
+                TransferFile::new("a", "a.bin", 10),
+                TransferFile::new("b", "b.bin", 40 * MIB),
+                TransferFile::new("c", "c.bin", 40 * MIB),
+            ],
+        );
+        record.status = TransferStatus::Failed;
+        record.bytes_transferred = 10 + 30 * MIB + 5;
+        record.files[0].status = Some(FileStatus::Completed);
+        record.files[1].status = Some(FileStatus::Failed);
+        record.files[2].status = Some(FileStatus::Skipped);
+
+        let files = unfinished(&record);
+        let offsets: Vec<_> = files.iter().map(|f| (f.name.as_str(), f.offset)).collect();
+        assert_eq!(offsets, [("b.bin", Some(14 * MIB)), ("c.bin", None)]);
+
+        // Resuming that again adds what the resumed send carried
+        let mut resumed = TransferRecord::outgoing("10.0.0.2", files);
+        resumed.status = TransferStatus::Failed;
+        resumed.bytes_transferred = 20 * MIB;
+        resumed.files[0].status = Some(FileStatus::Failed);
+        assert_eq!(unfinished(&resumed)[0].offset, Some(18 * MIB));
+
+        // Compressed files went out at other sizes
+        record.compression = Some(CompressionInfo {
+            original_bytes: 100,
+            compressed_bytes: 50,
+            ratio: 0.5,
+        });
+        assert!(unfinished(&record).iter().all(|f| f.offset.is_none()));
+    }
+
+    #[test]
+    fn test_resume_received() {
+        let dir = std::env::temp_dir().join(format!("gosh-resume-{}", uuid::Uuid::new_v4()));
+        let root = dir.join(RESUME_DIR_NAME);
+        let content: Vec<u8> = (0..100u8).collect();
+
+        // The interrupted transfer got 70 bytes of a.bin
+        let staging = dir.join("t1");
+        fs::create_dir_all(staging.join("docs")).unwrap();
+        fs::write(staging.join("docs/a.bin"), &content[..70]).unwrap();
+        let pending = |id: &str, files| PendingTransfer {
+            id: id.to_string(),
+            source_ip: "10.0.0.5".to_string(),
+            sender_name: None,
+            files,
+            total_size: 0,
+            received_at: Utc::now(),
+            kind: TransferKind::Files,
+            text_preview: None,
+        };
+        let mut interrupted = TransferRecord::incoming(&pending(
+            "t1",
+            vec![
+                TransferFile::new("f1", "docs/a.bin", 100),
+                TransferFile::new("f2", "docs/b.bin", 100),
+            ],
+        ));
+        interrupted.files[0].status = Some(FileStatus::Failed);
+        let partials = keep_partials(&root, &staging, &interrupted).unwrap();
+        assert_eq!(partials.len(), 1);
+        assert_eq!(partials[0].bytes_received, 70);
+        assert_eq!(partials[0].path, root.join("t1/docs/a.bin.part"));
+        assert!(!staging.join("docs").exists());
+
+        // The sender resumes a.bin from 60 and b.bin from 10, of which no
+        // part was kept
+        let staging = dir.join("t2");
+        fs::create_dir_all(staging.join("docs")).unwrap();
+        fs::write(staging.join("docs/a.bin"), &content[60..]).unwrap();
+        fs::write(staging.join("docs/b.bin"), &content[10..]).unwrap();
+        let mut record = TransferRecord::incoming(&pending(
+            "t2",
+            vec![
+                TransferFile::new("f1", "docs/a.bin", 40),
+                TransferFile::new("f2", "docs/b.bin", 90),
+            ],
+        ));
+        record.total_size = 130;
+        let offsets = HashMap::from([
+            ("docs/a.bin".to_string(), 60),
+            ("docs/b.bin".to_string(), 10),
+        ]);
+        let (errors, taken) = resume_received(&root, &staging, &mut record, &offsets);
+
+        assert_eq!(fs::read(staging.join("docs/a.bin")).unwrap(), content);
+        assert_eq!(record.files[0].size, 100);
+        assert_eq!(record.files[0].offset, Some(60));
+        assert_eq!(record.total_size, 190);
+        assert_eq!(taken, [("t1".to_string(), "docs/a.bin".to_string())]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(record.files[1].status, Some(FileStatus::Failed));
+        assert!(!staging.join("docs/b.bin").exists());
+        // The last part was taken, so its state is gone
+        assert!(!root.join("t1").exists());
+        assert!(!state_path(&root, "t1").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_stage_and_retain() {
+        let dir = std::env::temp_dir().join(format!("gosh-resume-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("project/sub")).unwrap();
+        fs::write(dir.join("project/sub/a.txt"), b"abcdef").unwrap();
+        fs::write(dir.join("project/b.txt"), b"b").unwrap();
+
+        let file = |name: &str, offset| {
+            let mut file = TransferFile::new(name, name, 0);
+            file.path = Some(dir.join(name));
+            file.offset = offset;
+            file
+        };
+        let files = [
+            file("project/sub/a.txt", Some(4)),
+            file("project/b.txt", None),
+        ];
+        let tails = stage("s1", &files, true).unwrap();
+        assert_eq!(tails.paths.len(), 1);
+        assert!(tails.paths[0].ends_with("project"));
+        assert_eq!(fs::read(tails.paths[0].join("sub/a.txt")).unwrap(), b"ef");
+        assert_eq!(fs::read(tails.paths[0].join("b.txt")).unwrap(), b"b");
+        let staged = tails.dir.clone();
+        drop(tails);
+        assert!(!staged.exists());
+
+        let root = dir.join(RESUME_DIR_NAME);
+        for id in ["t1", "t2"] {
+            fs::create_dir_all(root.join(id)).unwrap();
+            fs::write(state_path(&root, id), "{}").unwrap();
+        }
+        retain(&root, &HashSet::from(["t2".to_string()]));
+        assert!(!root.join("t1").exists() && !state_path(&root, "t1").exists());
+        assert!(root.join("t2").exists() && state_path(&root, "t2").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
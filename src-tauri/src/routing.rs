@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Routing rules for received files
+//
+// When a transfer is accepted, each file is matched against the routing rules
+// (first match wins) by sender, device name, name pattern and MIME type, and
+// the chosen directory is recorded in history. Routed files are moved to
+// their directory once the transfer has completed.
+
+use crate::types::{Favorite, FileRoute, RoutingRule, TransferFile, TransferRecord};
+use crate::{compress, filter};
+use chrono::{DateTime, Local};
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+
+/// Work out where each file of an accepted transfer should go
+///
+/// Files that match no rule are left out and stay in the download directory.
+pub fn plan(
+    rules: &[RoutingRule],
+    favorites: &[Favorite],
+    record: &TransferRecord,
+    download_dir: &Path,
+    now: DateTime<Local>,
+) -> Vec<FileRoute> {
+    let favorite = find_favorite(favorites, &record.peer_address);
+    let candidates: Vec<&RoutingRule> = rules
+        .iter()
+        .filter(|rule| rule.enabled && matches_sender(rule, favorites, record))
+        .collect();
+
+    record
+        .files
+        .iter()
+        .filter_map(|file| {
+            let rule = candidates.iter().find(|rule| matches_file(rule, file))?;
+            let directory = expand(&rule.directory, favorite, record, now);
+            let directory = if Path::new(&directory).is_absolute() {
+                PathBuf::from(directory)
+            } else {
+                download_dir.join(directory)
+            };
+
+            Some(FileRoute {
+                name: file.name.clone(),
+                rule: rule.name.clone(),
+                directory,
+                conflict_policy: rule.conflict_policy,
+                path: None,
+            })
+        })
+        .collect()
+}
+
+/// Find the favorite a peer address belongs to
+fn find_favorite<'a>(favorites: &'a [Favorite], peer_address: &str) -> Option<&'a Favorite> {
+    favorites
+        .iter()
+        .find(|f| f.address == peer_address || f.last_resolved_ip.as_deref() == Some(peer_address))
+}
+
+fn matches_sender(rule: &RoutingRule, favorites: &[Favorite], record: &TransferRecord) -> bool {
+    if let Some(sender) = &rule.sender {
+        let is_favorite = find_favorite(favorites, &record.peer_address)
+            .map(|f| f.address.eq_ignore_ascii_case(sender))
+            .unwrap_or(false);
+        if sender != &record.peer_address && !is_favorite {
+            return false;
+        }
+    }
+
+    if let Some(device_name) = &rule.device_name {
+        match &record.peer_name {
+            Some(name) if name.eq_ignore_ascii_case(device_name) => {}
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+/// Match a file by its original name (compressed files are sent under
+/// another one) and MIME type
+fn matches_file(rule: &RoutingRule, file: &TransferFile) -> bool {
+    let name = compress::original_name(&file.name);
+    if !rule.mime_types.is_empty() {
+        match mime_type(file) {
+            Some(mime) if filter::mime_matches(&rule.mime_types, &mime) => {}
+            _ => return false,
+        }
+    }
+    if rule.file_patterns.is_empty() {
+        return true;
+    }
+
+    let file_name = Path::new(name)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(name);
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..Default::default()
+    };
+
+    rule.file_patterns.iter().any(|p| {
+        Pattern::new(p)
+            .map(|pattern| pattern.matches_with(file_name, options))
+            .unwrap_or(false)
+    })
+}
+
+/// MIME type of a file as offered, or guessed from its original name
+fn mime_type(file: &TransferFile) -> Option<String> {
+    let name = compress::original_name(&file.name);
+    match &file.mime_type {
+        Some(mime) if name == file.name => Some(mime.clone()),
+        _ => mime_guess::from_path(name).first().map(|m| m.to_string()),
+    }
+}
+
+/// Expand the placeholders of a directory template
+fn expand(
+    template: &str,
+    favorite: Option<&Favorite>,
+    record: &TransferRecord,
+    now: DateTime<Local>,
+) -> String {
+    let sender = favorite
+        .map(|f| f.name.clone())
+        .or_else(|| record.peer_name.clone())
+        .unwrap_or_else(|| record.peer_address.clone());
+    let device = record
+        .peer_name
+        .clone()
+        .unwrap_or_else(|| "Unknown Device".to_string());
+
+    template
+        .replace("{sender}", &sanitize(&sender))
+        .replace("{device}", &sanitize(&device))
+        .replace("{yyyy-mm-dd}", &now.format("%Y-%m-%d").to_string())
+        .replace("{yyyy}", &now.format("%Y").to_string())
+        .replace("{mm}", &now.format("%m").to_string())
+        .replace("{dd}", &now.format("%d").to_string())
+}
+
+/// Make a peer-supplied value safe to use as a single path component
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_matches('.');
+
+    if cleaned.is_empty() {
+        "_".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PendingTransfer, TransferFile, TransferKind};
+    use chrono::{TimeZone, Utc};
+
+    fn file(name: &str) -> TransferFile {
+        TransferFile::new(name, name, 1)
+    }
+
+    #[test]
+    fn test_plan_routes() {
+        let record = TransferRecord::incoming(&PendingTransfer {
+            id: "t1".to_string(),
+            source_ip: "10.0.0.5".to_string(),
+            sender_name: Some("build/box".to_string()),
+            files: vec![file("report.PDF"), file("notes.txt")],
+            total_size: 2,
+            received_at: Utc::now(),
+            kind: TransferKind::Files,
+            text_preview: None,
+        });
+        let favorites = vec![Favorite::new("CI".to_string(), "10.0.0.5".to_string())];
+        let rules = vec![
+            RoutingRule {
+                name: "Docs".to_string(),
+                enabled: true,
+                sender: None,
+                device_name: None,
+                file_patterns: vec!["*.pdf".to_string()],
+                mime_types: vec![],
+                directory: "Docs/{device}".to_string(),
+                conflict_policy: None,
+            },
+            RoutingRule {
+                name: "CI drops".to_string(),
+                enabled: true,
+                sender: Some("10.0.0.5".to_string()),
+                device_name: None,
+                file_patterns: vec![],
+                mime_types: vec![],
+                directory: "{sender}/{yyyy-mm-dd}".to_string(),
+                conflict_policy: None,
+            },
+        ];
+        let now = Local.with_ymd_and_hms(2026, 3, 4, 12, 0, 0).unwrap();
+
+        let routes = plan(&rules, &favorites, &record, Path::new("/dl"), now);
+        assert_eq!(routes.len(), 2);
+        assert_eq!(routes[0].rule, "Docs");
+        assert_eq!(routes[0].directory, Path::new("/dl/Docs/build_box"));
+        assert_eq!(routes[1].rule, "CI drops");
+        assert_eq!(routes[1].directory, Path::new("/dl/CI/2026-03-04"));
+    }
+
+    #[test]
+    fn test_plan_routes_by_mime_type() {
+        let mut photo = file("IMG_0001.jpg");
+        photo.mime_type = Some("image/jpeg".to_string());
+        let record = TransferRecord::incoming(&PendingTransfer {
+            id: "t1".to_string(),
+            source_ip: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![photo, file("clip.png.gosh.zst"), file("notes.txt")],
+            total_size: 3,
+            received_at: Utc::now(),
+            kind: TransferKind::Files,
+            text_preview: None,
+        });
+        let rules = vec![RoutingRule {
+            name: "Pictures".to_string(),
+            enabled: true,
+            sender: None,
+            device_name: None,
+            file_patterns: vec![],
+            mime_types: vec!["image/".to_string()],
+            directory: "/pictures".to_string(),
+            conflict_policy: None,
+        }];
+
+        let routes = plan(&rules, &[], &record, Path::new("/dl"), Local::now());
+        let names: Vec<&str> = routes.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["IMG_0001.jpg", "clip.png.gosh.zst"]);
+    }
+}
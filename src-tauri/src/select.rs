@@ -0,0 +1,434 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Selecting the files of a directory send
+//
+// A directory send can leave files out: exclude patterns drop matching files
+// and directories, include patterns (if any) keep only matching files, and
+// `.gitignore` / `.ignore` files in the tree can be honoured. Patterns follow
+// gitignore rules: a pattern without a slash matches a name at any depth, one
+// with a slash matches the path relative to the directory (or to the ignore
+// file), a trailing slash matches directories only, and in ignore files a
+// leading `!` re-includes what an earlier pattern excluded. Symbolic links
+// to directories are followed, each directory only once in case links form
+// a loop.
+//
+// The engine sends a directory as it is on disk, so the selected files are
+// hard-linked (or copied) into a temporary directory that is sent instead.
+// Sends preserving metadata also need the empty directories and the symbolic
+// links of the tree (see `metadata`).
+
+use crate::types::{AppError, DirectoryFilter, DirectoryPreview, PreviewFile};
+use glob::{MatchOptions, Pattern};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Ignore files read in each directory when ignore files are honoured
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+/// A single gitignore-style pattern
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    /// Path of the directory the rule applies to, relative to the root
+    base: String,
+    /// Match the path below `base` rather than just the name
+    anchored: bool,
+    dir_only: bool,
+    negated: bool,
+}
+
+impl Rule {
+    /// Parse a pattern; None for blank lines and comments
+    fn parse(line: &str, base: &str) -> Result<Option<Self>, AppError> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+
+        let pattern = Pattern::new(line)
+            .map_err(|e| AppError::InvalidConfig(format!("Invalid pattern {:?}: {}", line, e)))?;
+        Ok(Some(Self {
+            pattern,
+            base: base.to_string(),
+            anchored,
+            dir_only,
+            negated,
+        }))
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match self.base.as_str() {
+            "" => path,
+            base => match path
+                .strip_prefix(base)
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(rest) => rest,
+                None => return false,
+            },
+        };
+        if self.anchored {
+            self.pattern.matches_with(relative, MATCH_OPTIONS)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            self.pattern.matches_with(name, MATCH_OPTIONS)
+        }
+    }
+}
+
+/// Whether the last rule matching `path` excludes it
+fn excluded(rules: &[Rule], path: &str, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(path, is_dir))
+        .is_some_and(|rule| !rule.negated)
+}
+
+fn parse_all(patterns: &[String]) -> Result<Vec<Rule>, AppError> {
+    let mut rules = Vec::new();
+    for pattern in patterns {
+        rules.extend(Rule::parse(pattern, "")?);
+    }
+    Ok(rules)
+}
+
+/// Read the ignore files of a directory
+fn read_ignore_files(dir: &Path, base: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for name in IGNORE_FILES {
+        let content = match fs::read_to_string(dir.join(name)) {
+            Ok(content) => content,
+            Err(_) => continue,
+        };
+        for line in content.lines() {
+            match Rule::parse(line, base) {
+                Ok(rule) => rules.extend(rule),
+                Err(e) => tracing::warn!("Ignoring line of {:?}: {}", dir.join(name), e),
+            }
+        }
+    }
+    rules
+}
+
+/// The entries of a directory a filter selects
+#[derive(Debug, Default)]
+pub struct Tree {
+    pub files: Vec<PathBuf>,
+    /// Directories without any entries (kept only without include patterns)
+    pub empty_dirs: Vec<PathBuf>,
+    /// Symbolic links, if they are kept rather than followed
+    pub links: Vec<PathBuf>,
+    /// Files left out
+    pub excluded: usize,
+}
+
+struct Walk<'a> {
+    filter: &'a DirectoryFilter,
+    include: Vec<Rule>,
+    keep_links: bool,
+    /// Directories walked so far, canonicalized
+    visited: HashSet<PathBuf>,
+    tree: Tree,
+}
+
+impl Walk<'_> {
+    fn walk(&mut self, dir: &Path, relative: &str, rules: &mut Vec<Rule>) {
+        if let Ok(canonical) = dir.canonicalize() {
+            if !self.visited.insert(canonical) {
+                return;
+            }
+        }
+        let inherited = rules.len();
+        if self.filter.use_ignore_files {
+            rules.extend(read_ignore_files(dir, relative));
+        }
+
+        let mut entries: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries.flatten().collect(),
+            Err(e) => {
+                tracing::warn!("Failed to read {:?}: {}", dir, e);
+                rules.truncate(inherited);
+                return;
+            }
+        };
+        entries.sort_by_key(|entry| entry.file_name());
+        if entries.is_empty() && !relative.is_empty() && self.include.is_empty() {
+            self.tree.empty_dirs.push(dir.to_path_buf());
+        }
+
+        for entry in entries {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            let child = match relative {
+                "" => name,
+                _ => format!("{}/{}", relative, name),
+            };
+            let is_dir = path.is_dir();
+            let is_link = self.keep_links && entry.file_type().is_ok_and(|t| t.is_symlink());
+
+            if excluded(rules, &child, is_dir) {
+                self.tree.excluded += if is_dir && !is_link {
+                    count_files(&path, &mut HashSet::new())
+                } else {
+                    1
+                };
+            } else if is_dir && !is_link {
+                self.walk(&path, &child, rules);
+            } else if !self.include.is_empty()
+                && !self.include.iter().any(|rule| rule.matches(&child, false))
+            {
+                self.tree.excluded += 1;
+            } else if is_link {
+                self.tree.links.push(path);
+            } else {
+                self.tree.files.push(path);
+            }
+        }
+        rules.truncate(inherited);
+    }
+}
+
+/// Count the files below `dir`, each directory only once in case links
+/// form a loop
+fn count_files(dir: &Path, visited: &mut HashSet<PathBuf>) -> usize {
+    if let Ok(canonical) = dir.canonicalize() {
+        if !visited.insert(canonical) {
+            return 0;
+        }
+    }
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| match entry.path() {
+                    path if path.is_dir() => count_files(&path, visited),
+                    _ => 1,
+                })
+                .sum()
+        })
+        .unwrap_or(0)
+}
+
+/// List the entries of `root` that `filter` selects
+///
+/// With `keep_links`, symbolic links are listed as links instead of being
+/// followed.
+pub fn select_tree(
+    root: &Path,
+    filter: &DirectoryFilter,
+    keep_links: bool,
+) -> Result<Tree, AppError> {
+    if !root.is_dir() {
+        return Err(AppError::FileIo(format!("Not a directory: {:?}", root)));
+    }
+
+    let mut rules = parse_all(&filter.exclude)?;
+    let mut walk = Walk {
+        filter,
+        include: parse_all(&filter.include)?,
+        keep_links,
+        visited: HashSet::new(),
+        tree: Tree::default(),
+    };
+    walk.walk(root, "", &mut rules);
+    Ok(walk.tree)
+}
+
+/// List the files of `root` that `filter` selects, and how many it leaves
+/// out
+pub fn select(root: &Path, filter: &DirectoryFilter) -> Result<(Vec<PathBuf>, usize), AppError> {
+    let tree = select_tree(root, filter, false)?;
+    Ok((tree.files, tree.excluded))
+}
+
+/// Preview which files of `root` a directory send with `filter` would send
+pub fn preview(root: &Path, filter: &DirectoryFilter) -> Result<DirectoryPreview, AppError> {
+    let (selected, excluded_count) = select(root, filter)?;
+    let files: Vec<PreviewFile> = selected
+        .iter()
+        .map(|path| PreviewFile {
+            name: path
+                .strip_prefix(root)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/"),
+            size: fs::metadata(path).map(|m| m.len()).unwrap_or(0),
+        })
+        .collect();
+
+    Ok(DirectoryPreview {
+        total_size: files.iter().map(|f| f.size).sum(),
+        file_count: files.len(),
+        excluded_count,
+        files,
+    })
+}
+
+/// Hard-link `from` to `to`, or copy it if linking fails
+pub fn link_or_copy(from: &Path, to: &Path) -> Result<(), AppError> {
+    fs::hard_link(from, to)
+        .or_else(|_| fs::copy(from, to).map(|_| ()))
+        .map_err(|e| AppError::FileIo(format!("Failed to copy {:?}: {}", from, e)))
+}
+
+/// The selected files of a directory, laid out for sending
+///
+/// The temporary directory is removed when dropped.
+pub struct Selection {
+    dir: PathBuf,
+    /// Directory to send in place of the original one
+    pub path: PathBuf,
+}
+
+impl Drop for Selection {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            tracing::warn!("Failed to remove {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+/// Lay out `files` of `root` in a temporary directory with the same name
+pub fn mirror(transfer_id: &str, root: &Path, files: &[PathBuf]) -> Result<Selection, AppError> {
+    let dir = std::env::temp_dir().join(format!("gosh-transfer-{}-selection", transfer_id));
+    let selection = Selection {
+        path: dir.join(root.file_name().unwrap_or_default()),
+        dir,
+    };
+
+    fs::create_dir_all(&selection.path)
+        .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", selection.path, e)))?;
+    for file in files {
+        let relative = file.strip_prefix(root).unwrap_or(file);
+        let target = selection.path.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", parent, e)))?;
+        }
+        link_or_copy(file, &target)?;
+    }
+    Ok(selection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let root = std::env::temp_dir().join(format!("gosh-select-{}", uuid::Uuid::new_v4()));
+        for dir in ["src", "node_modules/pkg", "target/debug", ".git", "docs"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in [
+            "src/main.rs",
+            "src/debug.log",
+            "node_modules/pkg/index.js",
+            "target/debug/app",
+            ".git/HEAD",
+            ".DS_Store",
+            "docs/keep.log",
+            "docs/guide.md",
+        ] {
+            fs::write(root.join(file), "x").unwrap();
+        }
+        fs::write(root.join(".gitignore"), "# build output\ntarget/\n*.log\n").unwrap();
+        fs::write(root.join("docs/.gitignore"), "!keep.log\n").unwrap();
+
+        let names = |filter: &DirectoryFilter| {
+            let (files, excluded) = select(&root, filter).unwrap();
+            let names: Vec<String> = files
+                .iter()
+                .map(|f| {
+                    f.strip_prefix(&root)
+                        .unwrap()
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect();
+            (names, excluded)
+        };
+
+        let mut filter = DirectoryFilter {
+            include: Vec::new(),
+            exclude: vec![
+                "node_modules/".to_string(),
+                ".git".to_string(),
+                ".DS_Store".to_string(),
+            ],
+            use_ignore_files: true,
+        };
+        assert_eq!(
+            names(&filter),
+            (
+                vec![
+                    ".gitignore".to_string(),
+                    "docs/.gitignore".to_string(),
+                    "docs/guide.md".to_string(),
+                    "docs/keep.log".to_string(),
+                    "src/main.rs".to_string(),
+                ],
+                5
+            )
+        );
+
+        filter.include = vec!["src/*.rs".to_string(), "*.md".to_string()];
+        assert_eq!(
+            names(&filter).0,
+            ["docs/guide.md".to_string(), "src/main.rs".to_string()]
+        );
+
+        filter.exclude = vec!["[".to_string()];
+        assert!(select(&root, &filter).is_err());
+
+        let preview = preview(&root, &DirectoryFilter::default()).unwrap();
+        assert_eq!(preview.file_count, 10);
+        assert_eq!(
+            preview.total_size,
+            preview.files.iter().map(|f| f.size).sum::<u64>()
+        );
+
+        let selection = mirror("t1", &root, &[root.join("src/main.rs")]).unwrap();
+        assert!(selection.path.join("src/main.rs").is_file());
+        assert!(!selection.path.join("docs").exists());
+        let temp = selection.dir.clone();
+        drop(selection);
+        assert!(!temp.exists());
+
+        // Links forming loops are walked and counted once
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&root, root.join("src/loop")).unwrap();
+            std::os::unix::fs::symlink(root.join("node_modules"), root.join("node_modules/pkg/up"))
+                .unwrap();
+            let filter = DirectoryFilter {
+                exclude: vec!["node_modules/".to_string()],
+                ..Default::default()
+            };
+            let (files, excluded) = select(&root, &filter).unwrap();
+            assert_eq!((files.len(), excluded), (9, 1));
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,683 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Outgoing transfers
+//
+// The engine reports sends under its own transfer ids, so outgoing transfers
+// are recorded in history by the send commands: a record is added before the
+// send starts and finished when the engine call returns. Records keep the
+// source paths and port, so a send can be repeated from history. Sends to a
+// peer with an upload limit go through a pacing relay (see `throttle`), and
+// compressed sends go out from a temporary copy (see `compress`), as do
+// filtered directories and directories sent with their metadata (see
+// `select` and `metadata`). Text messages are written to a temporary file
+// for the send (see `text`), and resumed sends go out from one holding the
+// rest of each file (see `resume`).
+
+use crate::commands::AppState;
+use crate::types::{
+    AppError, DirectoryFilter, FileStatus, TransferDirection, TransferFile, TransferKind,
+    TransferRecord, TransferStatus,
+};
+use crate::{checksum, compress, metadata, notifications, resume, select, sidecar, text, throttle};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+/// List the files a send will transfer, walking directories
+///
+/// Names are relative to the selected directory, as the peer will see them.
+/// Symbolic links are followed like the send does, each directory only once
+/// in case links form a loop.
+pub fn collect_files(paths: &[PathBuf]) -> Vec<TransferFile> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    for path in paths {
+        if path.is_dir() {
+            let base = path.parent().unwrap_or(path);
+            walk(path, base, &mut visited, &mut files);
+        } else {
+            push_file(
+                path,
+                Path::new(path.file_name().unwrap_or_default()),
+                &mut files,
+            );
+        }
+    }
+    files
+}
+
+fn walk(dir: &Path, base: &Path, visited: &mut HashSet<PathBuf>, files: &mut Vec<TransferFile>) {
+    if let Ok(canonical) = dir.canonicalize() {
+        if !visited.insert(canonical) {
+            return;
+        }
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("Failed to read {:?}: {}", dir, e);
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let link = fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink());
+        if link && !path.exists() {
+            tracing::warn!("Skipping broken link {:?}", path);
+        } else if path.is_dir() {
+            walk(&path, base, visited, files);
+        } else {
+            let name = path.strip_prefix(base).unwrap_or(&path).to_path_buf();
+            push_file(&path, &name, files);
+        }
+    }
+}
+
+fn push_file(path: &Path, name: &Path, files: &mut Vec<TransferFile>) {
+    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let mut file = TransferFile::new(
+        uuid::Uuid::new_v4().to_string(),
+        name.to_string_lossy().replace('\\', "/"),
+        size,
+    );
+    file.path = Some(path.to_path_buf());
+    files.push(file);
+}
+
+/// Create the history record for sending `paths` to a peer
+pub fn outgoing_record(
+    address: &str,
+    port: u16,
+    paths: Vec<PathBuf>,
+    directory: bool,
+) -> TransferRecord {
+    let mut record = TransferRecord::outgoing(address, collect_files(&paths));
+    record.port = Some(port);
+    record.source_paths = paths;
+    record.directory = directory;
+    record
+}
+
+/// Create the history record for sending a directory, limited to the files
+/// `filter` selects
+///
+/// With `preserve_metadata`, symbolic links are sent as links rather than
+/// followed, so they are not listed as files.
+pub fn directory_record(
+    address: &str,
+    port: u16,
+    directory: PathBuf,
+    filter: Option<DirectoryFilter>,
+    preserve_metadata: bool,
+) -> Result<TransferRecord, AppError> {
+    let filter = filter.filter(|f| !f.is_empty());
+    if filter.is_none() && !preserve_metadata {
+        return Ok(outgoing_record(address, port, vec![directory], true));
+    }
+
+    let tree = select::select_tree(
+        &directory,
+        &filter.clone().unwrap_or_default(),
+        preserve_metadata,
+    )?;
+    let base = directory.parent().unwrap_or(&directory);
+    let mut files = Vec::new();
+    for path in &tree.files {
+        push_file(path, path.strip_prefix(base).unwrap_or(path), &mut files);
+    }
+    let mut record = TransferRecord::outgoing(address, files);
+    record.port = Some(port);
+    record.source_paths = vec![directory];
+    record.directory = true;
+    record.directory_filter = filter;
+    record.preserve_metadata = preserve_metadata;
+    Ok(record)
+}
+
+/// Create the history record for sending a text message
+pub fn text_record(address: &str, port: u16, text: String) -> Result<TransferRecord, AppError> {
+    text::check(&text)?;
+    let mut file = TransferFile::new(
+        uuid::Uuid::new_v4().to_string(),
+        text::file_name(&text),
+        text.len() as u64,
+    );
+    file.mime_type = Some("text/plain".to_string());
+    let mut record = TransferRecord::outgoing(address, vec![file]);
+    record.port = Some(port);
+    record.kind = TransferKind::Text;
+    record.text = Some(text);
+    Ok(record)
+}
+
+/// Create the record for sending a sent transfer again
+///
+/// All files are sent again the way they were sent (directories with the
+/// same filter and metadata mode), or with `only_failed`
+/// just those that did not make it, sent as individual files. The address
+/// and port default to the original ones.
+pub fn resend_record(
+    original: &TransferRecord,
+    only_failed: bool,
+    address: Option<String>,
+    port: Option<u16>,
+) -> Result<TransferRecord, AppError> {
+    if original.direction != TransferDirection::Sent {
+        return Err(AppError::InvalidConfig(
+            "Only sent transfers can be re-sent".to_string(),
+        ));
+    }
+
+    if let (TransferKind::Text, Some(text)) = (original.kind, &original.text) {
+        let mut record = text_record(
+            &address.unwrap_or_else(|| original.peer_address.clone()),
+            port.or(original.port).ok_or_else(|| {
+                AppError::InvalidConfig("The port of this transfer is not known".to_string())
+            })?,
+            text.clone(),
+        )?;
+        record.resent_from = Some(original.id.clone());
+        return Ok(record);
+    }
+
+    let unknown =
+        || AppError::InvalidConfig("The source files of this transfer are not known".to_string());
+    let (paths, directory) = if only_failed {
+        let files = unfinished_files(original);
+        if files.is_empty() {
+            return Err(AppError::InvalidConfig(
+                "All files of this transfer were sent".to_string(),
+            ));
+        }
+        let paths: Option<Vec<PathBuf>> = files.into_iter().map(|f| f.path.clone()).collect();
+        (paths.ok_or_else(unknown)?, false)
+    } else if original.source_paths.is_empty() {
+        return Err(unknown());
+    } else {
+        (original.source_paths.clone(), original.directory)
+    };
+    if let Some(missing) = paths.iter().find(|p| !p.exists()) {
+        return Err(AppError::FileIo(format!(
+            "Source no longer exists: {:?}",
+            missing
+        )));
+    }
+
+    let address = address.unwrap_or_else(|| original.peer_address.clone());
+    let port = port.or(original.port).ok_or_else(|| {
+        AppError::InvalidConfig("The port of this transfer is not known".to_string())
+    })?;
+    let mut record = match paths.first() {
+        Some(path) if directory => directory_record(
+            &address,
+            port,
+            path.clone(),
+            original.directory_filter.clone(),
+            original.preserve_metadata,
+        )?,
+        _ => outgoing_record(&address, port, paths, false),
+    };
+    record.resent_from = Some(original.id.clone());
+    Ok(record)
+}
+
+/// Create the record for resuming a failed or cancelled send
+///
+/// The files that did not make it are offered to the same peer again, each
+/// one the peer got part of from an offset (see `resume`).
+pub fn resume_record(original: &TransferRecord) -> Result<TransferRecord, AppError> {
+    if original.direction != TransferDirection::Sent || original.kind == TransferKind::Text {
+        return Err(AppError::InvalidConfig(
+            "Only sent files can be resumed".to_string(),
+        ));
+    }
+    if !matches!(
+        original.status,
+        TransferStatus::Failed | TransferStatus::Cancelled
+    ) {
+        return Err(AppError::InvalidConfig(
+            "Only failed or cancelled sends can be resumed".to_string(),
+        ));
+    }
+
+    let files = resume::unfinished(original);
+    if files.is_empty() {
+        return Err(AppError::InvalidConfig(
+            "All files of this transfer were sent".to_string(),
+        ));
+    }
+    for file in &files {
+        let source = file.path.as_ref().ok_or_else(|| {
+            AppError::InvalidConfig("The source files of this transfer are not known".to_string())
+        })?;
+        // The part the peer kept must be the start of the same file
+        match fs::metadata(source) {
+            Ok(metadata) if metadata.len() == file.size => {}
+            Ok(_) => {
+                return Err(AppError::FileIo(format!(
+                    "Source changed since it was sent: {:?}",
+                    source
+                )))
+            }
+            Err(_) => {
+                return Err(AppError::FileIo(format!(
+                    "Source no longer exists: {:?}",
+                    source
+                )))
+            }
+        }
+    }
+
+    let port = original.port.ok_or_else(|| {
+        AppError::InvalidConfig("The port of this transfer is not known".to_string())
+    })?;
+    let mut record = TransferRecord::outgoing(&original.peer_address, files);
+    record.total_size = record.files.iter().map(|f| f.transfer_size()).sum();
+    record.port = Some(port);
+    record.source_paths = original.source_paths.clone();
+    record.directory = original.directory;
+    record.resumed_from = Some(original.id.clone());
+    Ok(record)
+}
+
+/// Files of a sent transfer that did not make it
+///
+/// Records without per-file outcomes (from older versions) fall back to the
+/// bytes transferred: files are sent in order, so the ones past it are
+/// missing or incomplete.
+pub fn unfinished_files(record: &TransferRecord) -> Vec<&TransferFile> {
+    if record.status == TransferStatus::Completed {
+        return Vec::new();
+    }
+    if record.files.iter().any(|f| f.status.is_some()) {
+        return record
+            .files
+            .iter()
+            .filter(|f| f.status != Some(FileStatus::Completed))
+            .collect();
+    }
+    let mut end = 0;
+    record
+        .files
+        .iter()
+        .filter(|file| {
+            end += file.size;
+            end > record.bytes_transferred
+        })
+        .collect()
+}
+
+/// Send the files of `record` and record the send in history
+///
+/// The files are hashed before they are sent. They are compressed if
+/// `compress` is set, or if it is not given and the peer is a favorite with
+/// compression turned on.
+pub async fn run(
+    app_handle: &AppHandle,
+    record: TransferRecord,
+    compress: Option<bool>,
+) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let address = record.peer_address.clone();
+    let port = record.port.ok_or("No port to send to")?;
+    let paths = record.source_paths.clone();
+    let directory = record.directory;
+    let filter = record.directory_filter.clone();
+    let preserve_metadata = directory && record.preserve_metadata;
+    let staged = directory && (filter.is_some() || preserve_metadata);
+    let message = match record.kind {
+        TransferKind::Text => record.text.clone(),
+        TransferKind::Files => None,
+    };
+    let sources: HashMap<String, PathBuf> = record
+        .files
+        .iter()
+        .filter_map(|f| Some((f.name.clone(), f.path.clone()?)))
+        .collect();
+    let selected: Vec<PathBuf> = record.files.iter().filter_map(|f| f.path.clone()).collect();
+    let resumed = record.resumed_from.is_some().then(|| record.files.clone());
+    let id = record_started(&state, record);
+
+    let selection = match paths.first() {
+        Some(root) if staged => {
+            let (transfer_id, root) = (id.clone(), root.clone());
+            let mirrored = tokio::task::spawn_blocking(move || match preserve_metadata {
+                true => {
+                    metadata::stage(&transfer_id, &root, &selected, &filter.unwrap_or_default())
+                }
+                false => select::mirror(&transfer_id, &root, &selected),
+            })
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|result| result.map_err(|e| e.to_string()));
+            match mirrored {
+                Ok(selection) => Some(selection),
+                Err(e) => return fail_start(app_handle, &id, e).await,
+            }
+        }
+        _ => None,
+    };
+    let paths = match &selection {
+        Some(selection) => vec![selection.path.clone()],
+        None => paths,
+    };
+    let text_file = match message {
+        Some(message) => match tokio::task::spawn_blocking(move || text::write(&message)).await {
+            Ok(Ok(file)) => Some(file),
+            Ok(Err(e)) => return fail_start(app_handle, &id, e.to_string()).await,
+            Err(e) => return fail_start(app_handle, &id, e.to_string()).await,
+        },
+        None => None,
+    };
+    let paths = match &text_file {
+        Some(file) => vec![file.path.clone()],
+        None => paths,
+    };
+
+    // Text messages are recognized by their file name, so never compressed
+    let compress = text_file.is_none()
+        && compress.unwrap_or_else(|| {
+            state.favorites.list().iter().any(|f| {
+                f.compress
+                    && (f.address == address || f.last_resolved_ip.as_deref() == Some(&address))
+            })
+        });
+    // What the peer takes besides the files, if it can be asked
+    let info = match &text_file {
+        Some(_) => None,
+        None => {
+            let engine = state.engine.lock().await;
+            engine.get_peer_info(&address, port).await.ok()
+        }
+    };
+    let takes_sidecar = info.as_ref().is_some_and(sidecar::peer_supports);
+    if !takes_sidecar {
+        tracing::info!("{} does not take a sidecar", address);
+    }
+    let tails = match resumed {
+        Some(files) => match stage_resumed(&state, &id, files, takes_sidecar, directory).await {
+            Ok(tails) => Some(tails),
+            Err(e) => return fail_start(app_handle, &id, e.to_string()).await,
+        },
+        None => None,
+    };
+    let (paths, offsets) = match &tails {
+        Some((tails, offsets)) => (tails.paths.clone(), offsets.clone()),
+        None => (paths, HashMap::new()),
+    };
+
+    // Resumed files must go out as they are
+    let packed = match compress && tails.is_none() {
+        true => pack(&id, &address, info.as_ref(), &paths, directory).await,
+        false => None,
+    };
+    let mut paths = match &packed {
+        Some(packed) => packed.paths.clone(),
+        None => paths,
+    };
+
+    // Text messages are a single file the receiver recognizes by its name
+    // A directory send only carries a sidecar in a temporary copy
+    let copied = selection.is_some() || packed.is_some() || tails.is_some();
+    let (digests, sidecar) = match &text_file {
+        Some(_) => (HashMap::new(), None),
+        None => {
+            let packed = packed
+                .as_ref()
+                .map(|p| p.digests.clone())
+                .unwrap_or_default();
+            let send = takes_sidecar && (copied || !directory);
+            write_sidecar(&id, &paths, send, directory, packed, sources, offsets).await
+        }
+    };
+    // First, so a receiver whose transfer breaks still has it
+    if let Some(sidecar) = &sidecar {
+        paths.insert(0, sidecar.path.clone());
+    }
+
+    let relay = start_relay(app_handle, &address, port).await;
+    let (target, target_port) = match &relay {
+        Some(relay) => (Ipv4Addr::LOCALHOST.to_string(), relay.port),
+        None => (address, port),
+    };
+    let engine = state.engine.lock().await;
+    let result = match paths.first() {
+        Some(path) if directory => {
+            engine
+                .send_directory(&target, target_port, path.clone())
+                .await
+        }
+        _ => engine.send_files(&target, target_port, paths).await,
+    }
+    .map_err(|e| e.to_string());
+    drop(engine);
+    drop(relay);
+    drop(selection);
+    drop(text_file);
+    drop(tails);
+    drop(sidecar);
+
+    let compression = packed.as_ref().map(|packed| packed.info.clone());
+    drop(packed);
+    let _ = state.history_store.update(&id, |record| {
+        checksum::apply(&mut record.files, &digests);
+        record.compression = compression;
+    });
+    record_finished(app_handle, &id, result.clone().err()).await;
+    result
+}
+
+/// Fail a send that could not be started
+async fn fail_start(app_handle: &AppHandle, id: &str, error: String) -> Result<(), String> {
+    record_finished(app_handle, id, Some(error.clone())).await;
+    Err(error)
+}
+
+/// Hash the files about to be sent and write the sidecar carrying their
+/// digests, modification times and offsets
+///
+/// The sidecar is only written if `send`. A directory send (which must go
+/// out from a temporary copy) carries it in the directory; a file send gets
+/// it back to send along. Either way the digests are returned by file name.
+async fn write_sidecar(
+    id: &str,
+    paths: &[PathBuf],
+    send: bool,
+    directory: bool,
+    packed: HashMap<PathBuf, String>,
+    sources: HashMap<String, PathBuf>,
+    offsets: HashMap<String, u64>,
+) -> (HashMap<String, String>, Option<sidecar::SidecarFile>) {
+    let (id, paths) = (id.to_string(), paths.to_vec());
+    let written = tokio::task::spawn_blocking(move || {
+        let sidecar = sidecar::build(&paths, &packed, &sources, &offsets);
+        let file = match paths.first() {
+            _ if !send => Ok(None),
+            Some(dir) if directory => sidecar::write(dir, &id, &sidecar).map(|_| None),
+            _ => sidecar::write_temp(&id, &sidecar).map(Some),
+        };
+        let file = file
+            .map_err(|e| tracing::warn!("Sending without a sidecar: {}", e))
+            .unwrap_or_default();
+        (sidecar.digests(), file)
+    })
+    .await;
+    written.unwrap_or_default()
+}
+
+/// Lay out the files of a resumed send, returning them with the offsets of
+/// those sent from one
+///
+/// Without a sidecar to tell the offsets, the files are sent in full and
+/// the record says so.
+async fn stage_resumed(
+    state: &AppState,
+    id: &str,
+    mut files: Vec<TransferFile>,
+    takes_sidecar: bool,
+    directory: bool,
+) -> Result<(resume::Tails, HashMap<String, u64>), AppError> {
+    if !takes_sidecar && files.iter().any(|f| f.offset.is_some()) {
+        for file in &mut files {
+            file.offset = None;
+        }
+        let _ = state.history_store.update(id, |record| {
+            record.files = files.clone();
+            record.total_size = files.iter().map(|f| f.size).sum();
+        });
+    }
+    let offsets = files
+        .iter()
+        .filter_map(|f| Some((f.name.clone(), f.offset?)))
+        .collect();
+
+    let id = id.to_string();
+    let tails = tokio::task::spawn_blocking(move || resume::stage(&id, &files, directory))
+        .await
+        .map_err(|e| AppError::FileIo(e.to_string()))??;
+    Ok((tails, offsets))
+}
+
+/// Compress the files of a send, if the peer lists zstd among its
+/// capabilities
+///
+/// The receiver only decompresses files its sidecar marks, so the peer must
+/// take a sidecar too. Files are sent as they are if the peer cannot be
+/// asked (`info` is None), or if compressing them fails.
+async fn pack(
+    id: &str,
+    address: &str,
+    info: Option<&serde_json::Value>,
+    paths: &[PathBuf],
+    directory: bool,
+) -> Option<compress::Packed> {
+    if !info.is_some_and(|info| compress::peer_supports(info) && sidecar::peer_supports(info)) {
+        tracing::info!("{} does not support compression", address);
+        return None;
+    }
+
+    let (id, paths) = (id.to_string(), paths.to_vec());
+    match tokio::task::spawn_blocking(move || compress::pack(&id, &paths, directory)).await {
+        Ok(Ok(packed)) => packed,
+        Ok(Err(e)) => {
+            tracing::warn!("Sending uncompressed: {}", e);
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Start a relay pacing the send if the peer has an upload limit
+///
+/// Sends go out unthrottled if the relay cannot start.
+async fn start_relay(app_handle: &AppHandle, address: &str, port: u16) -> Option<throttle::Relay> {
+    let state = app_handle.state::<AppState>();
+    if !throttle::limits_uploads(&state.settings.read().await.bandwidth, address) {
+        return None;
+    }
+    throttle::start_relay(app_handle.clone(), address.to_string(), port)
+        .await
+        .map_err(|e| tracing::warn!("Sending to {} without a rate limit: {}", address, e))
+        .ok()
+}
+
+/// Add the history record for a send that is about to start and track it
+pub fn record_started(state: &AppState, record: TransferRecord) -> String {
+    let id = record.id.clone();
+    state
+        .tracker
+        .begin_send(&id, &record.peer_address, record.files.clone());
+    if let Err(e) = state.history_store.add(record) {
+        tracing::warn!("Failed to record send {}: {}", id, e);
+    }
+    id
+}
+
+/// Finish the history record of a send and notify the user
+///
+/// A failed send keeps the bytes the engine reported as sent. The send
+/// counts as accepted from the first progress the engine reported.
+pub async fn record_finished(app_handle: &AppHandle, id: &str, error: Option<String>) {
+    let state = app_handle.state::<AppState>();
+    let sent = state.tracker.end_send(id);
+
+    if let Some(sent) = &sent {
+        let _ = state.history_store.update(id, |record| {
+            record.accepted_at = sent.accepted_at;
+            if error.is_some() {
+                record.bytes_transferred = sent.bytes_transferred;
+            }
+        });
+    }
+    let status = if error.is_some() {
+        TransferStatus::Failed
+    } else {
+        TransferStatus::Completed
+    };
+    if let Err(e) = state.history_store.set_status(id, status, error) {
+        tracing::warn!("Failed to update send {}: {}", id, e);
+    }
+
+    if let Some(record) = state.history_store.get(id) {
+        notifications::notify_finished(app_handle, &record).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_collect_files_link_loop() {
+        let dir = std::env::temp_dir().join(format!("gosh-send-{}", uuid::Uuid::new_v4()));
+        let root = dir.join("project");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.txt"), "a").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("loop")).unwrap();
+        std::os::unix::fs::symlink(dir.join("missing"), root.join("broken")).unwrap();
+
+        let files = collect_files(std::slice::from_ref(&root));
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["project/a.txt"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resend_record() {
+        let dir = std::env::temp_dir().join(format!("gosh-send-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<PathBuf> = ["a.bin", "b.bin", "c.bin"]
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                fs::write(&path, [0u8; 10]).unwrap();
+                path
+            })
+            .collect();
+
+        let mut original = outgoing_record("10.0.0.2", 53317, paths.clone(), false);
+        original.status = TransferStatus::Failed;
+        original.bytes_transferred = 15;
+
+        let failed = resend_record(&original, true, None, None).unwrap();
+        assert_eq!(failed.source_paths, paths[1..]);
+        assert_eq!(failed.port, Some(53317));
+        assert_eq!(failed.resent_from.as_deref(), Some(original.id.as_str()));
+
+        let all =
+            resend_record(&original, false, Some("10.0.0.3".to_string()), Some(8080)).unwrap();
+        assert_eq!(all.source_paths, paths);
+        assert_eq!(all.peer_address, "10.0.0.3");
+        assert_eq!(all.port, Some(8080));
+
+        fs::remove_file(&paths[0]).unwrap();
+        assert!(resend_record(&original, false, None, None).is_err());
+        original.status = TransferStatus::Completed;
+        assert!(resend_record(&original, true, None, None).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
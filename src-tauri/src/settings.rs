@@ -4,7 +4,7 @@
 // Settings are stored in a local JSON file.
 // No cloud sync, no tracking, just simple local persistence.
 
-use crate::types::{AppError, AppSettings};
+use crate::types::{AppError, AppSettings, NotificationSettings};
 use std::fs;
 use std::path::PathBuf;
 use std::sync::RwLock;
@@ -26,10 +26,13 @@ impl SettingsStore {
             let content = fs::read_to_string(&file_path)
                 .map_err(|e| AppError::FileIo(format!("Failed to read settings: {}", e)))?;
 
-            serde_json::from_str(&content).unwrap_or_else(|e| {
-                tracing::warn!("Failed to parse settings, using defaults: {}", e);
-                AppSettings::default()
-            })
+            serde_json::from_str(&content)
+                .map(migrate)
+                .and_then(serde_json::from_value)
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Failed to parse settings, using defaults: {}", e);
+                    AppSettings::default()
+                })
         } else {
             tracing::info!("No settings file found, using defaults");
             AppSettings::default()
@@ -99,6 +102,23 @@ impl SettingsStore {
     }
 }
 
+/// Upgrade settings written by older versions
+///
+/// `notificationsEnabled: false` becomes all per-event notifications off.
+fn migrate(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(object) = value.as_object_mut() {
+        if let Some(enabled) = object.remove("notificationsEnabled") {
+            if enabled == serde_json::Value::Bool(false) && !object.contains_key("notifications") {
+                if let Ok(disabled) = serde_json::to_value(NotificationSettings::disabled()) {
+                    object.insert("notifications".to_string(), disabled);
+                }
+            }
+        }
+    }
+
+    value
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -109,4 +129,20 @@ mod tests {
         assert_eq!(settings.port, 53317);
         assert_eq!(settings.theme, "system");
     }
+
+    #[test]
+    fn test_migrate_notifications_enabled() {
+        let old = serde_json::json!({
+            "port": 53317,
+            "deviceName": "Test",
+            "downloadDir": "/tmp",
+            "trustedHosts": [],
+            "receiveOnly": false,
+            "notificationsEnabled": false
+        });
+
+        let settings: AppSettings = serde_json::from_value(migrate(old)).unwrap();
+        assert!(!settings.notifications.transfer_complete);
+        assert!(!settings.notifications.incoming_request);
+    }
 }
@@ -0,0 +1,342 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Transfer sidecar
+//
+// The engine only transfers file contents, so a send carries what the
+// receiver needs to check them in a sidecar file: the SHA-256 digest of
+// each file, taken from the bytes handed to the engine (for a compressed
+// file, the bytes that were compressed), the modification time of the
+// original file, which name conflicts are decided by, and whether the file
+// was compressed for the transfer, or sent from an offset to resume an
+// interrupted transfer (see `resume`). File sends add it as their first file;
+// directory sends that go out from a temporary copy (filtered,
+// metadata-preserving or compressed) put it at the top of the copy. Other
+// directory sends and text messages carry no sidecar. It is named after the
+// send, `.gosh-sidecar-<id>.json`, since transfers received at the same
+// time share a directory (see `staging`). Only peers listing "sidecar" in
+// their `/info` capabilities get one; others would keep it as a file.
+//
+// The receiver takes the sidecar out of staging before anything else looks
+// at the transfer, and checks each received file against it.
+
+use crate::checksum;
+use crate::filter;
+use crate::types::{AppError, TransferRecord};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+const FILE_PREFIX: &str = ".gosh-sidecar";
+const FILE_SUFFIX: &str = ".json";
+
+/// Name of the sidecar file of the send `transfer_id`
+pub fn file_name(transfer_id: &str) -> String {
+    format!("{}-{}{}", FILE_PREFIX, transfer_id, FILE_SUFFIX)
+}
+
+/// Whether `name` is the name of a sidecar file
+pub fn is_file_name(name: &str) -> bool {
+    name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX)
+}
+
+/// Whether a peer's `/info` response allows sending a sidecar
+///
+/// Only peers listing "sidecar" in their capabilities do.
+pub fn peer_supports(info: &serde_json::Value) -> bool {
+    info.get("capabilities")
+        .and_then(|c| c.as_array())
+        .is_some_and(|capabilities| capabilities.iter().any(|c| c == "sidecar"))
+}
+
+/// One file of a transfer, named as the receiver will see it once unpacked
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SidecarEntry {
+    pub name: String,
+    pub sha256: String,
+    /// Modification time of the sender's file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    /// Sent compressed, as `<name>.gosh.zst`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compressed: bool,
+    /// Sent from this byte on; the receiver has the bytes before it from an
+    /// interrupted transfer. The digest is that of the whole file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+}
+
+/// What the sender tells the receiver about the files of a transfer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sidecar {
+    pub files: Vec<SidecarEntry>,
+}
+
+impl Sidecar {
+    /// What the sender told about a file
+    pub fn entry(&self, name: &str) -> Option<&SidecarEntry> {
+        self.files.iter().find(|entry| entry.name == name)
+    }
+
+    /// Names of the files sent compressed, as they are once unpacked
+    pub fn compressed(&self) -> HashSet<String> {
+        self.files
+            .iter()
+            .filter(|entry| entry.compressed)
+            .map(|entry| entry.name.clone())
+            .collect()
+    }
+
+    /// Offsets of the files sent from an offset, by file name
+    pub fn offsets(&self) -> HashMap<String, u64> {
+        self.files
+            .iter()
+            .filter_map(|entry| Some((entry.name.clone(), entry.offset?)))
+            .collect()
+    }
+
+    /// Digests by file name
+    pub fn digests(&self) -> HashMap<String, String> {
+        self.files
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.sha256.clone()))
+            .collect()
+    }
+}
+
+/// Build the sidecar of the paths about to be sent
+///
+/// `packed` holds the digests of compressed files, by compressed path; they
+/// are listed under their original name. Modification times are taken from
+/// `sources`, the original files by name, as the sent paths may be copies.
+/// Files sent from an offset (`offsets`, by name) are the rest of their
+/// source, whose digest is taken instead.
+pub fn build(
+    paths: &[PathBuf],
+    packed: &HashMap<PathBuf, String>,
+    sources: &HashMap<String, PathBuf>,
+    offsets: &HashMap<String, u64>,
+) -> Sidecar {
+    let mut sidecar = Sidecar::default();
+    for path in paths {
+        let base = path.parent().unwrap_or(path);
+        add(&mut sidecar, path, base, packed);
+    }
+    for entry in &mut sidecar.files {
+        let source = sources.get(&entry.name);
+        entry.modified = source
+            .and_then(|source| fs::metadata(source).and_then(|m| m.modified()).ok())
+            .map(DateTime::<Utc>::from);
+        let (offset, source) = match (offsets.get(&entry.name), source) {
+            (Some(&offset), Some(source)) => (offset, source),
+            _ => continue,
+        };
+        // Without the whole file's digest the receiver fails the file
+        entry.offset = Some(offset);
+        match checksum::sha256_file(source) {
+            Ok(digest) => entry.sha256 = digest,
+            Err(e) => tracing::warn!("Cannot take the digest of {:?}: {}", source, e),
+        }
+    }
+    sidecar
+}
+
+fn add(sidecar: &mut Sidecar, path: &Path, base: &Path, packed: &HashMap<PathBuf, String>) {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            tracing::warn!("Leaving {:?} out of the sidecar: {}", path, e);
+            return;
+        }
+    };
+    if metadata.is_dir() {
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                tracing::warn!("Leaving {:?} out of the sidecar: {}", path, e);
+                return;
+            }
+        };
+        for entry in entries.flatten() {
+            add(sidecar, &entry.path(), base, packed);
+        }
+        return;
+    }
+    if !metadata.is_file() {
+        return;
+    }
+
+    let name = path
+        .strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let entry = match packed.get(path) {
+        Some(digest) => SidecarEntry {
+            name: crate::compress::original_name(&name).to_string(),
+            sha256: digest.clone(),
+            modified: None,
+            compressed: true,
+            offset: None,
+        },
+        None => match checksum::sha256_file(path) {
+            Ok(digest) => SidecarEntry {
+                name,
+                sha256: digest,
+                modified: None,
+                compressed: false,
+                offset: None,
+            },
+            Err(e) => {
+                tracing::warn!("Leaving {:?} out of the sidecar: {}", path, e);
+                return;
+            }
+        },
+    };
+    sidecar.files.push(entry);
+}
+
+/// Write the sidecar of the send `transfer_id` to `dir`, returning its path
+pub fn write(dir: &Path, transfer_id: &str, sidecar: &Sidecar) -> Result<PathBuf, AppError> {
+    let json =
+        serde_json::to_vec_pretty(sidecar).map_err(|e| AppError::Serialization(e.to_string()))?;
+    let path = dir.join(file_name(transfer_id));
+    fs::write(&path, json)
+        .map_err(|e| AppError::FileIo(format!("Failed to write {:?}: {}", path, e)))?;
+    Ok(path)
+}
+
+/// A sidecar written out for a file send
+///
+/// The temporary directory is removed when dropped.
+pub struct SidecarFile {
+    dir: PathBuf,
+    /// File to send along with the others
+    pub path: PathBuf,
+}
+
+impl Drop for SidecarFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            tracing::warn!("Failed to remove {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+/// Write the sidecar of a file send to a temporary directory
+pub fn write_temp(transfer_id: &str, sidecar: &Sidecar) -> Result<SidecarFile, AppError> {
+    let dir = std::env::temp_dir().join(format!("gosh-transfer-{}-sidecar", transfer_id));
+    fs::create_dir_all(&dir)
+        .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", dir, e)))?;
+    let path = write(&dir, transfer_id, sidecar)?;
+    Ok(SidecarFile { dir, path })
+}
+
+/// Take the sidecar of a received transfer out of staging and of the record
+///
+/// It is at the top of the transfer, or at the top of a sent directory.
+pub fn extract(staging: &Path, record: &mut TransferRecord) -> Option<Sidecar> {
+    let index = record.files.iter().position(|file| {
+        let components: Vec<Component> = Path::new(&file.name).components().collect();
+        match components.as_slice() {
+            [Component::Normal(name)] | [Component::Normal(_), Component::Normal(name)] => {
+                is_file_name(&name.to_string_lossy())
+            }
+            _ => false,
+        }
+    })?;
+    let path = filter::received_path(staging, &record.files[index].name)?;
+    let sidecar = fs::read(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string()));
+    let sidecar = match sidecar {
+        Ok(sidecar) => sidecar,
+        Err(e) => {
+            tracing::warn!("Ignoring {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    let _ = fs::remove_file(&path);
+    let file = record.files.remove(index);
+    record.total_size = record.total_size.saturating_sub(file.size);
+    Some(sidecar)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PendingTransfer, TransferFile, TransferKind};
+
+    #[test]
+    fn test_peer_supports() {
+        assert!(!peer_supports(&serde_json::json!({ "deviceName": "pc" })));
+        assert!(!peer_supports(
+            &serde_json::json!({ "capabilities": ["zstd"] })
+        ));
+        assert!(peer_supports(
+            &serde_json::json!({ "capabilities": ["zstd", "sidecar"] })
+        ));
+    }
+
+    #[test]
+    fn test_build_and_extract() {
+        let dir = std::env::temp_dir().join(format!("gosh-sidecar-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(dir.join("docs/sub")).unwrap();
+        fs::write(dir.join("docs/sub/abc.txt"), b"abc").unwrap();
+        fs::write(dir.join("docs/a.log.gosh.zst"), b"packed").unwrap();
+        let packed = HashMap::from([(dir.join("docs/a.log.gosh.zst"), "feed".to_string())]);
+
+        let sources = HashMap::from([("docs/a.log".to_string(), dir.join("docs/sub/abc.txt"))]);
+
+        let sidecar = build(&[dir.join("docs")], &packed, &sources, &HashMap::new());
+        assert_eq!(sidecar.files.len(), 2);
+        let abc = sidecar.entry("docs/sub/abc.txt").unwrap();
+        assert_eq!(
+            abc.sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(abc.modified.is_none());
+        let log = sidecar.entry("docs/a.log").unwrap();
+        assert_eq!(log.sha256, "feed");
+        assert!(log.modified.is_some());
+        assert_eq!(
+            sidecar.compressed(),
+            HashSet::from(["docs/a.log".to_string()])
+        );
+
+        // The rest of abc.txt, sent from an offset
+        fs::write(dir.join("abc.txt"), b"c").unwrap();
+        let sources = HashMap::from([("abc.txt".to_string(), dir.join("docs/sub/abc.txt"))]);
+        let offsets = HashMap::from([("abc.txt".to_string(), 2)]);
+        let resumed = build(&[dir.join("abc.txt")], &HashMap::new(), &sources, &offsets);
+        assert_eq!(resumed.files[0].sha256, abc.sha256);
+        assert_eq!(resumed.offsets(), offsets);
+
+        // Receive it at the top of the sent directory
+        write(&dir.join("docs"), "s1", &sidecar).unwrap();
+        let name = format!("docs/{}", file_name("s1"));
+        assert!(is_file_name(&file_name("s1")));
+        let mut record = TransferRecord::incoming(&PendingTransfer {
+            id: "t1".to_string(),
+            source_ip: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![
+                TransferFile::new("f1", "docs/sub/abc.txt", 3),
+                TransferFile::new("f2", &name, 10),
+            ],
+            total_size: 13,
+            received_at: chrono::Utc::now(),
+            kind: TransferKind::Files,
+            text_preview: None,
+        });
+        assert_eq!(extract(&dir, &mut record), Some(sidecar));
+        assert_eq!(record.files.len(), 1);
+        assert_eq!(record.total_size, 3);
+        assert!(!dir.join(&name).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
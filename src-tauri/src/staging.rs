@@ -0,0 +1,363 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Per-transfer staging
+//
+// The engine writes received files into its download directory, of which it
+// has only one. It is pointed at `.gosh-incoming/.receiving` in the download
+// directory, shared by the transfers being received, and once a transfer
+// ends its entries are moved into a staging directory of its own,
+// `.gosh-incoming/<transfer id>`, where the rest of the pipeline finds them.
+//
+// Transfers are received at the same time as long as they write to
+// different entries of the shared directory. One whose top-level names clash
+// with those of a transfer being received or waiting (names are compared
+// ignoring case) waits, and is accepted by the engine once the clash is
+// gone; so does one accepted into another download directory while
+// transfers are received into the old one.
+
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the staging directory inside the download directory
+const STAGING_DIR_NAME: &str = ".gosh-incoming";
+
+/// Name of the directory the engine receives into, inside the staging root
+const RECEIVING_DIR_NAME: &str = ".receiving";
+
+/// Directory holding the staging directories of all transfers
+pub fn staging_root(download_dir: &Path) -> PathBuf {
+    download_dir.join(STAGING_DIR_NAME)
+}
+
+/// Staging root a staged file is in, if any
+pub fn root_of(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|p| p.ends_with(STAGING_DIR_NAME))
+}
+
+/// Staging directory of one transfer
+pub fn transfer_dir(download_dir: &Path, transfer_id: &str) -> PathBuf {
+    staging_root(download_dir).join(transfer_id)
+}
+
+/// Shared directory the engine receives a transfer staged in `dir` into
+pub fn receiving_dir(dir: &Path) -> PathBuf {
+    dir.parent().unwrap_or(dir).join(RECEIVING_DIR_NAME)
+}
+
+/// Create the directory the engine receives into and return its path
+pub fn prepare_receiving(download_dir: &Path) -> PathBuf {
+    let dir = staging_root(download_dir).join(RECEIVING_DIR_NAME);
+    if let Err(e) = fs::create_dir_all(&dir) {
+        tracing::warn!("Failed to create receiving dir {:?}: {}", dir, e);
+    }
+    dir
+}
+
+/// Move what a transfer received out of the shared receiving directory into
+/// its staging directory `dir`
+///
+/// `names` are the names of its files; their top-level entries are moved.
+pub fn collect<'a>(dir: &Path, names: impl IntoIterator<Item = &'a str>) {
+    let receiving = receiving_dir(dir);
+    let mut moved = HashSet::new();
+    for name in names {
+        let top = match top_level(name) {
+            Some(top) if moved.insert(top.to_string()) => top,
+            _ => continue,
+        };
+        let source = receiving.join(top);
+        if source.symlink_metadata().is_err() {
+            continue;
+        }
+        if let Err(e) = fs::create_dir_all(dir) {
+            tracing::warn!("Failed to create staging dir {:?}: {}", dir, e);
+            return;
+        }
+        if let Err(e) = fs::rename(&source, dir.join(top)) {
+            tracing::warn!("Failed to move {:?} into staging: {}", source, e);
+        }
+    }
+}
+
+/// Remove a transfer's staging directory and whatever is left in it
+pub fn remove(dir: &Path) {
+    if dir.exists() {
+        if let Err(e) = fs::remove_dir_all(dir) {
+            tracing::warn!("Failed to remove staging dir {:?}: {}", dir, e);
+        }
+    }
+}
+
+/// Remove everything in the staging root that no transfer needs any more
+///
+/// Entries holding one of the `keep` paths (files waiting for a name
+/// conflict to be resolved, directories of transfers being received) stay.
+pub fn remove_stale(root: &Path, keep: &[PathBuf]) {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if keep.iter().any(|kept| kept.starts_with(&path)) {
+            continue;
+        }
+        tracing::info!("Removing stale staging entry {:?}", path);
+        let result = if path.is_dir() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to remove {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Accepted incoming transfers, by whether the engine receives them yet
+#[derive(Default)]
+pub struct ReceiveQueue {
+    inner: Mutex<QueueState>,
+}
+
+/// An accepted transfer and the entries it writes to
+#[derive(Debug, Clone)]
+struct Claim {
+    id: String,
+    /// Staging directory of its own
+    dir: PathBuf,
+    /// Top-level names of its files, lowercased
+    names: HashSet<String>,
+}
+
+impl Claim {
+    fn clashes(&self, other: &Claim) -> bool {
+        self.dir.parent() != other.dir.parent() || !self.names.is_disjoint(&other.names)
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// Transfers the engine is receiving
+    receiving: Vec<Claim>,
+    /// Transfers waiting for a clash to end, in the order they were accepted
+    waiting: VecDeque<Claim>,
+}
+
+impl ReceiveQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let a transfer with the files `names` be received (into the shared
+    /// directory next to `dir`) unless it clashes with a transfer being
+    /// received or waiting, otherwise queue it
+    ///
+    /// Returns whether the transfer can be received now.
+    pub fn claim<'a>(
+        &self,
+        id: &str,
+        dir: PathBuf,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.receiving.iter().any(|claim| claim.id == id) {
+            return true;
+        }
+        if inner.waiting.iter().any(|claim| claim.id == id) {
+            return false;
+        }
+
+        let claim = Claim {
+            id: id.to_string(),
+            dir,
+            names: top_level_names(names),
+        };
+        let clash = inner
+            .receiving
+            .iter()
+            .chain(inner.waiting.iter())
+            .any(|other| other.clashes(&claim));
+        if clash {
+            inner.waiting.push_back(claim);
+            false
+        } else {
+            inner.receiving.push(claim);
+            true
+        }
+    }
+
+    /// Shared directory the engine receives into while transfers are being
+    /// received
+    pub fn receiving_dir(&self) -> Option<PathBuf> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .receiving
+            .first()
+            .map(|claim| receiving_dir(&claim.dir))
+    }
+
+    /// Whether the engine receives a transfer
+    pub fn is_receiving(&self, id: &str) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.receiving.iter().any(|claim| claim.id == id)
+    }
+
+    /// Whether a transfer is waiting for a clash to end
+    pub fn is_waiting(&self, id: &str) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.waiting.iter().any(|claim| claim.id == id)
+    }
+
+    /// Directories to keep in staging: those of the transfers being received
+    /// and waiting, and the shared directory while transfers are received
+    pub fn dirs(&self) -> Vec<PathBuf> {
+        let receiving = self.receiving_dir();
+        let inner = self.inner.lock().unwrap();
+        inner
+            .receiving
+            .iter()
+            .chain(inner.waiting.iter())
+            .map(|claim| claim.dir.clone())
+            .chain(receiving)
+            .collect()
+    }
+
+    /// Stop tracking a transfer that ended
+    ///
+    /// Returns the waiting transfers that no longer clash with any other, in
+    /// the order they were accepted; they now count as being received, and
+    /// the caller has the engine accept them.
+    pub fn finish(&self, id: &str) -> Vec<(String, PathBuf)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.receiving.retain(|claim| claim.id != id);
+        inner.waiting.retain(|claim| claim.id != id);
+
+        let mut ready = Vec::new();
+        let mut waiting = VecDeque::new();
+        while let Some(claim) = inner.waiting.pop_front() {
+            let clash = inner
+                .receiving
+                .iter()
+                .chain(waiting.iter())
+                .any(|other| other.clashes(&claim));
+            if clash {
+                waiting.push_back(claim);
+            } else {
+                ready.push((claim.id.clone(), claim.dir.clone()));
+                inner.receiving.push(claim);
+            }
+        }
+        inner.waiting = waiting;
+        ready
+    }
+}
+
+/// First component of a received file's name, as the engine resolves it
+fn top_level(name: &str) -> Option<&str> {
+    match Path::new(name).components().next()? {
+        Component::Normal(part) => part.to_str(),
+        _ => None,
+    }
+}
+
+/// First components of file names, lowercased
+fn top_level_names<'a>(names: impl IntoIterator<Item = &'a str>) -> HashSet<String> {
+    names
+        .into_iter()
+        .filter_map(top_level)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_receives_disjoint_transfers_concurrently() {
+        let queue = ReceiveQueue::new();
+        assert!(queue.claim("a", PathBuf::from("/s/a"), ["photos/1.jpg", "notes.txt"]));
+        assert!(queue.claim("b", PathBuf::from("/s/b"), ["report.pdf"]));
+        assert!(queue.is_receiving("a") && queue.is_receiving("b"));
+        assert_eq!(queue.receiving_dir(), Some(PathBuf::from("/s/.receiving")));
+    }
+
+    #[test]
+    fn test_queue_second_sender_waits_while_one_is_queued() {
+        let queue = ReceiveQueue::new();
+        assert!(queue.claim("a", PathBuf::from("/s/a"), ["photos/1.jpg"]));
+        // Clashes with "a" (ignoring case), so waits
+        assert!(!queue.claim("b", PathBuf::from("/s/b"), ["Photos/2.jpg", "x.txt"]));
+        // Clashes only with the waiting "b", and must not overtake it
+        assert!(!queue.claim("c", PathBuf::from("/s/c"), ["x.txt"]));
+        // A second sender with other names is received right away
+        assert!(queue.claim("d", PathBuf::from("/s/d"), ["y.txt"]));
+        assert!(queue.is_waiting("b") && queue.is_waiting("c"));
+
+        assert_eq!(queue.finish("a"), vec![("b".to_string(), "/s/b".into())]);
+        assert!(queue.is_receiving("b") && queue.is_waiting("c"));
+        assert_eq!(queue.finish("b"), vec![("c".to_string(), "/s/c".into())]);
+        assert!(queue.finish("c").is_empty());
+    }
+
+    #[test]
+    fn test_queue_waits_for_a_new_download_dir() {
+        let queue = ReceiveQueue::new();
+        assert!(queue.claim("a", PathBuf::from("/old/a"), ["a.txt"]));
+        assert!(!queue.claim("b", PathBuf::from("/new/b"), ["b.txt"]));
+
+        assert_eq!(queue.finish("a"), vec![("b".to_string(), "/new/b".into())]);
+        assert_eq!(
+            queue.receiving_dir(),
+            Some(PathBuf::from("/new/.receiving"))
+        );
+    }
+
+    #[test]
+    fn test_queue_finish_of_waiting_transfer_keeps_receiving() {
+        let queue = ReceiveQueue::new();
+        queue.claim("a", PathBuf::from("/s/a"), ["a.txt"]);
+        queue.claim("b", PathBuf::from("/s/b"), ["a.txt"]);
+
+        assert!(queue.finish("b").is_empty());
+        assert!(!queue.is_waiting("b"));
+        assert!(queue.is_receiving("a"));
+        assert!(queue.finish("a").is_empty());
+        assert_eq!(queue.receiving_dir(), None);
+    }
+
+    #[test]
+    fn test_collect_moves_top_level_entries() {
+        let download = std::env::temp_dir().join(format!("gosh-staging-{}", uuid::Uuid::new_v4()));
+        let receiving = prepare_receiving(&download);
+        fs::create_dir_all(receiving.join("photos")).unwrap();
+        fs::write(receiving.join("photos/1.jpg"), b"1").unwrap();
+        fs::write(receiving.join("other.txt"), b"2").unwrap();
+
+        let dir = transfer_dir(&download, "a");
+        collect(&dir, ["photos/1.jpg"]);
+        assert!(dir.join("photos/1.jpg").exists());
+        assert!(receiving.join("other.txt").exists());
+
+        fs::remove_dir_all(download).unwrap();
+    }
+
+    #[test]
+    fn test_remove_stale_keeps_needed_entries() {
+        let root = std::env::temp_dir().join(format!("gosh-staging-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("done")).unwrap();
+        fs::create_dir_all(root.join("conflict")).unwrap();
+        fs::write(root.join("done/a.txt"), b"a").unwrap();
+        fs::write(root.join("conflict/b.txt"), b"b").unwrap();
+        fs::write(root.join("old.txt"), b"c").unwrap();
+
+        remove_stale(&root, &[root.join("conflict/b.txt")]);
+        assert!(!root.join("done").exists());
+        assert!(!root.join("old.txt").exists());
+        assert!(root.join("conflict/b.txt").exists());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
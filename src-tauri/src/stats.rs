@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Transfer statistics
+//
+// Totals per peer, direction, day and week, computed from the transfer
+// history on request. Days and weeks follow local time. Throughput and
+// duration only consider completed transfers, timed from when they were
+// accepted so the wait for approval is left out; the success rate is the
+// completed share of finished (completed, failed, rejected or cancelled)
+// transfers.
+
+use crate::types::{
+    DirectionStats, PeerStats, PeriodStats, StatsSummary, TransferDirection, TransferRecord,
+    TransferStats, TransferStatus,
+};
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc};
+use std::collections::{BTreeMap, HashMap};
+
+/// Compute statistics for the records started within `[from, to)`
+pub fn compute(
+    records: &[TransferRecord],
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> TransferStats {
+    let records: Vec<&TransferRecord> = records
+        .iter()
+        .filter(|r| from.is_none_or(|from| r.started_at >= from))
+        .filter(|r| to.is_none_or(|to| r.started_at < to))
+        .collect();
+
+    let mut total = Accumulator::default();
+    let mut sent = Accumulator::default();
+    let mut received = Accumulator::default();
+    let mut peers: HashMap<&str, (Option<&str>, DateTime<Utc>, Accumulator)> = HashMap::new();
+    let mut days: BTreeMap<NaiveDate, Accumulator> = BTreeMap::new();
+    let mut weeks: BTreeMap<NaiveDate, Accumulator> = BTreeMap::new();
+
+    for record in records {
+        total.add(record);
+        match record.direction {
+            TransferDirection::Sent => sent.add(record),
+            TransferDirection::Received => received.add(record),
+        }
+
+        let peer = peers.entry(&record.peer_address).or_insert((
+            None,
+            record.started_at,
+            Accumulator::default(),
+        ));
+        if record.peer_name.is_some() && record.started_at >= peer.1 {
+            peer.0 = record.peer_name.as_deref();
+            peer.1 = record.started_at;
+        }
+        peer.2.add(record);
+
+        let day = record.started_at.with_timezone(&Local).date_naive();
+        days.entry(day).or_default().add(record);
+        weeks.entry(week_start(day)).or_default().add(record);
+    }
+
+    let mut by_peer: Vec<PeerStats> = peers
+        .into_iter()
+        .map(|(address, (name, _, acc))| PeerStats {
+            peer_address: address.to_string(),
+            peer_name: name.map(|n| n.to_string()),
+            summary: acc.finish(),
+        })
+        .collect();
+    by_peer.sort_by(|a, b| {
+        b.summary
+            .bytes
+            .cmp(&a.summary.bytes)
+            .then_with(|| a.peer_address.cmp(&b.peer_address))
+    });
+
+    TransferStats {
+        from,
+        to,
+        total: total.finish(),
+        by_direction: vec![
+            DirectionStats {
+                direction: TransferDirection::Sent,
+                summary: sent.finish(),
+            },
+            DirectionStats {
+                direction: TransferDirection::Received,
+                summary: received.finish(),
+            },
+        ],
+        by_peer,
+        by_day: days
+            .into_iter()
+            .map(|(day, acc)| PeriodStats {
+                period: day.format("%Y-%m-%d").to_string(),
+                start: day,
+                summary: acc.finish(),
+            })
+            .collect(),
+        by_week: weeks
+            .into_iter()
+            .map(|(start, acc)| {
+                let week = start.iso_week();
+                PeriodStats {
+                    period: format!("{}-W{:02}", week.year(), week.week()),
+                    start,
+                    summary: acc.finish(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Monday of the ISO week containing `day`
+fn week_start(day: NaiveDate) -> NaiveDate {
+    day - chrono::Duration::days(day.weekday().num_days_from_monday() as i64)
+}
+
+/// Running totals for one group of records
+#[derive(Default)]
+struct Accumulator {
+    summary: StatsSummary,
+    /// Completed transfers with a known, non-zero duration
+    timed: u32,
+    timed_bytes: u64,
+    timed_millis: i64,
+}
+
+impl Accumulator {
+    fn add(&mut self, record: &TransferRecord) {
+        let summary = &mut self.summary;
+        summary.transfers += 1;
+        match record.status {
+            TransferStatus::Completed => summary.completed += 1,
+            TransferStatus::Failed => summary.failed += 1,
+            TransferStatus::Rejected => summary.rejected += 1,
+            TransferStatus::Cancelled => summary.cancelled += 1,
+            TransferStatus::Pending | TransferStatus::InProgress => return,
+        }
+        if record.status != TransferStatus::Completed {
+            return;
+        }
+
+        summary.bytes += record.bytes_transferred;
+        summary.files += record.files.len() as u32;
+
+        let millis = match record.duration() {
+            Some(duration) => duration.num_milliseconds(),
+            None => return,
+        };
+        if millis <= 0 {
+            return;
+        }
+        self.timed += 1;
+        self.timed_bytes += record.bytes_transferred;
+        self.timed_millis += millis;
+
+        let throughput = record.bytes_transferred * 1000 / millis as u64;
+        summary.peak_throughput_bps = summary.peak_throughput_bps.max(Some(throughput));
+    }
+
+    fn finish(self) -> StatsSummary {
+        let mut summary = self.summary;
+        let finished = summary.completed + summary.failed + summary.rejected + summary.cancelled;
+        if finished > 0 {
+            summary.success_rate = Some(summary.completed as f64 / finished as f64);
+        }
+        if self.timed > 0 {
+            summary.average_throughput_bps =
+                Some(self.timed_bytes * 1000 / self.timed_millis as u64);
+            summary.mean_duration_secs =
+                Some(self.timed_millis as f64 / 1000.0 / self.timed as f64);
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn record(
+        peer: &str,
+        direction: TransferDirection,
+        status: TransferStatus,
+        started_at: DateTime<Utc>,
+        bytes: u64,
+        secs: i64,
+    ) -> TransferRecord {
+        let mut record = TransferRecord::outgoing(peer, Vec::new());
+        record.direction = direction;
+        record.status = status;
+        record.started_at = started_at;
+        record.completed_at = Some(started_at + Duration::seconds(secs));
+        record.total_size = bytes;
+        record.bytes_transferred = bytes;
+        record
+    }
+
+    #[test]
+    fn test_compute_stats() {
+        let monday = Utc.with_ymd_and_hms(2026, 1, 12, 12, 0, 0).unwrap();
+        let records = vec![
+            record(
+                "10.0.0.2",
+                TransferDirection::Sent,
+                TransferStatus::Completed,
+                monday,
+                4000,
+                2,
+            ),
+            record(
+                "10.0.0.2",
+                TransferDirection::Sent,
+                TransferStatus::Completed,
+                monday,
+                1000,
+                2,
+            ),
+            record(
+                "10.0.0.3",
+                TransferDirection::Received,
+                TransferStatus::Failed,
+                monday,
+                0,
+                1,
+            ),
+            record(
+                "10.0.0.3",
+                TransferDirection::Received,
+                TransferStatus::Completed,
+                monday + Duration::days(7),
+                900,
+                3,
+            ),
+        ];
+
+        let stats = compute(&records, None, None);
+        assert_eq!(stats.total.transfers, 4);
+        assert_eq!(stats.total.bytes, 5900);
+        assert_eq!(stats.total.success_rate, Some(0.75));
+        assert_eq!(stats.total.peak_throughput_bps, Some(2000));
+        assert_eq!(stats.total.average_throughput_bps, Some(5900 * 1000 / 7000));
+
+        assert_eq!(stats.by_peer[0].peer_address, "10.0.0.2");
+        assert_eq!(stats.by_peer[0].summary.mean_duration_secs, Some(2.0));
+        assert_eq!(stats.by_direction[1].summary.failed, 1);
+        assert_eq!(stats.by_week.len(), 2);
+
+        let ranged = compute(&records, Some(monday + Duration::days(1)), None);
+        assert_eq!(ranged.total.transfers, 1);
+        assert_eq!(ranged.by_peer.len(), 1);
+        assert_eq!(ranged.by_day.len(), 1);
+    }
+
+    #[test]
+    fn test_duration_excludes_approval_wait() {
+        let monday = Utc.with_ymd_and_hms(2026, 1, 12, 12, 0, 0).unwrap();
+        let mut waited = record(
+            "10.0.0.2",
+            TransferDirection::Received,
+            TransferStatus::Completed,
+            monday,
+            3000,
+            30,
+        );
+        waited.accepted_at = Some(monday + Duration::seconds(27));
+
+        let stats = compute(&[waited], None, None);
+        assert_eq!(stats.total.mean_duration_secs, Some(3.0));
+        assert_eq!(stats.total.peak_throughput_bps, Some(1000));
+    }
+}
@@ -0,0 +1,178 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Text messages
+//
+// A URL, a command line or a token can be sent as a text message instead of
+// a file. The engine only transfers files, so the sender writes the text to
+// a temporary file named `text-<preview>.gosh-text`, where the preview is the
+// hex-encoded start of the text: the receiver sees the file name with the
+// transfer request and can show the start of the message before it is
+// accepted. Once received, the text is kept in the history record and the
+// file is removed from staging; nothing is written to the download
+// directory.
+
+use crate::filter;
+use crate::types::{AppError, TransferFile, TransferRecord};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Suffix of the file carrying a text message
+pub const SUFFIX: &str = ".gosh-text";
+
+const PREFIX: &str = "text-";
+
+/// Largest text message, in bytes
+pub const MAX_BYTES: usize = 1024 * 1024;
+
+/// Bytes of the text carried in the file name
+const PREVIEW_BYTES: usize = 80;
+
+/// Name of the file carrying `text`
+pub fn file_name(text: &str) -> String {
+    let mut end = text.len().min(PREVIEW_BYTES);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}{}", PREFIX, hex::encode(&text[..end]), SUFFIX)
+}
+
+/// The preview of a transfer carrying a text message, None for other
+/// transfers
+///
+/// A preview shorter than the text ends in an ellipsis.
+pub fn preview(files: &[TransferFile]) -> Option<String> {
+    let file = match files {
+        [file] => file,
+        _ => return None,
+    };
+    let encoded = file.name.strip_prefix(PREFIX)?.strip_suffix(SUFFIX)?;
+    let bytes = hex::decode(encoded).ok()?;
+    let mut preview = String::from_utf8_lossy(&bytes).into_owned();
+    if (bytes.len() as u64) < file.size {
+        preview.push('…');
+    }
+    Some(preview)
+}
+
+/// A text message written out for sending
+///
+/// The temporary directory is removed when dropped.
+pub struct TextFile {
+    dir: PathBuf,
+    /// File to send
+    pub path: PathBuf,
+}
+
+impl Drop for TextFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.dir) {
+            tracing::warn!("Failed to remove {:?}: {}", self.dir, e);
+        }
+    }
+}
+
+/// Check that `text` can be sent
+pub fn check(text: &str) -> Result<(), AppError> {
+    if text.is_empty() {
+        return Err(AppError::InvalidConfig("The text is empty".to_string()));
+    }
+    if text.len() > MAX_BYTES {
+        return Err(AppError::InvalidConfig(format!(
+            "The text is longer than {} bytes",
+            MAX_BYTES
+        )));
+    }
+    Ok(())
+}
+
+/// Write `text` to a file for sending
+pub fn write(text: &str) -> Result<TextFile, AppError> {
+    check(text)?;
+    let dir = std::env::temp_dir().join(format!("gosh-transfer-text-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir)
+        .map_err(|e| AppError::FileIo(format!("Failed to create {:?}: {}", dir, e)))?;
+    let file = TextFile {
+        path: dir.join(file_name(text)),
+        dir,
+    };
+    fs::write(&file.path, text)
+        .map_err(|e| AppError::FileIo(format!("Failed to write {:?}: {}", file.path, e)))?;
+    Ok(file)
+}
+
+/// Read a received text message and remove its file from staging
+pub fn take(staging: &Path, record: &TransferRecord) -> Result<String, AppError> {
+    let path = record
+        .files
+        .first()
+        .and_then(|file| filter::received_path(staging, &file.name))
+        .ok_or_else(|| AppError::FileIo("The text message has no file".to_string()))?;
+    let size = fs::metadata(&path)
+        .map_err(|e| AppError::FileIo(format!("Failed to read {:?}: {}", path, e)))?
+        .len();
+    if size > MAX_BYTES as u64 {
+        let _ = fs::remove_file(&path);
+        return Err(AppError::FileIo(format!(
+            "The text is longer than {} bytes",
+            MAX_BYTES
+        )));
+    }
+
+    let bytes = fs::read(&path)
+        .map_err(|e| AppError::FileIo(format!("Failed to read {:?}: {}", path, e)))?;
+    let _ = fs::remove_file(&path);
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{PendingTransfer, TransferKind};
+
+    #[test]
+    fn test_preview() {
+        let text = "ssh deploy@10.0.0.7 -p 2222 # ünïcode ".repeat(5);
+        let file = TransferFile::new("f1", file_name(&text), text.len() as u64);
+        assert!(file.name.len() < 255);
+        let shown = preview(std::slice::from_ref(&file)).unwrap();
+        let start = shown.strip_suffix('…').unwrap();
+        assert!(text.starts_with(start) && start.len() <= PREVIEW_BYTES);
+
+        let short = TransferFile::new("f1", file_name("hi"), 2);
+        assert_eq!(preview(&[short]).as_deref(), Some("hi"));
+        let other = TransferFile::new("f1", "notes.txt", 2);
+        assert_eq!(preview(&[other]), None);
+    }
+
+    #[test]
+    fn test_write_and_take() {
+        let text = "ssh deploy@10.0.0.7 -p 2222 # ünïcode ".repeat(5);
+        let file = write(&text).unwrap();
+        let name = file.path.file_name().unwrap().to_string_lossy().to_string();
+
+        let transfer_file = TransferFile::new("f1", &name, text.len() as u64);
+        let shown = preview(std::slice::from_ref(&transfer_file)).unwrap();
+
+        // Receive the file
+        let staging = std::env::temp_dir().join(format!("gosh-text-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&staging).unwrap();
+        fs::copy(&file.path, staging.join(&name)).unwrap();
+        let record = TransferRecord::incoming(&PendingTransfer {
+            id: "t1".to_string(),
+            source_ip: "10.0.0.5".to_string(),
+            sender_name: None,
+            files: vec![transfer_file],
+            total_size: text.len() as u64,
+            received_at: chrono::Utc::now(),
+            kind: TransferKind::Text,
+            text_preview: Some(shown),
+        });
+        assert_eq!(take(&staging, &record).unwrap(), text);
+        assert!(!staging.join(&name).exists());
+
+        assert!(write("").is_err());
+        let temp = file.dir.clone();
+        drop(file);
+        assert!(!temp.exists());
+        fs::remove_dir_all(staging).unwrap();
+    }
+}
@@ -0,0 +1,861 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Bandwidth limits
+//
+// Upload and download limits are set globally or per peer (a peer's limits
+// replace the global ones), optionally only during a daily schedule. The
+// engine reads and writes its sockets itself and has no rate limit of its
+// own, so limited transfers go through local relays that pace the bytes
+// they forward:
+//
+// - Sends to a peer with an upload limit: the engine connects to a relay,
+//   which forwards the connection to the peer.
+// - While any download limit is set, the engine's server moves to a port of
+//   its own and a relay listens on the app's port in its place, forwarding
+//   each peer connection to the engine and pacing what the peer sends. The
+//   engine then sees every request coming from localhost, so the relay
+//   notes which peer offered each transfer (by the `id` in the body of its
+//   `POST /transfer`) and the app puts the peer's address back.
+//
+// Relays look their limits up before they forward anything and again every
+// second, so changed settings and schedule boundaries apply to transfers
+// already running.
+
+use crate::commands::AppState;
+use crate::events::AppEvent;
+use crate::notifications;
+use crate::types::{
+    AppError, AppSettings, BandwidthSettings, LimitSchedule, PendingTransfer, RateLimit,
+};
+use chrono::{Datelike, Local, NaiveDateTime};
+use gosh_lan_transfer::GoshTransferEngine;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How often a relay looks up its limit
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Largest chunk a relay reads and writes at once
+const MAX_CHUNK: usize = 64 * 1024;
+
+/// Largest request head the download relay looks into
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Largest transfer offer the download relay reads the ID of
+const MAX_OFFER: u64 = 4 * 1024 * 1024;
+
+/// Number of transfer offers whose peer the download relay remembers
+const MAX_SOURCES: usize = 256;
+
+/// Limits that apply to transfers with `peer`
+fn peer_limits<'a>(settings: &'a BandwidthSettings, peer: &str) -> &'a RateLimit {
+    settings
+        .peers
+        .iter()
+        .find(|p| p.address.eq_ignore_ascii_case(peer))
+        .map(|p| &p.limit)
+        .unwrap_or(&settings.global)
+}
+
+/// Whether the schedule lets limits apply at `now`
+fn scheduled(schedule: &LimitSchedule, now: NaiveDateTime) -> bool {
+    if !schedule.enabled {
+        return true;
+    }
+    (schedule.days.is_empty() || schedule.days.contains(&now.weekday()))
+        && notifications::in_daily_period(&schedule.start, &schedule.end, now.time())
+}
+
+/// Upload limit in bytes per second for sends to `peer` at `now` (local
+/// time)
+///
+/// A limit of zero counts as no limit.
+pub fn upload_limit(settings: &BandwidthSettings, peer: &str, now: NaiveDateTime) -> Option<u64> {
+    if !scheduled(&settings.schedule, now) {
+        return None;
+    }
+    peer_limits(settings, peer)
+        .upload_bps
+        .filter(|&bps| bps > 0)
+}
+
+/// Download limit in bytes per second for what `peer` sends at `now`
+/// (local time)
+///
+/// A limit of zero counts as no limit.
+pub fn download_limit(settings: &BandwidthSettings, peer: &str, now: NaiveDateTime) -> Option<u64> {
+    if !scheduled(&settings.schedule, now) {
+        return None;
+    }
+    peer_limits(settings, peer)
+        .download_bps
+        .filter(|&bps| bps > 0)
+}
+
+/// Limit for transfers with `peer` right now, zero if there is none
+async fn current_limit(
+    app_handle: &AppHandle,
+    peer: &str,
+    limit: fn(&BandwidthSettings, &str, NaiveDateTime) -> Option<u64>,
+) -> u64 {
+    let state = app_handle.state::<AppState>();
+    let settings = state.settings.read().await;
+    limit(&settings.bandwidth, peer, Local::now().naive_local()).unwrap_or(0)
+}
+
+/// Whether sends to `peer` have an upload limit at any time of day
+pub fn limits_uploads(settings: &BandwidthSettings, peer: &str) -> bool {
+    peer_limits(settings, peer)
+        .upload_bps
+        .is_some_and(|bps| bps > 0)
+}
+
+/// Whether any peer has a download limit at any time of day
+pub fn limits_downloads(settings: &BandwidthSettings) -> bool {
+    std::iter::once(&settings.global)
+        .chain(settings.peers.iter().map(|p| &p.limit))
+        .any(|limit| limit.download_bps.is_some_and(|bps| bps > 0))
+}
+
+/// Token bucket shared by the connections of a relay
+#[derive(Debug)]
+struct Pacer {
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Pacer {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new((0.0, Instant::now())),
+        }
+    }
+
+    /// Take `bytes` from the bucket, returning how long to wait before
+    /// writing them
+    ///
+    /// The bucket holds at most a quarter second's worth of bytes, so an idle
+    /// connection cannot burst far past the limit.
+    fn take(&self, bytes: usize, limit: Option<u64>) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (available, last) = &mut *state;
+        let now = Instant::now();
+        let rate = match limit {
+            Some(rate) => rate as f64,
+            None => {
+                *available = 0.0;
+                *last = now;
+                return Duration::ZERO;
+            }
+        };
+
+        let refill = now.duration_since(*last).as_secs_f64() * rate;
+        *available = (*available + refill).min(rate / 4.0) - bytes as f64;
+        *last = now;
+        if *available >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*available / rate)
+        }
+    }
+}
+
+/// Copy `reader` to `writer`, pacing writes to the current limit
+///
+/// `observe` sees each chunk before it is written.
+async fn copy_paced<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    limit: &AtomicU64,
+    pacer: &Pacer,
+    mut observe: impl FnMut(&[u8]),
+) where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0u8; MAX_CHUNK];
+    loop {
+        let current = Some(limit.load(Ordering::Relaxed)).filter(|&bps| bps > 0);
+        // Small chunks at low rates keep the pace even
+        let chunk = current.map_or(MAX_CHUNK, |bps| (bps as usize / 8).clamp(1024, MAX_CHUNK));
+        let read = match reader.read(&mut buf[..chunk]).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => read,
+        };
+        observe(&buf[..read]);
+        tokio::time::sleep(pacer.take(read, current)).await;
+        if writer.write_all(&buf[..read]).await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Forward one connection from the engine to the peer
+async fn forward(
+    inbound: TcpStream,
+    peer: (String, u16),
+    limit: Arc<AtomicU64>,
+    pacer: Arc<Pacer>,
+) {
+    let outbound = match TcpStream::connect((peer.0.as_str(), peer.1)).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::warn!("Relay cannot connect to {}:{}: {}", peer.0, peer.1, e);
+            return;
+        }
+    };
+    let (mut engine_read, mut engine_write) = inbound.into_split();
+    let (mut peer_read, mut peer_write) = outbound.into_split();
+
+    let upload = copy_paced(&mut engine_read, &mut peer_write, &limit, &pacer, |_| {});
+    let download = async {
+        let _ = tokio::io::copy(&mut peer_read, &mut engine_write).await;
+        let _ = engine_write.shutdown().await;
+    };
+    tokio::join!(upload, download);
+}
+
+/// A local relay pacing sends to one peer
+///
+/// The relay stops accepting connections when dropped.
+pub struct Relay {
+    /// Local port to send to instead of the peer's
+    pub port: u16,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Start a relay to `peer`:`port` on localhost
+pub async fn start_relay(
+    app_handle: AppHandle,
+    peer: String,
+    port: u16,
+) -> Result<Relay, AppError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .map_err(|e| AppError::Network(format!("Failed to start relay: {}", e)))?;
+    let local_port = listener
+        .local_addr()
+        .map_err(|e| AppError::Network(format!("Failed to start relay: {}", e)))?
+        .port();
+
+    // Known before the engine can connect, so no byte goes out unpaced
+    let limit = Arc::new(AtomicU64::new(
+        current_limit(&app_handle, &peer, upload_limit).await,
+    ));
+    let pacer = Arc::new(Pacer::new());
+    let task = tauri::async_runtime::spawn(async move {
+        let start = tokio::time::Instant::now() + REFRESH_INTERVAL;
+        let mut refresh = tokio::time::interval_at(start, REFRESH_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => {
+                    let current = current_limit(&app_handle, &peer, upload_limit).await;
+                    limit.store(current, Ordering::Relaxed);
+                }
+                accepted = listener.accept() => match accepted {
+                    Ok((inbound, _)) => {
+                        tauri::async_runtime::spawn(forward(
+                            inbound,
+                            (peer.clone(), port),
+                            limit.clone(),
+                            pacer.clone(),
+                        ));
+                    }
+                    Err(e) => tracing::warn!("Relay failed to accept a connection: {}", e),
+                },
+            }
+        }
+    });
+
+    Ok(Relay {
+        port: local_port,
+        task,
+    })
+}
+
+/// Peers that offered transfers through the download relay, by transfer ID
+#[derive(Debug, Default)]
+struct Sources {
+    inner: Mutex<(HashMap<String, IpAddr>, VecDeque<String>)>,
+}
+
+impl Sources {
+    /// Remember the peer of an offer, forgetting the oldest past
+    /// `MAX_SOURCES`
+    fn note(&self, transfer_id: &str, peer: IpAddr) {
+        let mut inner = self.inner.lock().unwrap();
+        let (peers, order) = &mut *inner;
+        if peers.insert(transfer_id.to_string(), peer).is_none() {
+            order.push_back(transfer_id.to_string());
+        }
+        while order.len() > MAX_SOURCES {
+            if let Some(oldest) = order.pop_front() {
+                peers.remove(&oldest);
+            }
+        }
+    }
+
+    fn get(&self, transfer_id: &str) -> Option<IpAddr> {
+        self.inner.lock().unwrap().0.get(transfer_id).copied()
+    }
+}
+
+/// Where an `OfferSniffer` is in the requests of a connection
+#[derive(Debug)]
+enum Sniff {
+    /// Reading a request head
+    Head(Vec<u8>),
+    /// Passing over a request body, kept if it is a transfer offer
+    Body {
+        remaining: u64,
+        offer: Option<Vec<u8>>,
+    },
+    /// Lost track of the requests (chunked body, oversized head)
+    Done,
+}
+
+/// Follows the HTTP requests a peer sends on one connection to find its
+/// transfer offers
+///
+/// It only looks at the bytes, which are forwarded unchanged. Requests are
+/// followed by their `Content-Length`; after a request with another body
+/// encoding the rest of the connection is not looked at.
+struct OfferSniffer {
+    peer: IpAddr,
+    sources: Arc<Sources>,
+    state: Sniff,
+}
+
+impl OfferSniffer {
+    fn new(peer: IpAddr, sources: Arc<Sources>) -> Self {
+        Self {
+            peer,
+            sources,
+            state: Sniff::Head(Vec::new()),
+        }
+    }
+
+    fn feed(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            match &mut self.state {
+                Sniff::Done => return,
+                Sniff::Head(head) => {
+                    let searched = head.len().saturating_sub(3);
+                    let before = head.len();
+                    head.extend_from_slice(bytes);
+                    let end = head[searched..]
+                        .windows(4)
+                        .position(|w| w == b"\r\n\r\n")
+                        .map(|i| searched + i + 4);
+                    match end {
+                        Some(end) => {
+                            head.truncate(end);
+                            bytes = &bytes[end - before..];
+                            self.state = parse_head(head);
+                        }
+                        None => {
+                            if head.len() > MAX_HEAD {
+                                self.state = Sniff::Done;
+                            }
+                            return;
+                        }
+                    }
+                }
+                Sniff::Body { remaining, offer } => {
+                    let taken = (*remaining).min(bytes.len() as u64) as usize;
+                    if let Some(offer) = offer.as_mut() {
+                        offer.extend_from_slice(&bytes[..taken]);
+                    }
+                    *remaining -= taken as u64;
+                    bytes = &bytes[taken..];
+                    if *remaining == 0 {
+                        if let Some(id) = offer.take().as_deref().and_then(offer_id) {
+                            self.sources.note(&id, self.peer);
+                        }
+                        self.state = Sniff::Head(Vec::new());
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// What follows a request head: the body, or the next request
+fn parse_head(head: &[u8]) -> Sniff {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(_) => return Sniff::Done,
+    };
+    let mut lines = text.split("\r\n");
+    let mut request = lines.next().unwrap_or_default().split(' ');
+    let (method, target) = (
+        request.next().unwrap_or_default(),
+        request.next().unwrap_or_default(),
+    );
+
+    let mut length = 0;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            match value.parse() {
+                Ok(parsed) => length = parsed,
+                Err(_) => return Sniff::Done,
+            }
+        } else if name.eq_ignore_ascii_case("transfer-encoding")
+            && !value.eq_ignore_ascii_case("identity")
+        {
+            return Sniff::Done;
+        }
+    }
+    if length == 0 {
+        return Sniff::Head(Vec::new());
+    }
+
+    let path = target.split('?').next().unwrap_or_default();
+    let is_offer = method == "POST" && path == "/transfer" && length <= MAX_OFFER;
+    Sniff::Body {
+        remaining: length,
+        offer: is_offer.then(Vec::new),
+    }
+}
+
+/// Transfer ID of a `POST /transfer` body
+fn offer_id(body: &[u8]) -> Option<String> {
+    let offer: serde_json::Value = serde_json::from_slice(body).ok()?;
+    offer.get("id")?.as_str().map(str::to_string)
+}
+
+/// Current download limit and token bucket of one peer's connections
+#[derive(Debug)]
+struct PeerPace {
+    limit: AtomicU64,
+    pacer: Pacer,
+}
+
+/// Forward one peer connection to the engine's server, pacing what the
+/// peer sends and noting its transfer offers
+async fn forward_incoming(
+    inbound: TcpStream,
+    peer: IpAddr,
+    engine_port: u16,
+    pace: Arc<PeerPace>,
+    sources: Arc<Sources>,
+) {
+    let outbound = match TcpStream::connect((Ipv4Addr::LOCALHOST, engine_port)).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::warn!("Relay cannot connect to the server: {}", e);
+            return;
+        }
+    };
+    let (mut peer_read, mut peer_write) = inbound.into_split();
+    let (mut engine_read, mut engine_write) = outbound.into_split();
+
+    let mut sniffer = OfferSniffer::new(peer, sources);
+    let download = copy_paced(
+        &mut peer_read,
+        &mut engine_write,
+        &pace.limit,
+        &pace.pacer,
+        |bytes| sniffer.feed(bytes),
+    );
+    let upload = async {
+        let _ = tokio::io::copy(&mut engine_read, &mut peer_write).await;
+        let _ = peer_write.shutdown().await;
+    };
+    tokio::join!(download, upload);
+}
+
+/// Accept peer connections on the app's port and forward them to the
+/// engine's server
+///
+/// Each peer's connections share a token bucket, paced to its download
+/// limit.
+async fn listen(
+    app_handle: AppHandle,
+    port: u16,
+    engine_port: u16,
+    sources: Arc<Sources>,
+) -> Result<tauri::async_runtime::JoinHandle<()>, AppError> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .await
+        .map_err(|e| AppError::Network(format!("Failed to listen on port {}: {}", port, e)))?;
+
+    Ok(tauri::async_runtime::spawn(async move {
+        let mut peers: HashMap<IpAddr, Arc<PeerPace>> = HashMap::new();
+        let start = tokio::time::Instant::now() + REFRESH_INTERVAL;
+        let mut refresh = tokio::time::interval_at(start, REFRESH_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = refresh.tick() => {
+                    // Peers without connections left are looked up again
+                    peers.retain(|_, pace| Arc::strong_count(pace) > 1);
+                    for (peer, pace) in &peers {
+                        let peer = peer.to_string();
+                        let current = current_limit(&app_handle, &peer, download_limit).await;
+                        pace.limit.store(current, Ordering::Relaxed);
+                    }
+                }
+                accepted = listener.accept() => match accepted {
+                    Ok((inbound, remote)) => {
+                        let peer = remote.ip();
+                        let pace = match peers.get(&peer) {
+                            Some(pace) => pace.clone(),
+                            None => {
+                                let peer_address = peer.to_string();
+                                // Known before forwarding, so no byte comes in unpaced
+                                let current =
+                                    current_limit(&app_handle, &peer_address, download_limit).await;
+                                let pace = Arc::new(PeerPace {
+                                    limit: AtomicU64::new(current),
+                                    pacer: Pacer::new(),
+                                });
+                                peers.insert(peer, pace.clone());
+                                pace
+                            }
+                        };
+                        tauri::async_runtime::spawn(forward_incoming(
+                            inbound,
+                            peer,
+                            engine_port,
+                            pace,
+                            sources.clone(),
+                        ));
+                    }
+                    Err(e) => tracing::warn!("Relay failed to accept a connection: {}", e),
+                },
+            }
+        }
+    }))
+}
+
+/// The download relay listening on the app's port
+struct Listening {
+    port: u16,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Relay in front of the engine's server while any download limit is set
+#[derive(Default)]
+pub struct IncomingRelay {
+    listening: Mutex<Option<Listening>>,
+    /// Port of its own the engine's server listens on, while it has one
+    engine_port: Mutex<Option<u16>>,
+    /// Every port the engine's server was moved to, for its events
+    moved_ports: Mutex<HashSet<u16>>,
+    sources: Arc<Sources>,
+}
+
+impl IncomingRelay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Port the engine's server listens on, given the app's `port`
+    pub fn engine_port(&self, port: u16) -> u16 {
+        self.engine_port.lock().unwrap().unwrap_or(port)
+    }
+
+    /// Put back the address of the peer that offered a transfer the engine
+    /// received through the relay
+    pub fn restore_source(&self, mut transfer: PendingTransfer) -> PendingTransfer {
+        let relayed = transfer
+            .source_ip
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback());
+        if let Some(peer) = self.sources.get(&transfer.id).filter(|_| relayed) {
+            transfer.source_ip = peer.to_string();
+        }
+        transfer
+    }
+
+    /// A server event with the app's `port` in place of the engine's own
+    ///
+    /// Returns None for a move of the engine's server that left the app's
+    /// port as it was.
+    pub fn public_event(&self, event: AppEvent, port: u16) -> Option<AppEvent> {
+        let moved = self.moved_ports.lock().unwrap();
+        let public = |p: u16| if moved.contains(&p) { port } else { p };
+        match event {
+            AppEvent::ServerStarted { port } => {
+                Some(AppEvent::ServerStarted { port: public(port) })
+            }
+            AppEvent::PortChanged { old_port, new_port } => {
+                let (old_port, new_port) = (public(old_port), public(new_port));
+                (old_port != new_port).then_some(AppEvent::PortChanged { old_port, new_port })
+            }
+            event => Some(event),
+        }
+    }
+
+    /// Start or stop the relay to match `settings`, moving the engine's
+    /// server between the app's port and one of its own
+    ///
+    /// `old_port` is the app's port before `settings`. If the relay cannot
+    /// listen, the engine's server takes the app's port back and downloads
+    /// are not limited; an error means the port is served by neither.
+    pub async fn apply(
+        &self,
+        app_handle: &AppHandle,
+        engine: &mut GoshTransferEngine,
+        settings: &AppSettings,
+        old_port: u16,
+    ) -> Result<(), AppError> {
+        let wanted = limits_downloads(&settings.bandwidth);
+        let relayed_port = {
+            let mut listening = self.listening.lock().unwrap();
+            // Left running, so its peers keep picking up changed limits
+            if wanted && listening.as_ref().is_some_and(|l| l.port == settings.port) {
+                return Ok(());
+            }
+            listening.take().map(|l| l.port)
+        };
+
+        self.rebind(app_handle, engine, settings, wanted, old_port)
+            .await?;
+        // The engine does not tell about the relay moving
+        if let Some(relayed_port) = relayed_port.filter(|&p| p != settings.port) {
+            app_handle
+                .state::<AppState>()
+                .publish(AppEvent::PortChanged {
+                    old_port: relayed_port,
+                    new_port: settings.port,
+                });
+        }
+        Ok(())
+    }
+
+    /// Put the engine's server, and the relay if `wanted`, on their ports
+    async fn rebind(
+        &self,
+        app_handle: &AppHandle,
+        engine: &mut GoshTransferEngine,
+        settings: &AppSettings,
+        wanted: bool,
+        old_port: u16,
+    ) -> Result<(), AppError> {
+        let moved = *self.engine_port.lock().unwrap();
+        if !wanted {
+            if moved.unwrap_or(old_port) != settings.port {
+                change_port(engine, settings.port).await?;
+            }
+            *self.engine_port.lock().unwrap() = None;
+            return Ok(());
+        }
+
+        let engine_port = match moved {
+            Some(engine_port) => engine_port,
+            None => {
+                let engine_port = free_port().await?;
+                self.moved_ports.lock().unwrap().insert(engine_port);
+                change_port(engine, engine_port).await?;
+                *self.engine_port.lock().unwrap() = Some(engine_port);
+                engine_port
+            }
+        };
+        let sources = self.sources.clone();
+        match listen(app_handle.clone(), settings.port, engine_port, sources).await {
+            Ok(task) => {
+                *self.listening.lock().unwrap() = Some(Listening {
+                    port: settings.port,
+                    task,
+                });
+            }
+            Err(e) => {
+                tracing::warn!("Receiving without a download limit: {}", e);
+                change_port(engine, settings.port).await?;
+                *self.engine_port.lock().unwrap() = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn change_port(engine: &mut GoshTransferEngine, port: u16) -> Result<(), AppError> {
+    engine
+        .change_port(port)
+        .await
+        .map_err(|e| AppError::Network(e.to_string()))
+}
+
+/// A port no one listens on right now
+async fn free_port() -> Result<u16, AppError> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .await
+        .map_err(|e| AppError::Network(format!("Failed to find a free port: {}", e)))?;
+    listener
+        .local_addr()
+        .map(|addr| addr.port())
+        .map_err(|e| AppError::Network(format!("Failed to find a free port: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::PeerRateLimit;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_limits() {
+        let mut settings = BandwidthSettings {
+            global: RateLimit {
+                upload_bps: Some(20_000_000),
+                download_bps: None,
+            },
+            peers: vec![PeerRateLimit {
+                address: "NAS.local".to_string(),
+                limit: RateLimit::default(),
+            }],
+            schedule: LimitSchedule::default(),
+        };
+        // A Wednesday
+        let at = |hour| {
+            NaiveDate::from_ymd_opt(2026, 3, 4)
+                .unwrap()
+                .and_hms_opt(hour, 0, 0)
+                .unwrap()
+        };
+
+        let upload =
+            |settings: &BandwidthSettings, peer, hour| upload_limit(settings, peer, at(hour));
+        assert_eq!(upload(&settings, "10.0.0.2", 20), Some(20_000_000));
+        assert_eq!(download_limit(&settings, "10.0.0.2", at(20)), None);
+        assert_eq!(upload(&settings, "nas.local", 10), None);
+        assert!(!limits_uploads(&settings, "nas.local"));
+        assert!(!limits_downloads(&settings));
+
+        settings.peers[0].limit.download_bps = Some(1_000_000);
+        assert_eq!(
+            download_limit(&settings, "nas.local", at(20)),
+            Some(1_000_000)
+        );
+        assert_eq!(download_limit(&settings, "10.0.0.2", at(20)), None);
+        assert!(limits_downloads(&settings));
+
+        settings.schedule.enabled = true;
+        assert_eq!(upload(&settings, "10.0.0.2", 10), Some(20_000_000));
+        assert_eq!(upload(&settings, "10.0.0.2", 20), None);
+        settings.schedule.days = vec![chrono::Weekday::Sat];
+        assert_eq!(upload(&settings, "10.0.0.2", 10), None);
+        assert!(limits_uploads(&settings, "10.0.0.2"));
+    }
+
+    #[test]
+    fn test_pacer() {
+        let pacer = Pacer::new();
+        assert_eq!(pacer.take(1000, None), Duration::ZERO);
+        let wait = pacer.take(1000, Some(1000));
+        assert!(wait > Duration::from_millis(900) && wait <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_sniffer_notes_transfer_offers() {
+        let sources = Arc::new(Sources::default());
+        let peer: IpAddr = "10.0.0.5".parse().unwrap();
+        let mut sniffer = OfferSniffer::new(peer, sources.clone());
+
+        let body = r#"{"id":"t1","senderName":"laptop","files":[],"totalSize":0}"#;
+        let stream = format!(
+            "GET /info HTTP/1.1\r\nHost: x\r\n\r\n\
+             POST /chunk?id=t0 HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello\
+             POST /transfer HTTP/1.1\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        // Fed in pieces, as the connection delivers them
+        for piece in stream.as_bytes().chunks(7) {
+            assert_eq!(sources.get("t1"), None);
+            sniffer.feed(piece);
+        }
+        assert_eq!(sources.get("t1"), Some(peer));
+        assert_eq!(sources.get("t0"), None);
+
+        let relay = IncomingRelay {
+            sources,
+            ..IncomingRelay::default()
+        };
+        let mut transfer = PendingTransfer {
+            id: "t1".into(),
+            source_ip: "127.0.0.1".into(),
+            sender_name: None,
+            files: vec![],
+            total_size: 0,
+            received_at: chrono::Utc::now(),
+            kind: Default::default(),
+            text_preview: None,
+        };
+        transfer = relay.restore_source(transfer);
+        assert_eq!(transfer.source_ip, "10.0.0.5");
+    }
+
+    #[test]
+    fn test_sniffer_stops_at_chunked_bodies() {
+        let sources = Arc::new(Sources::default());
+        let mut sniffer = OfferSniffer::new("10.0.0.5".parse().unwrap(), sources.clone());
+        sniffer.feed(b"POST /chunk HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n");
+        assert!(matches!(sniffer.state, Sniff::Done));
+    }
+
+    #[tokio::test]
+    async fn test_download_throughput() {
+        const RATE: u64 = 64 * 1024;
+        const SIZE: usize = 48 * 1024;
+
+        // Stands in for the engine's server, counting what arrives
+        let engine = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let engine_port = engine.local_addr().unwrap().port();
+        let received = tokio::spawn(async move {
+            let (mut stream, _) = engine.accept().await.unwrap();
+            let mut data = Vec::new();
+            stream.read_to_end(&mut data).await.unwrap();
+            data.len()
+        });
+
+        let relay = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let relay_port = relay.local_addr().unwrap().port();
+        let pace = Arc::new(PeerPace {
+            limit: AtomicU64::new(RATE),
+            pacer: Pacer::new(),
+        });
+        tokio::spawn(async move {
+            let (inbound, remote) = relay.accept().await.unwrap();
+            let sources = Arc::new(Sources::default());
+            forward_incoming(inbound, remote.ip(), engine_port, pace, sources).await;
+        });
+
+        let start = Instant::now();
+        let mut peer = TcpStream::connect((Ipv4Addr::LOCALHOST, relay_port))
+            .await
+            .unwrap();
+        peer.write_all(&vec![7u8; SIZE]).await.unwrap();
+        peer.shutdown().await.unwrap();
+        assert_eq!(received.await.unwrap(), SIZE);
+
+        let elapsed = start.elapsed().as_secs_f64();
+        let throughput = SIZE as f64 / elapsed;
+        assert!(
+            throughput <= RATE as f64 * 1.1,
+            "received {:.0} B/s with a limit of {} B/s",
+            throughput,
+            RATE
+        );
+        assert!(elapsed < 5.0);
+    }
+}
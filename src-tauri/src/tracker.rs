@@ -0,0 +1,348 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Active transfer tracking
+//
+// An in-memory snapshot of every transfer that has not finished yet, updated
+// from each published event. A reloaded webview (or any late consumer) reads
+// the snapshot instead of waiting for the next progress event.
+//
+// Sends are started by the send commands under the app's own history ID,
+// while the engine reports them under its own ID. The engine lock is held for
+// the duration of a send, so at most one send waits for its engine ID; the
+// first event for an unknown ID is attributed to it.
+//
+// The outcomes of recently finished transfers are kept too, so a consumer
+// that missed events can catch up on them.
+
+use crate::events::AppEvent;
+use crate::types::{ActiveTransfer, TransferDirection, TransferFile, TransferStatus};
+use chrono::Utc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, RwLock};
+
+/// Number of finished transfers whose outcome is kept
+const FINISHED_CAPACITY: usize = 256;
+
+/// Snapshot of the transfers in flight
+#[derive(Default)]
+pub struct TransferTracker {
+    transfers: RwLock<HashMap<String, ActiveTransfer>>,
+    /// Completion or failure events of recently finished transfers
+    finished: Mutex<VecDeque<AppEvent>>,
+}
+
+impl TransferTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// List active transfers, oldest first
+    pub fn list(&self) -> Vec<ActiveTransfer> {
+        let mut transfers: Vec<ActiveTransfer> =
+            self.transfers.read().unwrap().values().cloned().collect();
+        transfers.sort_by_key(|t| t.started_at);
+        transfers
+    }
+
+    /// Get an active transfer by ID
+    pub fn get(&self, id: &str) -> Option<ActiveTransfer> {
+        self.transfers.read().unwrap().get(id).cloned()
+    }
+
+    /// Update the snapshot from a published event
+    pub fn apply(&self, event: &AppEvent) {
+        let mut transfers = self.transfers.write().unwrap();
+        let now = Utc::now();
+
+        match event {
+            AppEvent::TransferRequest {
+                transfer,
+                auto_accepted,
+                ..
+            } => {
+                // Requests are tracked before screening and again once
+                // screened; keep any progress seen in between
+                let tracked =
+                    transfers
+                        .entry(transfer.id.clone())
+                        .or_insert_with(|| ActiveTransfer {
+                            id: transfer.id.clone(),
+                            record_id: None,
+                            direction: TransferDirection::Received,
+                            status: TransferStatus::Pending,
+                            peer_address: Some(transfer.source_ip.clone()),
+                            peer_name: transfer.sender_name.clone(),
+                            files: transfer.files.clone(),
+                            total_bytes: transfer.total_size,
+                            bytes_transferred: 0,
+                            speed_bps: 0,
+                            current_file: None,
+                            retries: 0,
+                            last_error: None,
+                            started_at: transfer.received_at,
+                            accepted_at: None,
+                            updated_at: now,
+                        });
+                if *auto_accepted {
+                    tracked.status = TransferStatus::InProgress;
+                }
+            }
+            AppEvent::TransferProgress { progress } => {
+                let transfer = entry(&mut transfers, &progress.transfer_id);
+                transfer.status = TransferStatus::InProgress;
+                // Sends learn that the peer accepted from the first progress
+                transfer.accepted_at.get_or_insert(now);
+                transfer.bytes_transferred = progress.bytes_transferred;
+                transfer.total_bytes = progress.total_bytes;
+                transfer.speed_bps = progress.speed_bps;
+                transfer.current_file = progress.current_file.clone();
+                transfer.updated_at = now;
+            }
+            AppEvent::TransferRetry {
+                transfer_id,
+                attempt,
+                error,
+                ..
+            } => {
+                let transfer = entry(&mut transfers, transfer_id);
+                transfer.retries = *attempt;
+                transfer.last_error = Some(error.clone());
+                transfer.speed_bps = 0;
+                transfer.updated_at = now;
+            }
+            AppEvent::TransferRejected { transfer_id, .. } => {
+                transfers.remove(transfer_id);
+            }
+            AppEvent::TransferComplete { transfer_id }
+            | AppEvent::TransferFailed { transfer_id, .. } => {
+                transfers.remove(transfer_id);
+                let mut finished = self.finished.lock().unwrap();
+                finished.push_back(event.clone());
+                while finished.len() > FINISHED_CAPACITY {
+                    finished.pop_front();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Last completion or failure event of a recently finished transfer
+    pub fn outcome(&self, id: &str) -> Option<AppEvent> {
+        self.finished
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|event| event.transfer_id() == Some(id))
+            .cloned()
+    }
+
+    /// Mark an incoming transfer as accepted
+    pub fn accept(&self, id: &str) {
+        if let Some(transfer) = self.transfers.write().unwrap().get_mut(id) {
+            transfer.status = TransferStatus::InProgress;
+            transfer.updated_at = Utc::now();
+        }
+    }
+
+    /// Put an incoming transfer back to awaiting a decision, after the
+    /// engine failed to accept it
+    pub fn unaccept(&self, id: &str) {
+        if let Some(transfer) = self.transfers.write().unwrap().get_mut(id) {
+            transfer.status = TransferStatus::Pending;
+            transfer.accepted_at = None;
+            transfer.updated_at = Utc::now();
+        }
+    }
+
+    /// Forget a transfer (rejected or cancelled by the user)
+    pub fn remove(&self, id: &str) {
+        self.transfers.write().unwrap().remove(id);
+    }
+
+    /// Forget incoming requests still awaiting a decision that are not in
+    /// `pending_ids`, e.g. after their outcome was missed
+    pub fn retain_pending(&self, pending_ids: &[String]) {
+        self.transfers.write().unwrap().retain(|id, t| {
+            t.direction != TransferDirection::Received
+                || t.status != TransferStatus::Pending
+                || pending_ids.contains(id)
+        });
+    }
+
+    /// Track a send started by a send command under its history ID
+    pub fn begin_send(&self, record_id: &str, peer_address: &str, files: Vec<TransferFile>) {
+        let now = Utc::now();
+        self.transfers.write().unwrap().insert(
+            record_id.to_string(),
+            ActiveTransfer {
+                id: record_id.to_string(),
+                record_id: Some(record_id.to_string()),
+                direction: TransferDirection::Sent,
+                status: TransferStatus::Pending,
+                peer_address: Some(peer_address.to_string()),
+                peer_name: None,
+                total_bytes: files.iter().map(|f| f.size).sum(),
+                files,
+                bytes_transferred: 0,
+                speed_bps: 0,
+                current_file: None,
+                retries: 0,
+                last_error: None,
+                started_at: now,
+                accepted_at: None,
+                updated_at: now,
+            },
+        );
+    }
+
+    /// Stop tracking a send once its command has returned, returning its
+    /// last snapshot
+    pub fn end_send(&self, record_id: &str) -> Option<ActiveTransfer> {
+        let mut transfers = self.transfers.write().unwrap();
+        let id = transfers
+            .iter()
+            .find(|(_, t)| t.record_id.as_deref() == Some(record_id))
+            .map(|(id, _)| id.clone())?;
+        transfers.remove(&id)
+    }
+}
+
+/// Find the transfer an engine event refers to
+///
+/// Unknown IDs belong to the send waiting for its engine ID, or else to a
+/// send started elsewhere.
+fn entry<'a>(
+    transfers: &'a mut HashMap<String, ActiveTransfer>,
+    id: &str,
+) -> &'a mut ActiveTransfer {
+    if !transfers.contains_key(id) {
+        let waiting = transfers
+            .iter()
+            .find(|(key, t)| t.record_id.as_deref() == Some(key.as_str()))
+            .map(|(key, _)| key.clone());
+
+        let transfer = match waiting.and_then(|key| transfers.remove(&key)) {
+            Some(mut transfer) => {
+                transfer.id = id.to_string();
+                transfer
+            }
+            None => {
+                let now = Utc::now();
+                ActiveTransfer {
+                    id: id.to_string(),
+                    record_id: None,
+                    direction: TransferDirection::Sent,
+                    status: TransferStatus::InProgress,
+                    peer_address: None,
+                    peer_name: None,
+                    files: Vec::new(),
+                    total_bytes: 0,
+                    bytes_transferred: 0,
+                    speed_bps: 0,
+                    current_file: None,
+                    retries: 0,
+                    last_error: None,
+                    started_at: now,
+                    accepted_at: None,
+                    updated_at: now,
+                }
+            }
+        };
+        transfers.insert(id.to_string(), transfer);
+    }
+
+    transfers.get_mut(id).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::TransferProgress;
+    use crate::types::{PendingTransfer, TransferKind};
+
+    fn progress(id: &str, bytes: u64) -> AppEvent {
+        AppEvent::TransferProgress {
+            progress: TransferProgress {
+                transfer_id: id.to_string(),
+                bytes_transferred: bytes,
+                total_bytes: 100,
+                current_file: Some("a.txt".to_string()),
+                speed_bps: 10,
+            },
+        }
+    }
+
+    #[test]
+    fn test_tracks_received_transfer() {
+        let tracker = TransferTracker::new();
+        tracker.apply(&AppEvent::TransferRequest {
+            transfer: PendingTransfer {
+                id: "r1".to_string(),
+                source_ip: "10.0.0.5".to_string(),
+                sender_name: Some("Laptop".to_string()),
+                files: vec![],
+                total_size: 100,
+                received_at: Utc::now(),
+                kind: TransferKind::Files,
+                text_preview: None,
+            },
+            held_reason: None,
+            auto_accepted: false,
+        });
+        assert_eq!(tracker.get("r1").unwrap().status, TransferStatus::Pending);
+
+        tracker.accept("r1");
+        tracker.apply(&progress("r1", 40));
+        tracker.apply(&AppEvent::TransferRetry {
+            transfer_id: "r1".to_string(),
+            attempt: 1,
+            max_attempts: 3,
+            error: "timeout".to_string(),
+        });
+        let transfer = tracker.get("r1").unwrap();
+        assert_eq!(transfer.direction, TransferDirection::Received);
+        assert_eq!(transfer.bytes_transferred, 40);
+        assert_eq!(transfer.retries, 1);
+        assert_eq!(transfer.peer_name.as_deref(), Some("Laptop"));
+
+        tracker.apply(&AppEvent::TransferComplete {
+            transfer_id: "r1".to_string(),
+        });
+        assert!(tracker.list().is_empty());
+    }
+
+    #[test]
+    fn test_keeps_outcome_of_finished_transfer() {
+        let tracker = TransferTracker::new();
+        tracker.apply(&progress("r1", 40));
+        tracker.apply(&AppEvent::TransferFailed {
+            transfer_id: "r1".to_string(),
+            error: "reset".to_string(),
+        });
+
+        assert!(tracker.get("r1").is_none());
+        assert!(matches!(
+            tracker.outcome("r1"),
+            Some(AppEvent::TransferFailed { error, .. }) if error == "reset"
+        ));
+        assert!(tracker.outcome("r2").is_none());
+    }
+
+    #[test]
+    fn test_send_adopts_engine_id() {
+        let tracker = TransferTracker::new();
+        tracker.begin_send("h1", "10.0.0.9", vec![]);
+        tracker.apply(&progress("e1", 25));
+
+        let transfers = tracker.list();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].id, "e1");
+        assert_eq!(transfers[0].record_id.as_deref(), Some("h1"));
+        assert_eq!(transfers[0].peer_address.as_deref(), Some("10.0.0.9"));
+        assert_eq!(transfers[0].direction, TransferDirection::Sent);
+        assert!(transfers[0].accepted_at.is_some());
+
+        tracker.end_send("h1");
+        assert!(tracker.list().is_empty());
+    }
+}
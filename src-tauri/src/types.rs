@@ -4,7 +4,7 @@
 // These types are used for Tauri IPC serialization.
 // The gosh-lan-transfer engine provides the core transfer logic.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use uuid::Uuid;
@@ -23,6 +23,9 @@ pub struct Favorite {
     pub last_resolved_ip: Option<String>,
     /// When this favorite was last used
     pub last_used: Option<DateTime<Utc>>,
+    /// Compress sends to this peer
+    #[serde(default)]
+    pub compress: bool,
 }
 
 impl Favorite {
@@ -33,6 +36,7 @@ impl Favorite {
             address,
             last_resolved_ip: None,
             last_used: None,
+            compress: false,
         }
     }
 }
@@ -58,7 +62,7 @@ pub enum TransferStatus {
 }
 
 /// A single file in a transfer
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct TransferFile {
     /// File name (not full path for security)
@@ -69,6 +73,67 @@ pub struct TransferFile {
     pub mime_type: Option<String>,
     /// Unique identifier for this file in the transfer
     pub id: String,
+    /// Local path of the file: the source file for sent transfers; for
+    /// received ones, the staged file until it is moved, then the final path
+    /// (after conflict handling)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Outcome of this file (None until the transfer reaches it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<FileStatus>,
+    /// Bytes of this file transferred
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bytes_transferred: Option<u64>,
+    /// Error that stopped the transfer while on this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// SHA-256 digest (hex) of the local copy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Modification time of the sender's copy of a received file, if the
+    /// sender told it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DateTime<Utc>>,
+    /// Bytes of the file an interrupted transfer delivered, of a file this
+    /// transfer resumes; only the rest is transferred
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+}
+
+impl TransferFile {
+    /// A file with nothing known about it but its name and size
+    pub fn new(id: impl Into<String>, name: impl Into<String>, size: u64) -> Self {
+        Self {
+            name: name.into(),
+            size,
+            mime_type: None,
+            id: id.into(),
+            path: None,
+            status: None,
+            bytes_transferred: None,
+            error: None,
+            sha256: None,
+            modified: None,
+            offset: None,
+        }
+    }
+
+    /// Bytes of the file the transfer itself carries: all of them, or those
+    /// past the offset of a resumed file
+    pub fn transfer_size(&self) -> u64 {
+        self.size.saturating_sub(self.offset.unwrap_or(0))
+    }
+}
+
+/// Outcome of a single file in a transfer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum FileStatus {
+    InProgress,
+    Completed,
+    Failed,
+    /// Not transferred: the transfer ended before reaching it
+    Skipped,
 }
 
 /// A completed or failed transfer record
@@ -83,6 +148,9 @@ pub struct TransferRecord {
     pub status: TransferStatus,
     /// Peer address (IP or hostname)
     pub peer_address: String,
+    /// Device name reported by the peer (if known)
+    #[serde(default)]
+    pub peer_name: Option<String>,
     /// Files transferred
     pub files: Vec<TransferFile>,
     /// Total size transferred
@@ -91,15 +159,387 @@ pub struct TransferRecord {
     pub bytes_transferred: u64,
     /// When the transfer started
     pub started_at: DateTime<Utc>,
+    /// When the receiver accepted the transfer and its bytes started to flow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accepted_at: Option<DateTime<Utc>>,
     /// When the transfer completed (or failed)
     pub completed_at: Option<DateTime<Utc>>,
     /// Error message if failed
     pub error: Option<String>,
+    /// Received files moved to quarantine by the receive filter
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub quarantined: Vec<QuarantinedFile>,
+    /// Received files redirected by routing rules
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<FileRoute>,
+    /// Received files whose name was already taken at the destination
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<FileConflict>,
+    /// Post-receive hooks run after the transfer completed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hook_runs: Vec<HookRun>,
+    /// Directory the engine wrote a received transfer into
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub staging_dir: Option<PathBuf>,
+    /// Peer port a sent transfer went to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Paths given to the send command (files, or the directory)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_paths: Vec<PathBuf>,
+    /// Sent as a directory, preserving its structure
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub directory: bool,
+    /// ID of the record this transfer re-sends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resent_from: Option<String>,
+    /// Files of the directory selected for sending (sent transfers)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub directory_filter: Option<DirectoryFilter>,
+    /// Files of an interrupted incoming transfer kept as `.part` files for
+    /// resuming
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partial_files: Vec<PartialFile>,
+    /// ID of the interrupted transfer this one resumes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resumed_from: Option<String>,
+    /// Sizes of the files sent compressed, if any were
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<CompressionInfo>,
+    /// Directory sent with modification times, permissions, empty
+    /// directories and symlinks
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub preserve_metadata: bool,
+    /// Files, or a text message
+    #[serde(default, skip_serializing_if = "TransferKind::is_files")]
+    pub kind: TransferKind,
+    /// The text of a text message (once received, for incoming ones)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
 }
 
-/// An incoming transfer pending user approval
+/// How much the compressed files of a transfer shrank
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CompressionInfo {
+    /// Size of the compressed files before compression
+    pub original_bytes: u64,
+    /// Size of the same files as sent
+    pub compressed_bytes: u64,
+    /// `compressed_bytes / original_bytes`
+    pub ratio: f64,
+}
+
+/// Which files of a directory are sent
+///
+/// Patterns follow `.gitignore` syntax.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DirectoryFilter {
+    /// Patterns files must match to be sent (empty = all files)
+    pub include: Vec<String>,
+    /// Patterns of files and directories left out
+    pub exclude: Vec<String>,
+    /// Also leave out what `.gitignore` and `.ignore` files in the tree
+    /// exclude
+    pub use_ignore_files: bool,
+}
+
+impl DirectoryFilter {
+    /// Whether the filter selects every file
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && !self.use_ignore_files
+    }
+}
+
+/// Files a directory send would transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryPreview {
+    pub files: Vec<PreviewFile>,
+    pub file_count: usize,
+    pub total_size: u64,
+    /// Files left out by the filter
+    pub excluded_count: usize,
+}
+
+/// A file a directory send would transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewFile {
+    /// Path relative to the directory
+    pub name: String,
+    pub size: u64,
+}
+
+/// What a send would transfer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendManifest {
+    pub files: Vec<ManifestFile>,
+    pub file_count: usize,
+    pub total_size: u64,
+    /// Paths that cannot be read; sending them would fail
+    pub unreadable: Vec<UnreadablePath>,
+    /// Symbolic links in the selection (their targets are sent)
+    pub symlinks: Vec<ManifestSymlink>,
+    /// Files a directory filter leaves out
+    pub excluded_count: usize,
+}
+
+/// A file in a send manifest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestFile {
+    /// Name as the peer will see it
+    pub name: String,
+    pub size: u64,
+    /// MIME type guessed from the extension
+    pub mime_type: Option<String>,
+}
+
+/// A path that cannot be read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnreadablePath {
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// A symbolic link found while listing a send
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManifestSymlink {
+    /// Name of the link as the peer will see it
+    pub name: String,
+    pub target: PathBuf,
+    /// Whether the link points to a directory
+    pub is_dir: bool,
+}
+
+/// Reachability of a peer, checked before a send
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerCheck {
+    pub reachable: bool,
+    /// The peer's `/info` response
+    pub info: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+/// Result of `prepare_send`: the manifest and the peer check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PreparedSend {
+    pub manifest: SendManifest,
+    pub peer: PeerCheck,
+}
+
+impl PreparedSend {
+    /// Whether the send can start: the peer answers and every file is readable
+    pub fn ready(&self) -> bool {
+        self.peer.reachable && self.manifest.unreadable.is_empty()
+    }
+}
+
+/// A partially received file kept after an interrupted transfer
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PartialFile {
+    /// File name as sent by the peer
+    pub name: String,
+    /// Full size of the file
+    pub size: u64,
+    /// Bytes received before the interruption
+    pub bytes_received: u64,
+    /// Path of the `.part` file
+    pub path: PathBuf,
+}
+
+/// Where a routing rule sends a received file
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct FileRoute {
+    /// File name as sent by the peer
+    pub name: String,
+    /// Name of the rule that matched
+    pub rule: String,
+    /// Directory the file is routed to
+    pub directory: PathBuf,
+    /// Name-conflict policy of the rule (None = global policy)
+    #[serde(default)]
+    pub conflict_policy: Option<ConflictPolicy>,
+    /// Final path once the file has been moved
+    pub path: Option<PathBuf>,
+}
+
+/// What to do when a received file's name is already taken
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ConflictPolicy {
+    /// Keep both, appending ` (1)`, ` (2)`, ... to the new file
+    #[default]
+    Rename,
+    /// Replace the existing file
+    Overwrite,
+    /// Drop the new file if it has the same size and hash, otherwise rename
+    SkipIfIdentical,
+    /// Keep whichever file was modified last, going by the sender's
+    /// modification time; keep both if the sender did not tell it
+    KeepNewer,
+    /// Leave the new file aside until the user resolves the conflict
+    Ask,
+}
+
+/// How a name conflict was resolved
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictResolution {
+    Renamed,
+    Overwritten,
+    Skipped,
+    Pending,
+}
+
+/// A received file whose name was already taken at the destination
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileConflict {
+    /// File name as sent by the peer
+    pub name: String,
+    /// Path the file was meant to be written to
+    pub target: PathBuf,
+    /// How the conflict was resolved
+    pub resolution: ConflictResolution,
+    /// Where the received data ended up (the staged file while pending,
+    /// the existing file when skipped)
+    pub path: PathBuf,
+}
+
+/// A received file moved to the quarantine directory
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantinedFile {
+    /// File name as sent by the peer
+    pub name: String,
+    /// Where the file was moved to
+    pub path: PathBuf,
+    /// Why the file was quarantined
+    pub reason: String,
+}
+
+impl TransferRecord {
+    /// Create the history record for a transfer sent to a peer
+    pub fn outgoing(peer_address: &str, files: Vec<TransferFile>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            direction: TransferDirection::Sent,
+            status: TransferStatus::InProgress,
+            peer_address: peer_address.to_string(),
+            peer_name: None,
+            total_size: files.iter().map(|f| f.size).sum(),
+            files,
+            bytes_transferred: 0,
+            started_at: Utc::now(),
+            accepted_at: None,
+            completed_at: None,
+            error: None,
+            quarantined: Vec::new(),
+            routes: Vec::new(),
+            conflicts: Vec::new(),
+            hook_runs: Vec::new(),
+            staging_dir: None,
+            port: None,
+            source_paths: Vec::new(),
+            directory: false,
+            resent_from: None,
+            directory_filter: None,
+            partial_files: Vec::new(),
+            resumed_from: None,
+            compression: None,
+            preserve_metadata: false,
+            kind: TransferKind::Files,
+            text: None,
+        }
+    }
+
+    /// Create a pending history record for an incoming transfer request
+    pub fn incoming(transfer: &PendingTransfer) -> Self {
+        Self {
+            id: transfer.id.clone(),
+            direction: TransferDirection::Received,
+            status: TransferStatus::Pending,
+            peer_address: transfer.source_ip.clone(),
+            peer_name: transfer.sender_name.clone(),
+            files: transfer.files.clone(),
+            total_size: transfer.total_size,
+            bytes_transferred: 0,
+            started_at: transfer.received_at,
+            accepted_at: None,
+            completed_at: None,
+            error: None,
+            quarantined: Vec::new(),
+            routes: Vec::new(),
+            conflicts: Vec::new(),
+            hook_runs: Vec::new(),
+            staging_dir: None,
+            port: None,
+            source_paths: Vec::new(),
+            directory: false,
+            resent_from: None,
+            directory_filter: None,
+            partial_files: Vec::new(),
+            resumed_from: None,
+            compression: None,
+            preserve_metadata: false,
+            kind: transfer.kind,
+            text: None,
+        }
+    }
+
+    /// Time from acceptance to completion, excluding the wait for the
+    /// receiver's approval
+    ///
+    /// Falls back to the start time for records accepted before it was kept.
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        let completed_at = self.completed_at?;
+        Some(completed_at - self.accepted_at.unwrap_or(self.started_at))
+    }
+}
+
+/// Live state of a transfer that has not finished yet
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveTransfer {
+    /// Transfer ID (engine ID once the engine has reported on it)
+    pub id: String,
+    /// History record ID, if different from `id`
+    pub record_id: Option<String>,
+    pub direction: TransferDirection,
+    /// Pending (awaiting approval) or in progress
+    pub status: TransferStatus,
+    /// Peer address (IP or hostname), if known
+    pub peer_address: Option<String>,
+    /// Device name reported by the peer (if known)
+    pub peer_name: Option<String>,
+    pub files: Vec<TransferFile>,
+    pub total_bytes: u64,
+    pub bytes_transferred: u64,
+    pub speed_bps: u64,
+    pub current_file: Option<String>,
+    /// Retry attempts made so far
+    pub retries: u32,
+    /// Error that caused the last retry
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    /// When the receiver accepted the transfer, if it has
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// An incoming transfer pending user approval
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct PendingTransfer {
     /// Transfer ID
     pub id: String,
@@ -113,6 +553,28 @@ pub struct PendingTransfer {
     pub total_size: u64,
     /// When the request was received
     pub received_at: DateTime<Utc>,
+    /// Files, or a text message
+    #[serde(default)]
+    pub kind: TransferKind,
+    /// Start of a text message, shown before it is accepted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_preview: Option<String>,
+}
+
+/// What a transfer carries
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferKind {
+    #[default]
+    Files,
+    /// A text message, kept in history instead of the download directory
+    Text,
+}
+
+impl TransferKind {
+    pub fn is_files(&self) -> bool {
+        *self == Self::Files
+    }
 }
 
 /// Network interface information
@@ -155,11 +617,43 @@ pub struct AppSettings {
     pub trusted_hosts: Vec<String>,
     /// Receive-only mode (disable sending)
     pub receive_only: bool,
-    /// Show system notifications
-    pub notifications_enabled: bool,
+    /// Which system notifications are shown, and quiet hours
+    #[serde(default)]
+    pub notifications: NotificationSettings,
     /// Theme preference: "dark", "light", or "system"
     #[serde(default = "default_theme")]
     pub theme: String,
+    /// Size, quota and free-space limits for incoming transfers
+    #[serde(default)]
+    pub receive_limits: ReceiveLimits,
+    /// File-type filter for incoming transfers
+    #[serde(default)]
+    pub receive_filter: ReceiveFilter,
+    /// Rules directing incoming files to other directories (first match wins)
+    #[serde(default)]
+    pub routing_rules: Vec<RoutingRule>,
+    /// What to do when a received file's name is already taken
+    #[serde(default)]
+    pub conflict_policy: ConflictPolicy,
+    /// Actions run after a transfer has been received, in order
+    #[serde(default)]
+    pub post_receive_hooks: Vec<PostReceiveHook>,
+    /// Outgoing webhooks fired on transfer events
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    /// How long transfer history is kept
+    #[serde(default)]
+    pub history_retention: HistoryRetention,
+    /// Upload and download rate limits
+    #[serde(default)]
+    pub bandwidth: BandwidthSettings,
+    /// Copy text messages from trusted hosts to the clipboard
+    #[serde(default)]
+    pub auto_copy_text: bool,
+    /// Restore the times, permissions, empty directories and links sent
+    /// with a directory
+    #[serde(default)]
+    pub apply_received_metadata: bool,
 }
 
 fn default_theme() -> String {
@@ -180,12 +674,631 @@ impl Default for AppSettings {
             download_dir,
             trusted_hosts: Vec::new(),
             receive_only: false,
-            notifications_enabled: true,
+            notifications: NotificationSettings::default(),
             theme: default_theme(),
+            receive_limits: ReceiveLimits::default(),
+            receive_filter: ReceiveFilter::default(),
+            routing_rules: Vec::new(),
+            conflict_policy: ConflictPolicy::default(),
+            post_receive_hooks: Vec::new(),
+            webhooks: Vec::new(),
+            history_retention: HistoryRetention::default(),
+            bandwidth: BandwidthSettings::default(),
+            auto_copy_text: false,
+            apply_received_metadata: false,
+        }
+    }
+}
+
+/// Limits on the size and age of transfer history
+///
+/// Transfers still pending or in progress are never removed.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryRetention {
+    /// Maximum number of records kept, oldest removed first (None = unlimited)
+    pub max_entries: Option<usize>,
+    /// Remove records older than this many days (None = keep forever)
+    pub max_age_days: Option<u32>,
+    /// Keep failed transfers for at least this many days, regardless of the
+    /// limits above
+    pub keep_failures_days: Option<u32>,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        Self {
+            max_entries: Some(10_000),
+            max_age_days: None,
+            keep_failures_days: None,
+        }
+    }
+}
+
+/// Per-event notification toggles
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationSettings {
+    /// Incoming transfer waiting for approval
+    pub incoming_request: bool,
+    /// Transfer sent or received successfully
+    pub transfer_complete: bool,
+    /// Transfer failed
+    pub transfer_failed: bool,
+    /// Incoming transfer rejected automatically (receive filter or limits)
+    pub transfer_rejected: bool,
+    /// Transfer is being retried after an error
+    pub transfer_retry: bool,
+    /// Server port changed
+    pub port_changed: bool,
+    /// Period during which no notifications are shown
+    pub quiet_hours: QuietHours,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            incoming_request: true,
+            transfer_complete: true,
+            transfer_failed: true,
+            transfer_rejected: true,
+            transfer_retry: false,
+            port_changed: true,
+            quiet_hours: QuietHours::default(),
         }
     }
 }
 
+impl NotificationSettings {
+    /// All notifications switched off (settings from before per-event toggles)
+    pub fn disabled() -> Self {
+        Self {
+            incoming_request: false,
+            transfer_complete: false,
+            transfer_failed: false,
+            transfer_rejected: false,
+            transfer_retry: false,
+            port_changed: false,
+            quiet_hours: QuietHours::default(),
+        }
+    }
+}
+
+/// Daily period without notifications, in local time
+///
+/// `start` and `end` are "HH:MM"; a period ending before it starts runs
+/// past midnight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct QuietHours {
+    pub enabled: bool,
+    pub start: String,
+    pub end: String,
+}
+
+impl Default for QuietHours {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            start: "22:00".to_string(),
+            end: "07:00".to_string(),
+        }
+    }
+}
+
+/// Upload and download rate limits, in bytes per second (None = unlimited)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RateLimit {
+    pub upload_bps: Option<u64>,
+    pub download_bps: Option<u64>,
+}
+
+/// Rate limits for one peer, replacing the global limits
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerRateLimit {
+    /// Peer address (IP or hostname; download limits only match an IP)
+    pub address: String,
+    #[serde(default)]
+    pub limit: RateLimit,
+}
+
+/// Bandwidth limits for transfers
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct BandwidthSettings {
+    /// Limits for all peers without limits of their own
+    pub global: RateLimit,
+    /// Per-peer limits
+    pub peers: Vec<PeerRateLimit>,
+    /// When the limits apply (always, if disabled)
+    pub schedule: LimitSchedule,
+}
+
+/// Daily period, in local time, during which bandwidth limits apply
+///
+/// `start` and `end` are "HH:MM"; a period ending before it starts runs
+/// past midnight. No days means every day.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LimitSchedule {
+    pub enabled: bool,
+    pub start: String,
+    pub end: String,
+    pub days: Vec<chrono::Weekday>,
+}
+
+impl Default for LimitSchedule {
+    fn default() -> Self {
+        use chrono::Weekday;
+
+        Self {
+            enabled: false,
+            start: "09:00".to_string(),
+            end: "17:00".to_string(),
+            days: vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ],
+        }
+    }
+}
+
+/// What to do with an incoming transfer that exceeds a receive limit
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverLimitAction {
+    /// Reject the transfer automatically
+    #[default]
+    Reject,
+    /// Keep the transfer pending for manual approval, even from trusted hosts
+    Hold,
+}
+
+/// Limits applied to incoming transfers before they are accepted
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReceiveLimits {
+    /// Maximum size of a single transfer in bytes (None = unlimited)
+    pub max_transfer_bytes: Option<u64>,
+    /// Maximum bytes received per local calendar day (None = unlimited)
+    pub daily_quota_bytes: Option<u64>,
+    /// Free space to keep in the download directory after receiving
+    pub min_free_space_bytes: u64,
+    /// Action taken when a limit would be exceeded
+    pub over_limit_action: OverLimitAction,
+}
+
+/// What the receive filter does with matching files
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// Filtering disabled
+    #[default]
+    Off,
+    /// Reject transfers containing a matching file
+    Block,
+    /// Move matching files to the quarantine directory after receiving
+    Quarantine,
+}
+
+/// File-type filter applied to incoming transfers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReceiveFilter {
+    /// Action taken for matching files
+    pub action: FilterAction,
+    /// Blocked file extensions, without the leading dot (case-insensitive)
+    pub extensions: Vec<String>,
+    /// Blocked MIME types; an entry ending in `/` matches the whole family
+    pub mime_types: Vec<String>,
+    /// Quarantine directory (None = "quarantine" in the app data directory)
+    pub quarantine_dir: Option<PathBuf>,
+}
+
+impl Default for ReceiveFilter {
+    fn default() -> Self {
+        #[rustfmt::skip]
+        let extensions = [
+            // Executables and installers
+            "exe", "msi", "com", "scr", "dll", "app", "pkg", "deb", "rpm", "apk", "appimage",
+            "jar",
+            // Scripts
+            "bat", "cmd", "ps1", "vbs", "wsf", "sh", "bash", "command",
+            // Disk images
+            "dmg", "iso", "img", "vhd", "vhdx",
+        ];
+        let mime_types = [
+            "application/x-msdownload",
+            "application/x-msdos-program",
+            "application/x-executable",
+            "application/x-sh",
+            "application/x-apple-diskimage",
+            "application/x-iso9660-image",
+            "application/vnd.microsoft.portable-executable",
+        ];
+
+        Self {
+            action: FilterAction::Off,
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            mime_types: mime_types.iter().map(|m| m.to_string()).collect(),
+            quarantine_dir: None,
+        }
+    }
+}
+
+/// Rule directing matching incoming files to another directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoutingRule {
+    /// User-friendly name, recorded in history
+    pub name: String,
+    /// Whether the rule is active
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Sender address to match (IP, or the address of a favorite)
+    #[serde(default)]
+    pub sender: Option<String>,
+    /// Sender device name to match (case-insensitive)
+    #[serde(default)]
+    pub device_name: Option<String>,
+    /// File name patterns to match, e.g. "*.pdf" (empty = all files)
+    #[serde(default)]
+    pub file_patterns: Vec<String>,
+    /// MIME types to match, e.g. "application/pdf", or "image/" for all
+    /// images (empty = all types)
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    /// Target directory, relative to the download directory or absolute.
+    /// Supports `{sender}`, `{device}`, `{yyyy}`, `{mm}`, `{dd}` and `{yyyy-mm-dd}`.
+    pub directory: String,
+    /// Name-conflict policy for files routed by this rule (None = global policy)
+    #[serde(default)]
+    pub conflict_policy: Option<ConflictPolicy>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Action performed by a post-receive hook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum HookAction {
+    /// Run a program with the received file paths appended to its arguments.
+    /// The sender and transfer are passed in `GOSH_*` environment variables.
+    #[serde(rename_all = "camelCase")]
+    RunCommand {
+        program: String,
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Move the received files to another directory
+    MoveFiles { directory: PathBuf },
+    /// Extract received .zip, .tar, .tar.gz and .tgz archives
+    /// (None = next to the archive)
+    ExtractArchives {
+        #[serde(default)]
+        directory: Option<PathBuf>,
+    },
+    /// Open the folder containing the received files
+    OpenFolder,
+}
+
+/// User-defined action run after a transfer has been received
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostReceiveHook {
+    /// User-friendly name, recorded in history
+    pub name: String,
+    /// Whether the hook is active
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// What the hook does
+    pub action: HookAction,
+    /// Time limit in seconds before the hook is abandoned
+    #[serde(default = "default_hook_timeout")]
+    pub timeout_secs: u64,
+    /// Also run for transfers from hosts that are not trusted
+    #[serde(default)]
+    pub allow_untrusted: bool,
+}
+
+fn default_hook_timeout() -> u64 {
+    60
+}
+
+/// Result of running a post-receive hook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HookRun {
+    /// Name of the hook
+    pub hook: String,
+    /// Whether the hook finished successfully
+    pub success: bool,
+    /// Exit code, for commands
+    pub exit_code: Option<i32>,
+    /// Captured stdout and stderr (truncated)
+    pub output: String,
+    /// Error message if the hook failed or timed out
+    pub error: Option<String>,
+    /// When the hook started
+    pub started_at: DateTime<Utc>,
+    /// How long the hook ran, in milliseconds
+    pub duration_ms: u64,
+}
+
+/// Transfer event that can trigger a webhook
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEvent {
+    TransferRequest,
+    TransferComplete,
+    TransferFailed,
+    TransferRetry,
+}
+
+impl WebhookEvent {
+    /// Map a frontend event name (e.g. "transfer-complete") to a webhook event
+    pub fn from_event_name(name: &str) -> Option<Self> {
+        match name {
+            "transfer-request" => Some(Self::TransferRequest),
+            "transfer-complete" => Some(Self::TransferComplete),
+            "transfer-failed" => Some(Self::TransferFailed),
+            "transfer-retry" => Some(Self::TransferRetry),
+            _ => None,
+        }
+    }
+
+    /// Frontend event name, also sent in the `X-Gosh-Event` header
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            Self::TransferRequest => "transfer-request",
+            Self::TransferComplete => "transfer-complete",
+            Self::TransferFailed => "transfer-failed",
+            Self::TransferRetry => "transfer-retry",
+        }
+    }
+}
+
+/// Outgoing webhook POSTed on selected transfer events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    /// User-friendly name, recorded in the delivery log
+    pub name: String,
+    /// Whether the webhook is active
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// URL the event payload is POSTed to
+    pub url: String,
+    /// Events that trigger the webhook
+    pub events: Vec<WebhookEvent>,
+    /// Shared secret used to sign the body with HMAC-SHA256
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// Delivery attempts before giving up
+    #[serde(default = "default_webhook_attempts")]
+    pub max_attempts: u32,
+}
+
+fn default_webhook_attempts() -> u32 {
+    5
+}
+
+/// Outcome of delivering an event to a webhook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    /// Unique identifier, also sent in the `X-Gosh-Delivery` header
+    pub id: String,
+    /// Name of the webhook
+    pub webhook: String,
+    /// URL the event was POSTed to
+    pub url: String,
+    /// Event that was delivered
+    pub event: WebhookEvent,
+    /// Transfer the event belongs to
+    pub transfer_id: Option<String>,
+    /// Whether the endpoint accepted the event
+    pub success: bool,
+    /// Number of attempts made
+    pub attempts: u32,
+    /// HTTP status of the last attempt
+    pub status_code: Option<u16>,
+    /// Error of the last failed attempt
+    pub error: Option<String>,
+    /// When the delivery finished
+    pub delivered_at: DateTime<Utc>,
+}
+
+/// Column to sort history by
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HistorySortField {
+    #[default]
+    StartedAt,
+    CompletedAt,
+    Direction,
+    Status,
+    Peer,
+    Size,
+    Files,
+    Duration,
+}
+
+/// Sort order
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filter, sort and page through transfer history
+///
+/// Every filter is optional; an empty query returns all records, newest first.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct HistoryQuery {
+    pub direction: Option<TransferDirection>,
+    /// Any of these statuses (empty: any status)
+    pub status: Vec<TransferStatus>,
+    /// Substring of the peer address or device name (case-insensitive)
+    pub peer: Option<String>,
+    /// Substring of any file name (case-insensitive)
+    pub file_name: Option<String>,
+    /// Started at or after
+    pub from: Option<DateTime<Utc>>,
+    /// Started before
+    pub to: Option<DateTime<Utc>>,
+    /// Minimum total size in bytes
+    pub min_size: Option<u64>,
+    /// Maximum total size in bytes
+    pub max_size: Option<u64>,
+    pub sort_by: HistorySortField,
+    pub order: SortOrder,
+    /// Number of matching records to skip
+    pub offset: usize,
+    /// Maximum number of records to return (all if not set)
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    /// Whether any filter is set (sorting and pagination aside)
+    pub fn has_filters(&self) -> bool {
+        self.direction.is_some()
+            || !self.status.is_empty()
+            || self.peer.is_some()
+            || self.file_name.is_some()
+            || self.from.is_some()
+            || self.to.is_some()
+            || self.min_size.is_some()
+            || self.max_size.is_some()
+    }
+}
+
+/// One page of a history query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryPage {
+    pub records: Vec<TransferRecord>,
+    /// Number of records matching the filters, across all pages
+    pub total: usize,
+    pub offset: usize,
+}
+
+/// What `forget_peer` removed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgottenPeer {
+    /// Number of history records deleted
+    pub history_deleted: usize,
+    /// Favorites deleted
+    pub favorites: Vec<Favorite>,
+    /// Trusted hosts removed
+    pub trusted_hosts: Vec<String>,
+}
+
+/// File format for history export and import
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    /// JSON Lines, one object per line
+    Jsonl,
+}
+
+impl ExportFormat {
+    /// Guess the format from a file extension (`.csv`, `.jsonl`, `.ndjson`)
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// Aggregated figures for a set of transfers
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsSummary {
+    /// Number of transfers, including unfinished ones
+    pub transfers: u32,
+    pub completed: u32,
+    pub failed: u32,
+    pub rejected: u32,
+    pub cancelled: u32,
+    /// Bytes moved by completed transfers
+    pub bytes: u64,
+    /// Files moved by completed transfers
+    pub files: u32,
+    /// Completed share of finished transfers (0.0 - 1.0)
+    pub success_rate: Option<f64>,
+    /// Bytes per second over all completed transfers
+    pub average_throughput_bps: Option<u64>,
+    /// Fastest single completed transfer, in bytes per second
+    pub peak_throughput_bps: Option<u64>,
+    /// Mean duration of completed transfers
+    pub mean_duration_secs: Option<f64>,
+}
+
+/// Statistics for one peer
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerStats {
+    pub peer_address: String,
+    /// Most recently reported device name
+    pub peer_name: Option<String>,
+    #[serde(flatten)]
+    pub summary: StatsSummary,
+}
+
+/// Statistics for one direction
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectionStats {
+    pub direction: TransferDirection,
+    #[serde(flatten)]
+    pub summary: StatsSummary,
+}
+
+/// Statistics for one day or ISO week (local time)
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PeriodStats {
+    /// `2026-01-17` for a day, `2026-W03` for a week
+    pub period: String,
+    /// First day of the period
+    pub start: NaiveDate,
+    #[serde(flatten)]
+    pub summary: StatsSummary,
+}
+
+/// Transfer statistics computed from history
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferStats {
+    /// Start of the date range (inclusive), if limited
+    pub from: Option<DateTime<Utc>>,
+    /// End of the date range (exclusive), if limited
+    pub to: Option<DateTime<Utc>>,
+    pub total: StatsSummary,
+    pub by_direction: Vec<DirectionStats>,
+    /// Sorted by bytes moved, most first
+    pub by_peer: Vec<PeerStats>,
+    /// Oldest first
+    pub by_day: Vec<PeriodStats>,
+    /// Oldest first
+    pub by_week: Vec<PeriodStats>,
+}
+
 /// Error types for the application
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
@@ -212,6 +1325,30 @@ pub enum AppError {
 
     #[error("Invalid configuration: {0}")]
     InvalidConfig(String),
+
+    #[error("Transfer of {size} bytes exceeds the per-transfer limit of {limit} bytes")]
+    TransferTooLarge { size: u64, limit: u64 },
+
+    #[error(
+        "Transfer of {size} bytes exceeds the daily quota ({used} of {limit} bytes used today)"
+    )]
+    DailyQuotaExceeded { size: u64, used: u64, limit: u64 },
+
+    #[error("Insufficient disk space: {required} bytes required, {available} bytes available")]
+    InsufficientSpace { required: u64, available: u64 },
+
+    #[error("Blocked file type: {0}")]
+    BlockedFileType(String),
+
+    #[error("Hook failed: {0}")]
+    Hook(String),
+
+    #[error("Checksum mismatch for {name}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
 }
 
 // Allow AppError to be returned from Tauri commands
@@ -0,0 +1,367 @@
+// SPDX-License-Identifier: AGPL-3.0
+// Gosh Transfer - Outgoing webhooks
+//
+// Selected transfer events are POSTed to user-configured URLs with the same
+// JSON payload that is emitted to the frontend. Failed deliveries are retried
+// with exponential backoff, and every delivery is kept in a log stored next to
+// the transfer history (maximum of 200 entries).
+
+use crate::commands::AppState;
+use crate::events::AppEvent;
+use crate::types::{AppError, Webhook, WebhookDelivery, WebhookEvent};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+const MAX_LOG_ENTRIES: usize = 200;
+
+/// Delay before the first retry; doubled on every further attempt
+const BASE_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Time limit for a single delivery attempt
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const EVENT_HEADER: &str = "X-Gosh-Event";
+pub const DELIVERY_HEADER: &str = "X-Gosh-Delivery";
+pub const SIGNATURE_HEADER: &str = "X-Gosh-Signature";
+
+/// HTTP client and delivery log, persisted to disk on changes
+pub struct WebhookStore {
+    client: reqwest::Client,
+    deliveries: RwLock<Vec<WebhookDelivery>>,
+    file_path: PathBuf,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WebhookLogFile {
+    deliveries: Vec<WebhookDelivery>,
+}
+
+impl WebhookStore {
+    /// Create a new webhook store, loading the delivery log from disk if available
+    pub fn new() -> Result<Self, AppError> {
+        let file_path = Self::get_log_path()?;
+
+        let deliveries = if file_path.exists() {
+            let content = fs::read_to_string(&file_path)
+                .map_err(|e| AppError::FileIo(format!("Failed to read webhook log: {}", e)))?;
+
+            let file: WebhookLogFile = serde_json::from_str(&content).unwrap_or_else(|e| {
+                tracing::warn!("Failed to parse webhook log, starting fresh: {}", e);
+                WebhookLogFile {
+                    deliveries: Vec::new(),
+                }
+            });
+
+            file.deliveries
+        } else {
+            Vec::new()
+        };
+
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| AppError::Network(format!("Failed to create HTTP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            deliveries: RwLock::new(deliveries),
+            file_path,
+        })
+    }
+
+    /// Get the path to the delivery log
+    fn get_log_path() -> Result<PathBuf, AppError> {
+        let config_dir = directories::ProjectDirs::from("com", "gosh", "transfer")
+            .ok_or_else(|| AppError::FileIo("Could not determine config directory".to_string()))?
+            .config_dir()
+            .to_path_buf();
+
+        fs::create_dir_all(&config_dir)
+            .map_err(|e| AppError::FileIo(format!("Failed to create config dir: {}", e)))?;
+
+        Ok(config_dir.join("webhook_deliveries.json"))
+    }
+
+    /// Persist the delivery log to disk
+    fn persist(&self) -> Result<(), AppError> {
+        let file = WebhookLogFile {
+            deliveries: self.deliveries.read().unwrap().clone(),
+        };
+
+        let content = serde_json::to_string_pretty(&file).map_err(|e| {
+            AppError::Serialization(format!("Failed to serialize webhook log: {}", e))
+        })?;
+
+        fs::write(&self.file_path, content)
+            .map_err(|e| AppError::FileIo(format!("Failed to write webhook log: {}", e)))
+    }
+
+    /// List logged deliveries, oldest first
+    pub fn list(&self) -> Vec<WebhookDelivery> {
+        self.deliveries.read().unwrap().clone()
+    }
+
+    /// Add a delivery to the log
+    pub fn record(&self, delivery: WebhookDelivery) -> Result<(), AppError> {
+        {
+            let mut deliveries = self.deliveries.write().unwrap();
+            deliveries.push(delivery);
+
+            while deliveries.len() > MAX_LOG_ENTRIES {
+                deliveries.remove(0);
+            }
+        }
+
+        self.persist()
+    }
+
+    /// Clear the delivery log
+    pub fn clear(&self) -> Result<(), AppError> {
+        self.deliveries.write().unwrap().clear();
+        self.persist()
+    }
+}
+
+/// Fire the webhooks subscribed to a published event
+///
+/// Events without a webhook kind are ignored. Deliveries run in the
+/// background so the bus never waits on the network.
+pub fn dispatch(app_handle: &AppHandle, event: &AppEvent) {
+    let kind = match WebhookEvent::from_event_name(event.name()) {
+        Some(kind) => kind,
+        None => return,
+    };
+    let payload = match serde_json::to_value(event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            tracing::warn!("Failed to serialize webhook payload: {}", e);
+            return;
+        }
+    };
+
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let state = app_handle.state::<AppState>();
+        let webhooks: Vec<Webhook> = state
+            .settings
+            .read()
+            .await
+            .webhooks
+            .iter()
+            .filter(|w| w.enabled && w.events.contains(&kind))
+            .cloned()
+            .collect();
+
+        for webhook in webhooks {
+            let client = state.webhooks.client.clone();
+            let payload = payload.clone();
+            let app_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                let delivery = deliver(&client, &webhook, kind, &payload, BASE_BACKOFF).await;
+                let state = app_handle.state::<AppState>();
+                if let Err(e) = state.webhooks.record(delivery) {
+                    tracing::warn!("Failed to record webhook delivery: {}", e);
+                }
+            });
+        }
+    });
+}
+
+/// POST an event payload to a webhook, retrying with exponential backoff
+///
+/// Network errors, 429 and 5xx responses are retried; other responses end
+/// the delivery.
+pub async fn deliver(
+    client: &reqwest::Client,
+    webhook: &Webhook,
+    event: WebhookEvent,
+    payload: &serde_json::Value,
+    base_backoff: Duration,
+) -> WebhookDelivery {
+    let id = uuid::Uuid::new_v4().to_string();
+    let body = payload.to_string();
+    let max_attempts = webhook.max_attempts.max(1);
+
+    let mut attempts = 0;
+    let mut status_code = None;
+    let mut error = None;
+    let mut success = false;
+
+    while attempts < max_attempts {
+        if attempts > 0 {
+            tokio::time::sleep(base_backoff * 2u32.saturating_pow(attempts - 1)).await;
+        }
+        attempts += 1;
+
+        let mut request = client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event.event_name())
+            .header(DELIVERY_HEADER, &id)
+            .body(body.clone());
+        if let Some(secret) = &webhook.secret {
+            request = request.header(SIGNATURE_HEADER, sign(secret, body.as_bytes()));
+        }
+
+        let retry = match request.send().await {
+            Ok(response) => {
+                let status = response.status();
+                status_code = Some(status.as_u16());
+                if status.is_success() {
+                    success = true;
+                    error = None;
+                    break;
+                }
+                error = Some(format!("Endpoint responded with {}", status));
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(e) => {
+                status_code = None;
+                error = Some(e.to_string());
+                true
+            }
+        };
+
+        tracing::warn!(
+            "Webhook '{}' attempt {}/{} failed: {:?}",
+            webhook.name,
+            attempts,
+            max_attempts,
+            error
+        );
+        if !retry {
+            break;
+        }
+    }
+
+    WebhookDelivery {
+        id,
+        webhook: webhook.name.clone(),
+        url: webhook.url.clone(),
+        event,
+        transfer_id: transfer_id(payload),
+        success,
+        attempts,
+        status_code,
+        error,
+        delivered_at: Utc::now(),
+    }
+}
+
+/// Sign a body with HMAC-SHA256, formatted as `sha256=<hex digest>`
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Transfer id of an event payload (`transferId`, or `transfer.id` for requests)
+fn transfer_id(payload: &serde_json::Value) -> Option<String> {
+    payload
+        .get("transferId")
+        .or_else(|| payload.get("transfer").and_then(|t| t.get("id")))
+        .and_then(|id| id.as_str())
+        .map(|id| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP stand-in answering with `statuses` in turn and returning
+    /// the raw requests it received
+    async fn serve(statuses: Vec<u16>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut raw = Vec::new();
+                let mut buf = [0u8; 4096];
+                loop {
+                    let read = socket.read(&mut buf).await.unwrap();
+                    raw.extend_from_slice(&buf[..read]);
+                    let text = String::from_utf8_lossy(&raw);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text[..end]
+                            .lines()
+                            .find_map(|l| {
+                                l.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if raw.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                    if read == 0 {
+                        break;
+                    }
+                }
+                let response = format!(
+                    "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                    status
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&raw).to_string());
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries_and_signs() {
+        let (url, server) = serve(vec![503, 200]).await;
+        let webhook = Webhook {
+            name: "chat".to_string(),
+            enabled: true,
+            url,
+            events: vec![WebhookEvent::TransferComplete],
+            secret: Some("s3cret".to_string()),
+            max_attempts: 3,
+        };
+        let payload = serde_json::json!({
+            "type": "transferComplete",
+            "transferId": "t1"
+        });
+
+        let client = reqwest::Client::new();
+        let delivery = deliver(
+            &client,
+            &webhook,
+            WebhookEvent::TransferComplete,
+            &payload,
+            Duration::from_millis(10),
+        )
+        .await;
+
+        assert!(delivery.success);
+        assert_eq!(delivery.attempts, 2);
+        assert_eq!(delivery.status_code, Some(200));
+        assert_eq!(delivery.transfer_id.as_deref(), Some("t1"));
+
+        let requests = server.await.unwrap();
+        let body = payload.to_string();
+        let signature = sign("s3cret", body.as_bytes());
+        for request in &requests {
+            let lower = request.to_lowercase();
+            assert!(lower.contains("x-gosh-event: transfer-complete"));
+            assert!(lower.contains(&format!("x-gosh-signature: {}", signature)));
+            assert!(request.ends_with(&body));
+        }
+    }
+}